@@ -0,0 +1,136 @@
+//! Bit level IO, the basis of the entropy coders and of the stream headers.
+
+pub use reader::BitReader;
+pub use writer::BitWriter;
+
+mod reader;
+mod writer;
+
+#[cfg(test)]
+mod test {
+    use std::io::ErrorKind;
+
+    use super::{BitReader, BitWriter};
+
+    #[test]
+    fn msb_first() {
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bit(true).unwrap();
+        writer.write_bits(0b011, 3).unwrap();
+        writer.write_bits(0xabc, 12).unwrap();
+        writer.write_bits(0b1, 2).unwrap();
+        assert_eq!(writer.bits_written(), 18);
+        assert_eq!(
+            writer.finish().unwrap(),
+            [0b1011_1010, 0b1011_1100, 0b0100_0000]
+        );
+
+        let mut reader = BitReader::new(&[0b1011_1010, 0b1011_1100, 0b0100_0000][..]);
+        assert!(reader.read_bit().unwrap());
+        assert_eq!(reader.read_bits(3).unwrap(), 0b011);
+        assert_eq!(reader.read_bits(12).unwrap(), 0xabc);
+        assert_eq!(reader.read_bits(2).unwrap(), 0b01);
+    }
+
+    #[test]
+    fn exp_golomb_codes() {
+        let mut writer = BitWriter::new(Vec::new());
+        for n in 0..4 {
+            writer.write_exp_golomb(n, 0).unwrap();
+        }
+        // 1 010 011 00100
+        assert_eq!(writer.finish().unwrap(), [0b1010_0110, 0b0100_0000]);
+
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_exp_golomb(3, 2).unwrap();
+        writer.write_signed_exp_golomb(-2, 0).unwrap();
+        // 1 11 00101
+        assert_eq!(writer.finish().unwrap(), [0b1110_0101]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let unsigned = [0, 1, 2, 7, 8, 1000, 1 << 40, u64::MAX - 1, u64::MAX];
+        let signed = [0, 1, -1, 17, -300, i64::MAX, i64::MIN + 1];
+
+        let mut writer = BitWriter::new(Vec::new());
+        for k in [0, 3, 63] {
+            for &n in &unsigned {
+                writer.write_exp_golomb(n, k).unwrap();
+            }
+            for &n in &signed {
+                writer.write_signed_exp_golomb(n, k).unwrap();
+            }
+        }
+        writer.write_unary(100).unwrap();
+        writer.align().unwrap();
+        assert!(writer.is_aligned());
+        writer.write_bits(u64::MAX, 64).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let mut reader = BitReader::new(bytes.as_slice());
+        for k in [0, 3, 63] {
+            for &n in &unsigned {
+                assert_eq!(reader.read_exp_golomb(k).unwrap(), n, "{k}");
+            }
+            for &n in &signed {
+                assert_eq!(reader.read_signed_exp_golomb(k).unwrap(), n, "{k}");
+            }
+        }
+        assert_eq!(reader.read_unary().unwrap(), 100);
+        reader.align();
+        assert_eq!(reader.read_bits(64).unwrap(), u64::MAX);
+        assert_eq!(reader.bits_read(), bytes.len() as u64 * 8);
+        assert_eq!(
+            reader.read_bit().unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn peek() {
+        let mut reader = BitReader::new(&[0b1011_0010, 0b1100_0000][..]);
+        assert_eq!(reader.peek_bits(12).unwrap(), (0b1011_0010_1100, 12));
+        assert_eq!(reader.read_bits(3).unwrap(), 0b101);
+        assert_eq!(reader.bits_read(), 3);
+        assert_eq!(reader.peek_bits(4).unwrap(), (0b1001, 4));
+        reader.align();
+        assert!(reader.is_aligned());
+        assert_eq!(reader.bits_read(), 8);
+        // Past the end of the source
+        assert_eq!(reader.peek_bits(10).unwrap(), (0b11_0000_0000, 8));
+        assert_eq!(reader.read_bits(2).unwrap(), 0b11);
+        assert_eq!(reader.peek_bits(0).unwrap(), (0, 0));
+        assert_eq!(
+            reader.read_bits(7).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn truncation() {
+        let mut reader = BitReader::new(&[0xff][..]);
+        assert_eq!(
+            reader.read_bits(9).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+
+        let mut reader = BitReader::new(&[0, 0][..]);
+        assert_eq!(
+            reader.read_unary().unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+
+        // Prefix of a code longer than 64 bits
+        let mut reader = BitReader::new(&[0; 9][..]);
+        assert_eq!(
+            reader.read_exp_golomb(0).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+        let mut reader = BitReader::new(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0x80, 0, 0][..]);
+        assert_eq!(
+            reader.read_exp_golomb(0).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+}
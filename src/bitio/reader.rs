@@ -0,0 +1,149 @@
+use std::io::{self, Read};
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated bitstream")
+}
+
+/// Reads bits MSB first from a byte source, as written by [`BitWriter`](super::BitWriter).
+///
+/// Reading past the end of the source fails with [`io::ErrorKind::UnexpectedEof`].
+#[derive(Debug)]
+pub struct BitReader<R> {
+    inner: R,
+    /// Bytes read from the source and not consumed yet, in the `available` low bits
+    bits: u64,
+    available: u32,
+    read: u64,
+}
+
+impl<R: Read> BitReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            bits: 0,
+            available: 0,
+            read: 0,
+        }
+    }
+
+    /// Number of bits consumed so far, skipped padding included.
+    pub fn bits_read(&self) -> u64 {
+        self.read
+    }
+
+    pub fn is_aligned(&self) -> bool {
+        self.available.is_multiple_of(8)
+    }
+
+    /// Returns the source, dropping the unread bits of the current byte and the bytes read by [`BitReader::peek_bits`].
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Buffers the next byte of the source, returning `false` at its end.
+    fn fill(&mut self) -> io::Result<bool> {
+        let mut byte = [0];
+        match self.inner.read_exact(&mut byte) {
+            Ok(()) => {
+                self.bits = (self.bits << 8) | byte[0] as u64;
+                self.available += 8;
+                Ok(true)
+            }
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn read_bit(&mut self) -> io::Result<bool> {
+        Ok(self.read_bits(1)? != 0)
+    }
+
+    /// Reads `count` bits, the first one being the most significant of the result.
+    pub fn read_bits(&mut self, count: u32) -> io::Result<u64> {
+        assert!(count <= 64, "Cannot read more than 64 bits at once");
+        let mut value = 0u64;
+        let mut count = count;
+        while count > 0 {
+            if self.available == 0 && !self.fill()? {
+                return Err(truncated());
+            }
+            let take = count.min(self.available);
+            let chunk = (self.bits >> (self.available - take)) & (u64::MAX >> (64 - take));
+            value = (value << take) | chunk;
+            self.available -= take;
+            self.read += take as u64;
+            count -= take;
+        }
+        Ok(value)
+    }
+
+    /// Returns the next `count` bits without consuming them, and how many of them are before the end of the source.
+    ///
+    /// The bits past the end of the source are zeros.
+    pub fn peek_bits(&mut self, count: u32) -> io::Result<(u64, u32)> {
+        assert!(count <= 56, "Cannot peek more than 56 bits at once");
+        while self.available < count && self.fill()? {}
+        if count == 0 {
+            return Ok((0, 0));
+        }
+        let bits = if self.available >= count {
+            self.bits >> (self.available - count)
+        } else {
+            self.bits << (count - self.available)
+        };
+        Ok((bits & (u64::MAX >> (64 - count)), count.min(self.available)))
+    }
+
+    /// Reads a number written by [`BitWriter::write_unary`](super::BitWriter::write_unary).
+    pub fn read_unary(&mut self) -> io::Result<u64> {
+        let mut n = 0;
+        while !self.read_bit()? {
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    /// Reads a number written by [`BitWriter::write_exp_golomb`](super::BitWriter::write_exp_golomb).
+    pub fn read_exp_golomb(&mut self, k: u32) -> io::Result<u64> {
+        assert!(k < 64, "Exp-Golomb order must be lower than 64");
+        let zeros = self.read_unary()?;
+        let bits = zeros + k as u64;
+        if bits > 64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Exp-Golomb code of {bits} bits does not fit in 64 bits"),
+            ));
+        }
+        let w = (1u128 << bits) | self.read_bits(bits as u32)? as u128;
+        u64::try_from(w - (1 << k)).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Exp-Golomb code does not fit in 64 bits",
+            )
+        })
+    }
+
+    /// Reads a number written by [`BitWriter::write_signed_exp_golomb`](super::BitWriter::write_signed_exp_golomb).
+    pub fn read_signed_exp_golomb(&mut self, k: u32) -> io::Result<i64> {
+        let mapped = self.read_exp_golomb(k)?;
+        // Codes up to 2^64 - 1 map to `-2^63 + 1..=2^63`, the last one being out of range
+        let half = (mapped / 2) as i64;
+        if mapped % 2 == 1 {
+            half.checked_add(1).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Signed Exp-Golomb code does not fit in 64 bits",
+                )
+            })
+        } else {
+            Ok(-half)
+        }
+    }
+
+    /// Skips the remaining bits of the current byte.
+    pub fn align(&mut self) {
+        let padding = self.available % 8;
+        self.read += padding as u64;
+        self.available -= padding;
+    }
+}
@@ -0,0 +1,106 @@
+use std::io::{self, Write};
+
+/// Packs bits MSB first into a byte sink.
+///
+/// Whole bytes are written as soon as they are complete,
+/// the last partial byte is padded with zeros by [`BitWriter::align`] or [`BitWriter::finish`].
+#[derive(Debug)]
+pub struct BitWriter<W> {
+    inner: W,
+    byte: u8,
+    filled: u32,
+    written: u64,
+}
+
+impl<W: Write> BitWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            byte: 0,
+            filled: 0,
+            written: 0,
+        }
+    }
+
+    /// Number of bits written so far, padding included.
+    pub fn bits_written(&self) -> u64 {
+        self.written
+    }
+
+    pub fn is_aligned(&self) -> bool {
+        self.filled == 0
+    }
+
+    pub fn write_bit(&mut self, bit: bool) -> io::Result<()> {
+        self.write_bits(bit as u64, 1)
+    }
+
+    /// Writes the `count` low bits of `value`, most significant first.
+    pub fn write_bits(&mut self, value: u64, count: u32) -> io::Result<()> {
+        assert!(count <= 64, "Cannot write more than 64 bits at once");
+        let mut count = count;
+        while count > 0 {
+            let take = count.min(8 - self.filled);
+            let chunk = (value >> (count - take)) as u8 & (0xff >> (8 - take));
+            self.byte |= chunk << (8 - self.filled - take);
+            self.filled += take;
+            self.written += take as u64;
+            count -= take;
+            if self.filled == 8 {
+                self.inner.write_all(&[self.byte])?;
+                self.byte = 0;
+                self.filled = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes `n` as `n` zeros followed by a one.
+    pub fn write_unary(&mut self, n: u64) -> io::Result<()> {
+        let mut zeros = n;
+        while zeros > 0 {
+            let count = zeros.min(64) as u32;
+            self.write_bits(0, count)?;
+            zeros -= count as u64;
+        }
+        self.write_bit(true)
+    }
+
+    /// Writes `n` with the Exp-Golomb code of order `k`.
+    ///
+    /// `n + 2^k` is written on its `m` significant bits, preceded by `m - k - 1` zeros.
+    pub fn write_exp_golomb(&mut self, n: u64, k: u32) -> io::Result<()> {
+        assert!(k < 64, "Exp-Golomb order must be lower than 64");
+        let w = n as u128 + (1 << k);
+        let m = u128::BITS - w.leading_zeros();
+        self.write_unary((m - k - 1) as u64)?;
+        // The leading one has been written by the unary prefix
+        self.write_bits((w - (1 << (m - 1))) as u64, m - 1)
+    }
+
+    /// Writes `n` with the Exp-Golomb code of order `k` of `2n - 1` if `n > 0`, `-2n` otherwise.
+    pub fn write_signed_exp_golomb(&mut self, n: i64, k: u32) -> io::Result<()> {
+        assert!(n != i64::MIN, "i64::MIN has no signed Exp-Golomb code");
+        let mapped = if n > 0 {
+            (n as u64) * 2 - 1
+        } else {
+            n.unsigned_abs() * 2
+        };
+        self.write_exp_golomb(mapped, k)
+    }
+
+    /// Pads the current byte with zeros.
+    pub fn align(&mut self) -> io::Result<()> {
+        if self.filled > 0 {
+            self.write_bits(0, 8 - self.filled)?;
+        }
+        Ok(())
+    }
+
+    /// Aligns and flushes the stream, returning the sink.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.align()?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
@@ -0,0 +1,154 @@
+/// Extension of a signal beyond its edges.
+///
+/// Lifting steps only read the band they do not modify, so each mode is applied to the band being read.
+/// For a signal `x` of length `n`:
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Boundary {
+    /// Whole-sample symmetric: `x[-1] = x[1]` and `x[n] = x[n - 2]`.
+    ///
+    /// The low and high bands are extended as if the signal itself was mirrored.
+    Symmetric,
+    /// Half-sample symmetric: each band is mirrored with `b[-1] = b[0]` and `b[m] = b[m - 1]`.
+    HalfSymmetric,
+    /// Each band wraps around: `b[-1] = b[m - 1]` and `b[m] = b[0]`.
+    Periodic,
+    /// Samples outside of the signal are zero.
+    Zero,
+    /// Each band repeats its edge samples: `b[-2] = b[-1] = b[0]`.
+    Constant,
+    /// The low band repeats its edge samples, while high band samples outside of the signal are zero.
+    ///
+    /// This is the extension of the original hand-written kernels, kept as the default so that they give the same output.
+    #[default]
+    RepeatLow,
+}
+
+impl Boundary {
+    pub const ALL: [Boundary; 6] = [
+        Boundary::Symmetric,
+        Boundary::HalfSymmetric,
+        Boundary::Periodic,
+        Boundary::Zero,
+        Boundary::Constant,
+        Boundary::RepeatLow,
+    ];
+
+    /// Maps the index `j` of a band to the index of the sample it extends, or `None` if the sample is zero.
+    ///
+    /// `len` is the length of the whole signal, and `odd` tells if the band holds its odd samples.
+    pub fn extend(self, j: isize, len: usize, odd: bool) -> Option<usize> {
+        let m = if odd { len / 2 } else { len.div_ceil(2) } as isize;
+        if (0..m).contains(&j) {
+            return Some(j as usize);
+        }
+        if m == 0 {
+            return None;
+        }
+
+        let j = match self {
+            Boundary::Symmetric => {
+                let n = len as isize;
+                if n == 1 {
+                    return Some(0);
+                }
+                let parity = odd as isize;
+                let period = 2 * (n - 1);
+                let q = (2 * j + parity).rem_euclid(period);
+                let q = if q < n { q } else { period - q };
+                (q - parity) / 2
+            }
+            Boundary::HalfSymmetric => {
+                let j = j.rem_euclid(2 * m);
+                if j < m {
+                    j
+                } else {
+                    2 * m - 1 - j
+                }
+            }
+            Boundary::Periodic => j.rem_euclid(m),
+            Boundary::Zero => return None,
+            Boundary::Constant => j.clamp(0, m - 1),
+            Boundary::RepeatLow if odd => return None,
+            Boundary::RepeatLow => j.clamp(0, m - 1),
+        };
+
+        Some(j as usize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Boundary;
+
+    fn extended(boundary: Boundary, len: usize, odd: bool) -> Vec<Option<usize>> {
+        (-3..len as isize / 2 + 3)
+            .map(|j| boundary.extend(j, len, odd))
+            .collect()
+    }
+
+    #[test]
+    fn symmetric() {
+        // x: 0 1 2 3 4 5 6 7, mirrored as ... 3 2 1 | 0 1 ... 6 7 | 6 5 4 ...
+        assert_eq!(
+            extended(Boundary::Symmetric, 8, false),
+            [3, 2, 1, 0, 1, 2, 3, 3, 2, 1].map(Some)
+        );
+        assert_eq!(
+            extended(Boundary::Symmetric, 8, true),
+            [2, 1, 0, 0, 1, 2, 3, 2, 1, 0].map(Some)
+        );
+        assert_eq!(
+            extended(Boundary::Symmetric, 2, true),
+            [0, 0, 0, 0, 0, 0, 0].map(Some)
+        );
+        assert_eq!(extended(Boundary::Symmetric, 1, false)[3], Some(0));
+    }
+
+    #[test]
+    fn other_modes() {
+        assert_eq!(
+            extended(Boundary::HalfSymmetric, 6, false),
+            [2, 1, 0, 0, 1, 2, 2, 1, 0].map(Some)
+        );
+        assert_eq!(
+            extended(Boundary::Periodic, 6, true),
+            [0, 1, 2, 0, 1, 2, 0, 1, 2].map(Some)
+        );
+        assert_eq!(
+            extended(Boundary::Constant, 6, true),
+            [0, 0, 0, 0, 1, 2, 2, 2, 2].map(Some)
+        );
+        assert_eq!(
+            extended(Boundary::RepeatLow, 6, false),
+            [0, 0, 0, 0, 1, 2, 2, 2, 2].map(Some)
+        );
+        assert_eq!(
+            extended(Boundary::RepeatLow, 6, true),
+            [
+                None,
+                None,
+                None,
+                Some(0),
+                Some(1),
+                Some(2),
+                None,
+                None,
+                None
+            ]
+        );
+        assert_eq!(
+            extended(Boundary::Zero, 6, true),
+            [
+                None,
+                None,
+                None,
+                Some(0),
+                Some(1),
+                Some(2),
+                None,
+                None,
+                None
+            ]
+        );
+    }
+}
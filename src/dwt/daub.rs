@@ -1,296 +1,244 @@
-use super::Dwt1;
+use crate::{
+    memory::Strided,
+    numeric::{Overflow, OverflowError, Sample},
+    quant::{PiecewiseLinear, Quantizer},
+};
+
+use super::{
+    lifting::{lifting_kernel_impl, Filter, Lifting, Rounding, Step},
+    Boundary, Dwt1,
+};
 
 pub struct Daub53;
 
-impl Dwt1<i8> for Daub53 {
-    fn dwt1(
-        &self,
-        mut sig: crate::memory::Strided<&mut i8>,
-        mut tmp: crate::memory::Strided<&mut i8>,
-    ) {
-        for (&src, dst) in sig.iter().zip(tmp.iter_mut()) {
-            *dst = src;
-        }
-        let [src1, src2] = tmp.deinterleave_array();
-        let (dst1, dst2) = sig.split_at_mut(sig.len() / 2);
+impl Daub53 {
+    pub const LIFTING: Lifting<'static> = Lifting::new(
+        &[
+            Step::Predict(Filter::new(0, &[1, 1], 0, 1)),
+            Step::Update(Filter::new(-1, &[1, 1], 0, 2)),
+        ],
+        Rounding::Truncate,
+    );
+}
 
-        let mut h0 = 0i16;
-        for (i, (&a, (&b, (l, h)))) in src1
-            .into_iter()
-            .zip(src2.into_iter().zip(dst1.into_iter().zip(dst2)))
-            .enumerate()
-        {
-            let c = tmp.checked_get(2 * i + 2).copied().unwrap_or(a);
-            *h = b.wrapping_sub(((a as i16 + c as i16) / 2) as i8);
-            *l = a.wrapping_add(((h0 + *h as i16) / 4) as i8);
-            h0 = *h as i16;
-        }
-    }
+lifting_kernel_impl!(Daub53);
+
+/// Integer-to-integer version of the CDF 9/7 wavelet from the following paper
+///
+/// > Calderbank, A. Robert, et al.
+/// > "Wavelet transforms that map integers to integers."
+/// > Applied and computational harmonic analysis 5.3 (1998): 332-369.
+///
+/// The irrational lifting coefficients are approximated by dyadic fractions:
+///
+/// ```text
+/// d1[n] = x[2n+1] - floor(203/128 (x[2n] + x[2n+2]) + 1/2)
+/// s1[n] = x[2n] - floor(217/4096 (d1[n-1] + d1[n]) + 1/2)
+/// d[n] = d1[n] + floor(113/128 (s1[n] + s1[n+1]) + 1/2)
+/// s[n] = s1[n] + floor(1817/4096 (d[n-1] + d[n]) + 1/2)
+/// ```
+pub struct Daub97;
+
+impl Daub97 {
+    pub const LIFTING: Lifting<'static> = Lifting::new(
+        &[
+            Step::Predict(Filter::new(0, &[203, 203], 64, 7)),
+            Step::Update(Filter::new(-1, &[-217, -217], 2047, 12)),
+            Step::Predict(Filter::new(0, &[-113, -113], 63, 7)),
+            Step::Update(Filter::new(-1, &[1817, 1817], 2048, 12)),
+        ],
+        Rounding::Floor,
+    );
+}
 
-    fn idwt1(
-        &self,
-        sig: crate::memory::Strided<&mut i8>,
-        mut tmp: crate::memory::Strided<&mut i8>,
-    ) {
-        for (&src, dst) in sig.iter().zip(tmp.iter_mut()) {
-            *dst = src;
-        }
-        let (src1, src2) = tmp.split_at(tmp.len() / 2);
-        let [dst1, dst2] = sig.into_deinterleave_array();
+lifting_kernel_impl!(Daub97);
 
-        let mut c = None;
-        for (i, (&l, (&h, (dst1, dst2)))) in src1
-            .into_iter()
-            .zip(src2.into_iter().zip(dst1.into_iter().zip(dst2)))
-            .enumerate()
-            .rev()
-        {
-            let h0 = src2
-                .checked_get(i.wrapping_sub(1))
-                .copied()
-                .unwrap_or_default();
-            let a = l.wrapping_sub(((h0 as i16 + h as i16) / 4) as i8);
-            let b = h.wrapping_add(((a as i16 + c.unwrap_or(a) as i16) / 2) as i8);
-            c = Some(a);
+/// Lossy 5/3 kernel on 8-bit samples, compressing the high band with a piecewise linear quantizer.
+///
+/// The high band is quantized after the update step, see [`Quantized`](super::quantized::Quantized) for a drift-free version.
+#[derive(Debug, Clone, Copy)]
+pub struct LossyDaub53 {
+    pub boundary: Boundary,
+    /// The transform is computed on the wider type, this policy applies when narrowing the bands back
+    pub overflow: Overflow,
+}
 
-            *dst1 = a;
-            *dst2 = b;
+/// Clamps the bands to the sample range, like the original 8-bit kernel.
+impl Default for LossyDaub53 {
+    fn default() -> Self {
+        Self {
+            boundary: Boundary::default(),
+            overflow: Overflow::Saturating,
         }
     }
 }
 
-impl Dwt1<i16> for Daub53 {
-    fn dwt1(
+impl LossyDaub53 {
+    /// Quantizer of the high band
+    pub const QUANTIZER: PiecewiseLinear = PiecewiseLinear::new(64, 3);
+
+    fn forward<W: Sample, T: Sample<Wide = W>>(
         &self,
-        mut sig: crate::memory::Strided<&mut i16>,
-        mut tmp: crate::memory::Strided<&mut i16>,
-    ) {
-        for (&src, dst) in sig.iter().zip(tmp.iter_mut()) {
-            *dst = src;
+        overflow: Overflow,
+        mut sig: Strided<&mut T>,
+    ) -> Result<(), OverflowError> {
+        let mut wide = sig.iter().map(|&x| x.widen()).collect::<Vec<_>>();
+        let mut tmp = wide.clone();
+        // The wide type holds any 5/3 coefficient of the samples, and wrapping never fails anyway
+        _ = Daub53::LIFTING
+            .with_boundary(self.boundary)
+            .forward(wide.as_mut_slice().into(), tmp.as_mut_slice().into());
+
+        let (low, high) = wide.split_at(sig.len().div_ceil(2));
+        let (dst1, dst2) = sig.split_at_mut(sig.len().div_ceil(2));
+        for (&l, dst1) in low.iter().zip(dst1) {
+            *dst1 = overflow.narrow(l.to_i64()).ok_or(OverflowError)?;
         }
-        let [src1, src2] = tmp.deinterleave_array();
-        let (dst1, dst2) = sig.split_at_mut(sig.len() / 2);
-
-        let mut h0 = 0i16;
-        for (i, (&a, (&b, (l, h)))) in src1
-            .into_iter()
-            .zip(src2.into_iter().zip(dst1.into_iter().zip(dst2)))
-            .enumerate()
-        {
-            let c = tmp.checked_get(2 * i + 2).copied().unwrap_or(a);
-            *h = b.wrapping_sub((a + c) / 2);
-            *l = a.wrapping_add((h0 + *h) / 4);
-            h0 = *h;
+        for (&h, dst2) in high.iter().zip(dst2) {
+            let q = Self::QUANTIZER.quantize(h.to_i64());
+            *dst2 = overflow.narrow(q).ok_or(OverflowError)?;
         }
+        Ok(())
     }
 
-    fn idwt1(
+    fn inverse<W: Sample, T: Sample<Wide = W>>(
         &self,
-        sig: crate::memory::Strided<&mut i16>,
-        mut tmp: crate::memory::Strided<&mut i16>,
-    ) {
-        for (&src, dst) in sig.iter().zip(tmp.iter_mut()) {
-            *dst = src;
-        }
-        let (src1, src2) = tmp.split_at(tmp.len() / 2);
-        let [dst1, dst2] = sig.into_deinterleave_array();
-
-        let mut c = None;
-        for (i, (&l, (&h, (dst1, dst2)))) in src1
-            .into_iter()
-            .zip(src2.into_iter().zip(dst1.into_iter().zip(dst2)))
+        overflow: Overflow,
+        mut sig: Strided<&mut T>,
+    ) -> Result<(), OverflowError> {
+        let half = sig.len().div_ceil(2);
+        let mut wide = sig
+            .iter()
             .enumerate()
-            .rev()
-        {
-            let h0 = src2
-                .checked_get(i.wrapping_sub(1))
-                .copied()
-                .unwrap_or_default();
-            let a = l - (h0 + h) / 4;
-            let b = h + (a + c.unwrap_or(a)) / 2;
-            c = Some(a);
-
-            *dst1 = a;
-            *dst2 = b;
+            .map(|(i, &x)| {
+                if i < half {
+                    x.widen()
+                } else {
+                    W::saturating_from_i64(Self::QUANTIZER.dequantize(x.to_i64()))
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut tmp = wide.clone();
+        _ = Daub53::LIFTING
+            .with_boundary(self.boundary)
+            .inverse(wide.as_mut_slice().into(), tmp.as_mut_slice().into());
+
+        for (&x, dst) in wide.iter().zip(sig.iter_mut()) {
+            *dst = overflow.narrow(x.to_i64()).ok_or(OverflowError)?;
         }
+        Ok(())
     }
 }
 
-pub struct LossyDaub53;
-
-fn scale_down(x: i16, t: i16, n: i16) -> i16 {
-    if x < -t {
-        ((x + t) / n) - t
-    } else if x > t {
-        ((x - t) / n) + t
-    } else {
-        x
-    }
-}
-fn scale_up(x: i16, t: i16, n: i16) -> i16 {
-    if x < -t {
-        ((x + t) * n) - t
-    } else if x > t {
-        ((x - t) * n) + t
-    } else {
-        x
-    }
-}
-
-fn encode(x: i16) -> i16 {
-    scale_down(x, 64, 3)
-
-    // match x {
-    //     ..-256 => -17, // x < -256
-    //     ..-192 => -16, // -256 <= x < -192
-    //     ..-128 => -15, // -192 <= x < -128
-    //     ..-96 => -14,  // -128 <= x < -96
-    //     ..-64 => -13,  // -96 <= x < -64
-    //     ..-48 => -12,  // -64 <= x < -48
-    //     ..-32 => -11,  // -48 <= x < -32
-    //     ..-24 => -10,  // -32 <= x < -24
-    //     ..-16 => -9,   // -24 <= x < -16
-    //     ..-12 => -8,   // -16 <= x < -12
-    //     ..-8 => -7,    // -12 <= x < -8
-    //     ..-6 => -6,    // -8 <= x < -6
-    //     ..-4 => -5,    // -6 <= x < -4
-    //     ..-3 => -4,    // x = -4
-    //     ..-2 => -3,    // x = -3
-    //     ..-1 => -2,    // x = -2
-    //     ..0 => -1,     // x = -1
-    //     ..1 => 0,      // x = 0
-    //     ..2 => 1,      // x = 1
-    //     ..3 => 2,      // x = 2
-    //     ..4 => 3,      // x = 3
-    //     ..6 => 4,      // 4 <= x < 6
-    //     ..8 => 5,      // 6 <= x < 8
-    //     ..12 => 6,     // 8 <= x < 12
-    //     ..16 => 7,     // 12 <= x < 16
-    //     ..24 => 8,     // 16 <= x < 24
-    //     ..32 => 9,     // 24 <= x < 32
-    //     ..48 => 10,    // 32 <= x < 48
-    //     ..64 => 11,    // 48 <= x < 64
-    //     ..96 => 12,    // 64 <= x < 96
-    //     ..128 => 13,   // 96 <= x < 128
-    //     ..192 => 14,   // 128 <= x < 192
-    //     ..256 => 15,   // 192 <= x < 256
-    //     _ => 16,       // 256 <= x
-    // }
-}
-fn decode(x: i16) -> i16 {
-    scale_up(x, 64, 3)
-
-    // match x {
-    //     ..=-17 => -256, // x < -256
-    //     -16 => -224,    // -256 <= x < -192
-    //     -15 => -160,    // -192 <= x < -128
-    //     -14 => -112,    // -128 <= x < -96
-    //     -13 => -80,     // -96 <= x < -64
-    //     -12 => -56,     // -64 <= x < -48
-    //     -11 => -40,     // -48 <= x < -32
-    //     -10 => -28,     // -32 <= x < -24
-    //     -9 => -20,      // -24 <= x < -16
-    //     -8 => -14,      // -16 <= x < -12
-    //     -7 => -10,      // -12 <= x < -8
-    //     -6 => -7,       // -8 <= x < -6
-    //     -5 => -5,       // -6 <= x < -4
-    //     -4 => -4,       // x = -4
-    //     -3 => -3,       // x = -3
-    //     -2 => -2,       // x = -2
-    //     -1 => -1,       // x = -1
-    //     0 => 0,         // x = 0
-    //     1 => 1,         // x = 1
-    //     2 => 2,         // x = 2
-    //     3 => 3,         // x = 3
-    //     4 => 4,         // 4 <= x < 6
-    //     5 => 6,         // 6 <= x < 8
-    //     6 => 9,         // 8 <= x < 12
-    //     7 => 13,        // 12 <= x < 16
-    //     8 => 19,        // 16 <= x < 24
-    //     9 => 27,        // 24 <= x < 32
-    //     10 => 39,       // 32 <= x < 48
-    //     11 => 55,       // 48 <= x < 64
-    //     12 => 79,       // 64 <= x < 96
-    //     13 => 111,      // 96 <= x < 128
-    //     14 => 159,      // 128 <= x < 192
-    //     15 => 223,      // 192 <= x < 256
-    //     16.. => 256,    // 256 <= x
-    // }
-}
-
-impl Dwt1<i8> for LossyDaub53 {
-    fn dwt1(
-        &self,
-        mut sig: crate::memory::Strided<&mut i8>,
-        mut tmp: crate::memory::Strided<&mut i8>,
-    ) {
-        for (&src, dst) in sig.iter().zip(tmp.iter_mut()) {
-            *dst = src;
+macro_rules! lossy_daub53_impl {
+    ($t:ty) => {
+        impl Dwt1<$t> for LossyDaub53 {
+            fn dwt1(&self, sig: Strided<&mut $t>, _tmp: Strided<&mut $t>) {
+                // Never fails, see `Overflow::infallible`
+                _ = self.forward(self.overflow.infallible(), sig);
+            }
+            fn try_dwt1(
+                &self,
+                sig: Strided<&mut $t>,
+                _tmp: Strided<&mut $t>,
+            ) -> Result<(), OverflowError> {
+                self.forward(self.overflow, sig)
+            }
+
+            fn idwt1(&self, sig: Strided<&mut $t>, _tmp: Strided<&mut $t>) {
+                _ = self.inverse(self.overflow.infallible(), sig);
+            }
+            fn try_idwt1(
+                &self,
+                sig: Strided<&mut $t>,
+                _tmp: Strided<&mut $t>,
+            ) -> Result<(), OverflowError> {
+                self.inverse(self.overflow, sig)
+            }
         }
-        let [src1, src2] = tmp.deinterleave_array();
-        let (dst1, dst2) = sig.split_at_mut(sig.len() / 2);
-
-        let mut h0 = 0i16;
-        for (i, (&a, (&b, (dst1, dst2)))) in src1
-            .into_iter()
-            .zip(src2.into_iter().zip(dst1.into_iter().zip(dst2)))
-            .enumerate()
-        {
-            let c = tmp.checked_get(2 * i + 2).copied().unwrap_or(a);
-            let a = a as i16;
-            let b = b as i16;
-            let c = c as i16;
-
-            let h = b - (a + c) / 2;
-            let l = a + (h0 + h) / 4;
-            h0 = h;
-
-            let h = encode(h);
-            let h = h.clamp(-128, 127) as i8;
-            let l = l.clamp(-128, 127) as i8;
-
-            *dst1 = l;
-            *dst2 = h;
+    };
+}
+// `i64` has no wider sample type, and the quantized high band of unsigned types would lose its sign
+lossy_daub53_impl!(i8);
+lossy_daub53_impl!(i16);
+lossy_daub53_impl!(i32);
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        dwt::Dwt1,
+        numeric::{Overflow, OverflowError},
+        testing::signal,
+    };
+
+    use super::{Daub97, LossyDaub53};
+
+    #[test]
+    fn daub97_round_trip() {
+        for len in [2, 4, 8, 10, 64] {
+            let input = signal(len, 7 + len as u32, |x| x as i16 % 4096);
+
+            let mut sig = input.clone();
+            let mut tmp = vec![0; len];
+            Daub97.dwt1_slice(&mut sig, &mut tmp);
+            Daub97.idwt1_slice(&mut sig, &mut tmp);
+            assert_eq!(sig, input);
+
+            let input = input.iter().map(|&x| x as i32 * 1000).collect::<Vec<_>>();
+            let mut sig = input.clone();
+            let mut tmp = vec![0; len];
+            Daub97.dwt1_slice(&mut sig, &mut tmp);
+            Daub97.idwt1_slice(&mut sig, &mut tmp);
+            assert_eq!(sig, input);
         }
     }
 
-    fn idwt1(
-        &self,
-        sig: crate::memory::Strided<&mut i8>,
-        mut tmp: crate::memory::Strided<&mut i8>,
-    ) {
-        for (&src, dst) in sig.iter().zip(tmp.iter_mut()) {
-            *dst = src;
+    #[test]
+    fn daub97_compaction() {
+        // Polynomials of degree up to 3 are annihilated by the high pass filter away from the borders
+        let input = (0..32)
+            .map(|x: i32| x * x * 4 - x * 16 + 7)
+            .collect::<Vec<_>>();
+        let mut sig = input.clone();
+        let mut tmp = vec![0; input.len()];
+        Daub97.dwt1_slice(&mut sig, &mut tmp);
+
+        for &h in &sig[18..30] {
+            assert!(h.abs() <= 2, "{h}");
         }
-        let (src1, src2) = tmp.split_at(tmp.len() / 2);
-        let [dst1, dst2] = sig.into_deinterleave_array();
-
-        let mut c = None;
-        for (i, (&l, (&h, (dst1, dst2)))) in src1
-            .into_iter()
-            .zip(src2.into_iter().zip(dst1.into_iter().zip(dst2)))
-            .enumerate()
-            .rev()
-        {
-            let h0 = src2
-                .checked_get(i.wrapping_sub(1))
-                .copied()
-                .unwrap_or_default();
-            let l = l as i16;
-            let h = h as i16;
-            let h0 = h0 as i16;
-
-            let h = decode(h);
-            let h0 = decode(h0);
-
-            let a = l - (h + h0) / 4;
-            let b = h + (a + c.unwrap_or(a)) / 2;
-            c = Some(a);
+    }
 
-            let a = a.clamp(-128, 127) as i8;
-            let b = b.clamp(-128, 127) as i8;
-            *dst1 = a;
-            *dst2 = b;
-        }
+    #[test]
+    fn lossy_daub53_overflow() {
+        // The update step overshoots the top of the i8 range around the plateau
+        let input = [-128i8, 127, 127, 127, -128, 0, 0, 0];
+        let mut tmp = [0; 8];
+        let transform = |overflow| {
+            let (mut sig, mut tmp) = (input, [0; 8]);
+            LossyDaub53 {
+                overflow,
+                ..Default::default()
+            }
+            .dwt1_slice(&mut sig, &mut tmp);
+            sig
+        };
+
+        let mut sig = input;
+        LossyDaub53::default().dwt1_slice(&mut sig, &mut tmp);
+        assert_eq!(sig, transform(Overflow::Saturating));
+        assert_eq!(sig[1], 127);
+        assert_eq!(transform(Overflow::Wrapping)[1], 190u8 as i8);
+
+        // Without an error to report, widening wraps around
+        assert_eq!(transform(Overflow::Widening), transform(Overflow::Wrapping));
+        let widening = LossyDaub53 {
+            overflow: Overflow::Widening,
+            ..Default::default()
+        };
+        let mut sig = input;
+        assert_eq!(
+            widening.try_dwt1(sig.as_mut_slice().into(), tmp.as_mut_slice().into()),
+            Err(OverflowError)
+        );
     }
 }
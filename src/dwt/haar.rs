@@ -1,57 +1,125 @@
-use super::Dwt0;
+use crate::{
+    memory::Strided,
+    numeric::{for_each_sample, Overflow, OverflowError, Sample},
+};
+
+use super::{
+    dwt1_pairs, idwt1_pairs,
+    lifting::{Filter, Lifting, LiftingKernel, Rounding, Step},
+    Boundary, Dwt0, Dwt1,
+};
 
 /// Implementation of the Haar wavelet from the following paper
 ///
 /// > Calderbank, A. Robert, et al.
 /// > "Wavelet transforms that map integers to integers."
 /// > Applied and computational harmonic analysis 5.3 (1998): 332-369.
+///
+/// Arithmetic wraps on `i8` and unsigned samples, and is plain on wider signed samples,
+/// use [`Haar::with_overflow`] for an explicit [`Overflow`] policy.
 pub struct Haar;
 
-impl Dwt0<i8> for Haar {
-    fn dwt0(&self, a: i8, b: i8) -> (i8, i8) {
-        let h = b.wrapping_sub(a);
-        let l = a.wrapping_add(h / 2);
+impl Haar {
+    /// The Haar wavelet never reads outside of the signal, so its boundary has no effect.
+    pub const LIFTING: Lifting<'static> = Lifting::new(
+        &[
+            Step::Predict(Filter::new(0, &[1], 0, 0)),
+            Step::Update(Filter::new(0, &[1], 0, 1)),
+        ],
+        Rounding::Truncate,
+    );
 
-        (l, h)
+    /// The kernel with another boundary extension, which has no effect on the Haar wavelet.
+    pub const fn with_boundary(self, boundary: Boundary) -> Lifting<'static> {
+        Self::LIFTING.with_boundary(boundary)
     }
 
-    fn idwt0(&self, l: i8, h: i8) -> (i8, i8) {
-        let a = l.wrapping_sub(h / 2);
-        let b = a.wrapping_add(h);
+    pub const fn with_overflow(self, overflow: Overflow) -> Lifting<'static> {
+        Self::LIFTING.with_overflow(overflow)
+    }
+}
 
-        (a, b)
+impl LiftingKernel for Haar {
+    fn lifting(&self) -> Lifting<'_> {
+        Self::LIFTING
     }
 }
 
-impl Dwt0<i16> for Haar {
-    fn dwt0(&self, a: i16, b: i16) -> (i16, i16) {
-        let h = b - a;
-        let l = a + h / 2;
+impl<T: Sample> Dwt0<T> for Haar {
+    fn dwt0(&self, a: T, b: T) -> (T, T) {
+        if plain::<T>() {
+            let h = b - a;
+            let l = a + half(h);
+            (l, h)
+        } else {
+            let h = b.wrapping_sub(a);
+            let l = a.wrapping_add(half(h));
+            (l, h)
+        }
+    }
 
-        (l, h)
+    fn idwt0(&self, l: T, h: T) -> (T, T) {
+        if plain::<T>() {
+            let a = l - half(h);
+            let b = a + h;
+            (a, b)
+        } else {
+            let a = l.wrapping_sub(half(h));
+            let b = a.wrapping_add(h);
+            (a, b)
+        }
     }
+}
 
-    fn idwt0(&self, l: i16, h: i16) -> (i16, i16) {
-        let a = l - h / 2;
-        let b = a + h;
+/// Whether `T` is a signed type wider than 8 bits, which has room for the coefficients of 8 bit samples.
+///
+/// On these, the kernels use plain arithmetic, so an overflow is caught in debug builds.
+/// `i8` samples use their whole range and unsigned differences are negative, so both wrap.
+fn plain<T: Sample>() -> bool {
+    T::MIN < T::default() && std::mem::size_of::<T>() > 1
+}
 
-        (a, b)
-    }
+/// `x / 2`, rounded toward zero.
+fn half<T: Sample>(x: T) -> T {
+    T::wrapping_narrow(x.widen() / T::Wide::from(2))
 }
 
+/// Haar variant storing half of the difference of `i8` samples, so both bands stay in the sample range.
+///
+/// The least significant bit of the difference is lost.
+/// The low band wraps and the reconstruction saturates, use [`LossyHaar::with_overflow`] for a single policy.
+/// Other sample types have no such constraint and use the lossless [`Haar`] transform.
 pub struct LossyHaar;
 
-impl Dwt0<i8> for LossyHaar {
-    fn dwt0(&self, a: i8, b: i8) -> (i8, i8) {
-        let h = (b as i16) - (a as i16);
-        let h = h / 2;
-        let h = h.clamp(-128, 127) as i8;
+impl LossyHaar {
+    pub fn with_overflow(self, overflow: Overflow) -> LossyHaarWith {
+        LossyHaarWith { overflow }
+    }
+}
+
+/// [`LossyHaar`] with the same overflow policy in both directions.
+///
+/// The lossless transform of the other sample types is [`Haar::with_overflow`].
+pub struct LossyHaarWith {
+    pub overflow: Overflow,
+}
+
+impl<T: Sample> Dwt0<T> for LossyHaar {
+    fn dwt0(&self, a: T, b: T) -> (T, T) {
+        if !lossy::<T>() {
+            return Haar.dwt0(a, b);
+        }
+        let h = (b.widen() - a.widen()) / T::Wide::from(2);
+        let h = T::saturating_narrow(h);
         let l = a.wrapping_add(h);
 
         (l, h)
     }
 
-    fn idwt0(&self, l: i8, h: i8) -> (i8, i8) {
+    fn idwt0(&self, l: T, h: T) -> (T, T) {
+        if !lossy::<T>() {
+            return Haar.idwt0(l, h);
+        }
         let a = l.saturating_sub(h);
         let b = a.saturating_add(h).saturating_add(h);
 
@@ -59,18 +127,141 @@ impl Dwt0<i8> for LossyHaar {
     }
 }
 
-impl Dwt0<i16> for LossyHaar {
-    fn dwt0(&self, a: i16, b: i16) -> (i16, i16) {
-        let h = b - a;
-        let l = a + h / 2;
+/// Whether `T` is `i8`, the only type [`LossyHaar`] is lossy on.
+fn lossy<T: Sample>() -> bool {
+    T::MIN < T::default() && std::mem::size_of::<T>() == 1
+}
 
-        (l, h)
+impl LossyHaarWith {
+    fn forward<T: Sample>(
+        &self,
+        overflow: Overflow,
+        sig: Strided<&mut T>,
+        tmp: Strided<&mut T>,
+    ) -> Result<(), OverflowError> {
+        if !lossy::<T>() {
+            return Haar.with_overflow(overflow).forward(sig, tmp);
+        }
+        dwt1_pairs(sig, tmp, |a, b| {
+            let h = T::saturating_narrow((b.widen() - a.widen()) / T::Wide::from(2));
+            let l = overflow.add(a, h.to_i64() as i128).ok_or(OverflowError)?;
+            Ok((l, h))
+        })
     }
 
-    fn idwt0(&self, l: i16, h: i16) -> (i16, i16) {
-        let a = l - h / 2;
-        let b = a + h;
+    fn inverse<T: Sample>(
+        &self,
+        overflow: Overflow,
+        sig: Strided<&mut T>,
+        tmp: Strided<&mut T>,
+    ) -> Result<(), OverflowError> {
+        if !lossy::<T>() {
+            return Haar.with_overflow(overflow).inverse(sig, tmp);
+        }
+        idwt1_pairs(sig, tmp, |l, h| {
+            let h = h.to_i64() as i128;
+            let a = overflow.add(l, -h).ok_or(OverflowError)?;
+            let b = overflow.add(a, 2 * h).ok_or(OverflowError)?;
+            Ok((a, b))
+        })
+    }
+}
 
-        (a, b)
+macro_rules! lossy_haar_impl {
+    ($t:ty) => {
+        impl Dwt1<$t> for LossyHaarWith {
+            fn dwt1(&self, sig: Strided<&mut $t>, tmp: Strided<&mut $t>) {
+                // Never fails, see `Overflow::infallible`
+                _ = self.forward(self.overflow.infallible(), sig, tmp);
+            }
+            fn try_dwt1(
+                &self,
+                sig: Strided<&mut $t>,
+                tmp: Strided<&mut $t>,
+            ) -> Result<(), OverflowError> {
+                self.forward(self.overflow, sig, tmp)
+            }
+
+            fn idwt1(&self, sig: Strided<&mut $t>, tmp: Strided<&mut $t>) {
+                _ = self.inverse(self.overflow.infallible(), sig, tmp);
+            }
+            fn try_idwt1(
+                &self,
+                sig: Strided<&mut $t>,
+                tmp: Strided<&mut $t>,
+            ) -> Result<(), OverflowError> {
+                self.inverse(self.overflow, sig, tmp)
+            }
+        }
+    };
+}
+for_each_sample!(lossy_haar_impl);
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        dwt::Dwt1,
+        numeric::{Overflow, OverflowError},
+        testing::signal,
+    };
+
+    use super::{Haar, LossyHaar};
+
+    #[test]
+    fn lossy_overflow_policies() {
+        // Clamped or corrupted coefficients reconstruct out of the i8 range
+        let coefs = [127i8, -100];
+        let mut tmp = [0; 2];
+        let inverse = |overflow| {
+            let (mut sig, mut tmp) = (coefs, [0; 2]);
+            LossyHaar
+                .with_overflow(overflow)
+                .idwt1_slice(&mut sig, &mut tmp);
+            sig
+        };
+
+        let mut sig = coefs;
+        LossyHaar.idwt1_slice(&mut sig, &mut tmp);
+        assert_eq!(sig, [127, -73]);
+        assert_eq!(inverse(Overflow::Saturating), sig);
+        assert_eq!(inverse(Overflow::Wrapping), [227u8 as i8, 27]);
+        assert_eq!(inverse(Overflow::Widening), inverse(Overflow::Wrapping));
+
+        let mut sig = coefs;
+        assert_eq!(
+            LossyHaar
+                .with_overflow(Overflow::Widening)
+                .try_idwt1(sig.as_mut_slice().into(), tmp.as_mut_slice().into()),
+            Err(OverflowError)
+        );
+
+        // Only the least significant bit of the odd samples is lost
+        let input = signal(64, 5, |x| x as i8);
+        for overflow in [Overflow::Wrapping, Overflow::Saturating, Overflow::Widening] {
+            let kernel = LossyHaar.with_overflow(overflow);
+            let mut sig = input.clone();
+            let mut tmp = vec![0; 64];
+            kernel.dwt1_slice(&mut sig, &mut tmp);
+            kernel.idwt1_slice(&mut sig, &mut tmp);
+            for (x, y) in sig.iter().zip(&input) {
+                assert!(x.abs_diff(*y) <= 1, "{overflow:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn haar_overflow() {
+        let mut sig = [-128i8, 127];
+        let mut tmp = [0; 2];
+        assert_eq!(
+            Haar.with_overflow(Overflow::Widening)
+                .try_dwt1(sig.as_mut_slice().into(), tmp.as_mut_slice().into()),
+            Err(OverflowError)
+        );
+
+        let mut sig = [-128i8, 127];
+        Haar.with_overflow(Overflow::Saturating)
+            .dwt1_slice(&mut sig, &mut tmp);
+        assert_eq!(sig, [-128 + 63, 127]);
     }
 }
@@ -0,0 +1,188 @@
+use crate::memory::{ImageView, ImageViewMut};
+
+/// Orientation of a subband, named after its vertical then horizontal frequencies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Orientation {
+    /// Low frequencies in both directions, only present at the coarsest level
+    LL,
+    /// Horizontal details, stored at the top right of the level
+    LH,
+    /// Vertical details, stored at the bottom left of the level
+    HL,
+    /// Diagonal details, stored at the bottom right of the level
+    HH,
+}
+
+impl Orientation {
+    /// Detail orientations of a level, in storage order.
+    pub const DETAILS: [Orientation; 3] = [Orientation::LH, Orientation::HL, Orientation::HH];
+}
+
+/// Position of a subband inside a Mallat decomposition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Subband {
+    /// Decomposition level, from 1 (finest details) to the number of levels (`LL` band)
+    pub level: usize,
+    pub orientation: Orientation,
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Subband {
+    pub fn size(&self) -> usize {
+        self.width * self.height
+    }
+
+    pub fn view<'a, T>(&self, image: ImageView<'a, T>) -> ImageView<'a, T> {
+        image.into_subview(self.x, self.y, self.width, self.height)
+    }
+
+    pub fn view_mut<'a, T>(&self, image: ImageViewMut<'a, T>) -> ImageViewMut<'a, T> {
+        image.into_subview_mut(self.x, self.y, self.width, self.height)
+    }
+}
+
+/// Subbands of a multilevel Mallat decomposition.
+///
+/// Each level transforms the `LL` band of the previous level in place.
+/// A region of `w x h` samples splits into `ceil(w / 2) x ceil(h / 2)` low samples,
+/// so odd sizes are supported at every level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubbandLayout {
+    width: usize,
+    height: usize,
+    levels: usize,
+}
+
+impl SubbandLayout {
+    pub fn new(width: usize, height: usize, levels: usize) -> Self {
+        Self {
+            width,
+            height,
+            levels,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    pub fn levels(&self) -> usize {
+        self.levels
+    }
+
+    /// Size of the `LL` region after `level` decompositions, `level` 0 being the whole image.
+    pub fn low_size(&self, level: usize) -> (usize, usize) {
+        (
+            self.width.div_ceil(1 << level),
+            self.height.div_ceil(1 << level),
+        )
+    }
+
+    pub fn checked_band(&self, level: usize, orientation: Orientation) -> Option<Subband> {
+        if orientation == Orientation::LL {
+            if level != self.levels {
+                return None;
+            }
+            let (width, height) = self.low_size(level);
+            return Some(Subband {
+                level,
+                orientation,
+                x: 0,
+                y: 0,
+                width,
+                height,
+            });
+        }
+        if level == 0 || level > self.levels {
+            return None;
+        }
+
+        let (width, height) = self.low_size(level - 1);
+        let (low_width, low_height) = self.low_size(level);
+        let (x, width) = match orientation {
+            Orientation::LH | Orientation::HH => (low_width, width - low_width),
+            _ => (0, low_width),
+        };
+        let (y, height) = match orientation {
+            Orientation::HL | Orientation::HH => (low_height, height - low_height),
+            _ => (0, low_height),
+        };
+        Some(Subband {
+            level,
+            orientation,
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+
+    pub fn band(&self, level: usize, orientation: Orientation) -> Subband {
+        self.checked_band(level, orientation).unwrap_or_else(|| {
+            panic!(
+                "No {orientation:?} band at level {level} of a {} level decomposition",
+                self.levels
+            )
+        })
+    }
+
+    /// All the subbands, from the `LL` band to the finest details.
+    pub fn bands(&self) -> impl Iterator<Item = Subband> + '_ {
+        std::iter::once(self.band(self.levels, Orientation::LL)).chain(
+            (1..=self.levels)
+                .rev()
+                .flat_map(|level| Orientation::DETAILS.map(|o| self.band(level, o))),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        dwt::{daub::Daub53, Dwt2},
+        memory::Image,
+    };
+
+    use super::{Orientation, SubbandLayout};
+
+    #[test]
+    fn bands_tile_the_image() {
+        let layout = SubbandLayout::new(45, 1080, 5);
+        let mut count = Image::with_value(45, 1080, &0u8);
+        for band in layout.bands() {
+            for row in band.view_mut(count.view_mut()).rows_mut() {
+                for cell in row {
+                    *cell += 1;
+                }
+            }
+        }
+        assert!(count.rows().all(|row| row.iter().all(|&c| c == 1)));
+        assert_eq!(layout.bands().count(), 16);
+
+        let hh = layout.band(4, Orientation::HH);
+        assert_eq!((hh.x, hh.y, hh.width, hh.height), (3, 68, 3, 67));
+        assert_eq!(layout.checked_band(4, Orientation::LL), None);
+        assert_eq!(layout.checked_band(6, Orientation::LH), None);
+    }
+
+    #[test]
+    fn decompose_round_trip() {
+        let input = Image::with_fn(37, 29, |x, y| ((x * 13 + y * 7) % 251) as i16 - 125);
+        let mut image = input.clone();
+        let mut tmp = input.clone();
+
+        let layout = Daub53.decompose(image.view_mut(), tmp.view_mut(), 3);
+        assert_eq!(layout, SubbandLayout::new(37, 29, 3));
+
+        let ll = layout.band(3, Orientation::LL);
+        assert_eq!((ll.width, ll.height), (5, 4));
+
+        Daub53.reconstruct(image.view_mut(), tmp.view_mut(), 3);
+        assert_eq!(image, input);
+    }
+}
@@ -0,0 +1,612 @@
+use crate::{
+    memory::Strided,
+    numeric::{for_each_sample, Overflow, OverflowError, Sample},
+    quant::Quantizer,
+};
+
+use super::{Boundary, Dwt1};
+
+/// Rounding applied to the output of a lifting [`Filter`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// Round toward zero, like the integer division used by the hand-written kernels.
+    #[default]
+    Truncate,
+    /// Round toward negative infinity, like the `floor` of the integer wavelet literature.
+    ///
+    /// Steps with the opposite sign of their step kind use `-floor(y / k) = floor((k - 1 - y) / k)`.
+    Floor,
+}
+
+/// Integer filter computing `(sum(taps[k] * band[i + start + k]) + offset) / 2^shift`.
+///
+/// A filter can also look ahead in the band it modifies:
+/// `sum(lookahead[k] * target[i + 1 + k])` is added to the sum, using the values before the step.
+/// Lookahead samples that the boundary extension maps back onto `i` or before are zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Filter<'a> {
+    pub start: isize,
+    pub taps: &'a [i32],
+    pub lookahead: &'a [i32],
+    pub offset: i32,
+    pub shift: u32,
+}
+
+impl<'a> Filter<'a> {
+    pub const fn new(start: isize, taps: &'a [i32], offset: i32, shift: u32) -> Self {
+        Self {
+            start,
+            taps,
+            lookahead: &[],
+            offset,
+            shift,
+        }
+    }
+
+    pub const fn with_lookahead(self, lookahead: &'a [i32]) -> Self {
+        Self { lookahead, ..self }
+    }
+}
+
+/// Single step of a lifting scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step<'a> {
+    /// `high[i] -= filter(low)`
+    Predict(Filter<'a>),
+    /// `low[i] += filter(high)`
+    Update(Filter<'a>),
+}
+
+/// Wavelet kernel described by an ordered list of lifting steps.
+///
+/// The signal is split into its even samples (low band) and odd samples (high band),
+/// then each step is applied in order.
+/// A signal of length `n` has `ceil(n / 2)` low band samples and `floor(n / 2)` high band samples.
+/// The inverse transform undoes the steps in reverse order, so any list of steps is perfectly reversible.
+///
+/// Samples outside of the signal are provided by the [`Boundary`] extension, and reconstruction stays perfect whatever the mode.
+/// Results out of the sample range are handled by the [`Overflow`] policy, which only keeps reconstruction perfect when wrapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lifting<'a> {
+    pub steps: &'a [Step<'a>],
+    pub rounding: Rounding,
+    pub boundary: Boundary,
+    pub overflow: Overflow,
+}
+
+impl<'a> Lifting<'a> {
+    pub const fn new(steps: &'a [Step<'a>], rounding: Rounding) -> Self {
+        Self {
+            steps,
+            rounding,
+            boundary: Boundary::RepeatLow,
+            overflow: Overflow::Wrapping,
+        }
+    }
+
+    pub const fn with_boundary(self, boundary: Boundary) -> Self {
+        Self { boundary, ..self }
+    }
+
+    pub const fn with_overflow(self, overflow: Overflow) -> Self {
+        Self { overflow, ..self }
+    }
+
+    /// Runs the steps on bands of `len` samples in total, calling `apply` for each sample a step modifies.
+    ///
+    /// The samples are visited in the order the steps must run, forward or `inverse`,
+    /// so that transforms on other kinds of samples than plain values share the lifting loop.
+    pub(super) fn run<E>(
+        &self,
+        len: usize,
+        inverse: bool,
+        mut apply: impl FnMut(&Lift<'_>) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let mut run_step = |(index, step): (usize, &Step<'_>)| {
+            let (filter, predict, sign, target_len) = match step {
+                Step::Predict(filter) => (filter, true, -1, len / 2),
+                Step::Update(filter) => (filter, false, 1, len.div_ceil(2)),
+            };
+            for k in 0..target_len {
+                // With a lookahead, the inverse must recover the samples after `i` before recovering `i`
+                let target = if inverse { target_len - 1 - k } else { k };
+                apply(&Lift {
+                    lifting: self,
+                    filter,
+                    step: index,
+                    predict,
+                    target,
+                    sign: if inverse { -sign } else { sign },
+                    len,
+                })?;
+            }
+            Ok(())
+        };
+
+        if inverse {
+            self.steps
+                .iter()
+                .enumerate()
+                .rev()
+                .try_for_each(&mut run_step)
+        } else {
+            self.steps.iter().enumerate().try_for_each(&mut run_step)
+        }
+    }
+
+    fn lift<'s, T: Copy>(
+        &self,
+        low: &mut Strided<&'s mut T>,
+        high: &mut Strided<&'s mut T>,
+        inverse: bool,
+        widen: impl Fn(T) -> i64,
+        add: impl Fn(T, i128) -> Option<T>,
+    ) -> Result<(), OverflowError> {
+        let len = low.len() + high.len();
+        self.run(len, inverse, |lift| {
+            let (target, source) = if lift.predict {
+                (&mut *high, &*low)
+            } else {
+                (&mut *low, &*high)
+            };
+            let d = lift.filter(|j| widen(source[j]), |j| widen(target[j]));
+            let i = lift.target;
+            target[i] = add(target[i], lift.sign * d).ok_or(OverflowError)?;
+            Ok(())
+        })
+    }
+}
+
+/// Update of a single sample by a lifting step, see [`Lifting::run`].
+pub(super) struct Lift<'a> {
+    lifting: &'a Lifting<'a>,
+    filter: &'a Filter<'a>,
+    /// Index of the step in [`Lifting::steps`]
+    pub step: usize,
+    /// Whether the step modifies the high band from the low band
+    pub predict: bool,
+    /// Index of the modified sample in its band
+    pub target: usize,
+    /// `1` when the filter output is added to the sample, `-1` when it is subtracted
+    pub sign: i128,
+    len: usize,
+}
+
+impl Lift<'_> {
+    /// Indices in the other band of the samples read by the taps, `None` for the zeros of the boundary extension.
+    pub fn sources(&self) -> impl Iterator<Item = Option<usize>> + '_ {
+        let start = self.target as isize + self.filter.start;
+        (0..self.filter.taps.len()).map(move |k| {
+            self.lifting
+                .boundary
+                .extend(start + k as isize, self.len, !self.predict)
+        })
+    }
+
+    /// Output of the filter, reading the other band with `source` and the modified band with `target`.
+    ///
+    /// Both functions get indices inside their band, already extended by the boundary.
+    pub fn filter(&self, source: impl Fn(usize) -> i64, target: impl Fn(usize) -> i64) -> i128 {
+        let mut sum = self.filter.offset as i128;
+        for (&tap, j) in self.filter.taps.iter().zip(self.sources()) {
+            sum += tap as i128 * j.map_or(0, &source) as i128;
+        }
+        for (k, &tap) in self.filter.lookahead.iter().enumerate() {
+            let j = self.target as isize + 1 + k as isize;
+            // Samples reflected onto the target or before would not be the same in both directions
+            let value = self
+                .lifting
+                .boundary
+                .extend(j, self.len, self.predict)
+                .filter(|&j| j > self.target)
+                .map_or(0, &target);
+            sum += tap as i128 * value as i128;
+        }
+
+        match self.lifting.rounding {
+            Rounding::Truncate => sum / (1 << self.filter.shift),
+            Rounding::Floor => sum >> self.filter.shift,
+        }
+    }
+}
+
+impl Lifting<'_> {
+    /// The same transform, with an overflow policy that never fails, see [`Overflow::infallible`].
+    pub(super) fn infallible(&self) -> Self {
+        self.with_overflow(self.overflow.infallible())
+    }
+
+    pub(super) fn forward<T: Sample>(
+        &self,
+        mut sig: Strided<&mut T>,
+        mut tmp: Strided<&mut T>,
+    ) -> Result<(), OverflowError> {
+        for (&src, dst) in sig.iter().zip(tmp.iter_mut()) {
+            *dst = src;
+        }
+        let [even, odd] = tmp.deinterleave_array();
+        let (mut low, mut high) = sig.split_at_mut(sig.len().div_ceil(2));
+
+        for (&src, dst) in even.into_iter().zip(low.iter_mut()) {
+            *dst = src;
+        }
+        for (&src, dst) in odd.into_iter().zip(high.iter_mut()) {
+            *dst = src;
+        }
+
+        self.lift(&mut low, &mut high, false, T::to_i64, |x, d| {
+            self.overflow.add(x, d)
+        })
+    }
+
+    pub(super) fn inverse<T: Sample>(
+        &self,
+        sig: Strided<&mut T>,
+        mut tmp: Strided<&mut T>,
+    ) -> Result<(), OverflowError> {
+        for (&src, dst) in sig.iter().zip(tmp.iter_mut()) {
+            *dst = src;
+        }
+        let (mut low, mut high) = tmp.split_at_mut(tmp.len().div_ceil(2));
+
+        self.lift(&mut low, &mut high, true, T::to_i64, |x, d| {
+            self.overflow.add(x, d)
+        })?;
+
+        let [even, odd] = sig.into_deinterleave_array();
+        for (&src, dst) in low.iter().zip(even) {
+            *dst = src;
+        }
+        for (&src, dst) in high.iter().zip(odd) {
+            *dst = src;
+        }
+        Ok(())
+    }
+}
+
+impl Lifting<'_> {
+    /// Forward transform quantizing the high band inside the lifting steps.
+    ///
+    /// The first prediction quantizes the high band, and the following predictions subtract
+    /// quantized predictions from the indices, so the inverse recovers exactly the same indices.
+    /// Every later step reads the dequantized high band, like the inverse will,
+    /// and the high band is left holding the quantization indices.
+    ///
+    /// Update steps before the first prediction would read odd samples the inverse cannot recover exactly,
+    /// so only kernels starting with a prediction, like all the kernels of this crate, are drift-free.
+    pub(super) fn forward_quantized<T: Sample>(
+        &self,
+        mut sig: Strided<&mut T>,
+        mut tmp: Strided<&mut T>,
+        quantizer: &impl Quantizer,
+    ) -> Result<(), OverflowError> {
+        for (&src, dst) in sig.iter().zip(tmp.iter_mut()) {
+            *dst = src;
+        }
+        let [even, odd] = tmp.deinterleave_array();
+        let (mut low, mut high) = sig.split_at_mut(sig.len().div_ceil(2));
+
+        for (&src, dst) in even.into_iter().zip(low.iter_mut()) {
+            *dst = src;
+        }
+        let mut indices = odd.iter().map(|h| h.to_i64()).collect::<Vec<_>>();
+        self.lift_quantized(&mut low, &mut indices, false, quantizer)?;
+
+        for (h, &q) in high.iter_mut().zip(&indices) {
+            *h = self.overflow.narrow(q).ok_or(OverflowError)?;
+        }
+        Ok(())
+    }
+
+    /// Inverse of [`Lifting::forward_quantized`], lossy only by the quantization of the high band.
+    pub(super) fn inverse_quantized<T: Sample>(
+        &self,
+        sig: Strided<&mut T>,
+        mut tmp: Strided<&mut T>,
+        quantizer: &impl Quantizer,
+    ) -> Result<(), OverflowError> {
+        for (&src, dst) in sig.iter().zip(tmp.iter_mut()) {
+            *dst = src;
+        }
+        let (mut low, high) = tmp.split_at_mut(tmp.len().div_ceil(2));
+        let mut values = high.iter().map(|q| q.to_i64()).collect::<Vec<_>>();
+        self.lift_quantized(&mut low, &mut values, true, quantizer)?;
+
+        let [even, odd] = sig.into_deinterleave_array();
+        for (&src, dst) in low.iter().zip(even) {
+            *dst = src;
+        }
+        for (&h, dst) in values.iter().zip(odd) {
+            *dst = self.overflow.narrow(h).ok_or(OverflowError)?;
+        }
+        Ok(())
+    }
+
+    /// Lifting loop of [`Lifting::forward_quantized`], the high band being values before the first prediction
+    /// and indices after it.
+    fn lift_quantized<T: Sample>(
+        &self,
+        low: &mut Strided<&mut T>,
+        high: &mut [i64],
+        inverse: bool,
+        quantizer: &impl Quantizer,
+    ) -> Result<(), OverflowError> {
+        let first = self
+            .steps
+            .iter()
+            .position(|step| matches!(step, Step::Predict(_)));
+        let len = low.len() + high.len();
+        let saturate = |x: i128| x.clamp(i64::MIN as i128, i64::MAX as i128) as i64;
+
+        self.run(len, inverse, |lift| {
+            let i = lift.target;
+            // During the first prediction, the lookahead only reads samples not quantized yet
+            let quantized = first.is_some_and(|first| lift.step > first);
+            let read = |j: usize| {
+                if quantized {
+                    quantizer.dequantize(high[j])
+                } else {
+                    high[j]
+                }
+            };
+
+            if !lift.predict {
+                let d = lift.filter(read, |j| low[j].to_i64());
+                low[i] = self
+                    .overflow
+                    .add(low[i], lift.sign * d)
+                    .ok_or(OverflowError)?;
+            } else if Some(lift.step) == first {
+                let d = lift.filter(|j| low[j].to_i64(), read);
+                high[i] = if inverse {
+                    saturate(quantizer.dequantize(high[i]) as i128 + lift.sign * d)
+                } else {
+                    quantizer.quantize(saturate(high[i] as i128 + lift.sign * d))
+                };
+            } else {
+                let d = lift.filter(|j| low[j].to_i64(), read);
+                let d = if quantized {
+                    quantizer.quantize(saturate(d))
+                } else {
+                    saturate(d)
+                };
+                high[i] = saturate(high[i] as i128 + lift.sign * d as i128);
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Kernel described by a [`Lifting`] scheme, whose steps can be run one by one.
+pub trait LiftingKernel {
+    fn lifting(&self) -> Lifting<'_>;
+}
+
+impl LiftingKernel for Lifting<'_> {
+    fn lifting(&self) -> Lifting<'_> {
+        *self
+    }
+}
+
+macro_rules! lifting_impl {
+    ($t:ty) => {
+        impl Dwt1<$t> for Lifting<'_> {
+            fn dwt1(&self, sig: Strided<&mut $t>, tmp: Strided<&mut $t>) {
+                // Never fails, see `Lifting::infallible`
+                _ = self.infallible().forward(sig, tmp);
+            }
+            fn try_dwt1(
+                &self,
+                sig: Strided<&mut $t>,
+                tmp: Strided<&mut $t>,
+            ) -> Result<(), OverflowError> {
+                self.forward(sig, tmp)
+            }
+
+            fn idwt1(&self, sig: Strided<&mut $t>, tmp: Strided<&mut $t>) {
+                _ = self.infallible().inverse(sig, tmp);
+            }
+            fn try_idwt1(
+                &self,
+                sig: Strided<&mut $t>,
+                tmp: Strided<&mut $t>,
+            ) -> Result<(), OverflowError> {
+                self.inverse(sig, tmp)
+            }
+        }
+    };
+}
+for_each_sample!(lifting_impl);
+
+/// Implements [`Dwt1`] for a kernel type by forwarding to its `LIFTING` description.
+macro_rules! lifting_kernel_impl {
+    ($t:ty, $kernel:ty) => {
+        impl crate::dwt::Dwt1<$t> for $kernel {
+            fn dwt1(
+                &self,
+                sig: crate::memory::Strided<&mut $t>,
+                tmp: crate::memory::Strided<&mut $t>,
+            ) {
+                Self::LIFTING.dwt1(sig, tmp);
+            }
+
+            fn idwt1(
+                &self,
+                sig: crate::memory::Strided<&mut $t>,
+                tmp: crate::memory::Strided<&mut $t>,
+            ) {
+                Self::LIFTING.idwt1(sig, tmp);
+            }
+        }
+    };
+    ($kernel:ty) => {
+        impl $kernel {
+            /// The kernel with another boundary extension than [`Boundary::RepeatLow`](crate::dwt::Boundary::RepeatLow).
+            pub const fn with_boundary(
+                self,
+                boundary: crate::dwt::Boundary,
+            ) -> crate::dwt::lifting::Lifting<'static> {
+                Self::LIFTING.with_boundary(boundary)
+            }
+        }
+
+        impl crate::dwt::lifting::LiftingKernel for $kernel {
+            fn lifting(&self) -> crate::dwt::lifting::Lifting<'_> {
+                Self::LIFTING
+            }
+        }
+        crate::numeric::for_each_sample!(lifting_kernel_impl, $kernel);
+    };
+}
+pub(crate) use lifting_kernel_impl;
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        dwt::{daub::Daub53, Dwt1},
+        numeric::{Overflow, OverflowError},
+        testing::signal,
+    };
+
+    use super::{Boundary, Filter, Lifting, Rounding, Step};
+
+    /// Hand-written 5/3 kernel that [`Daub53`] used before being expressed as a [`Lifting`].
+    fn daub53_reference(sig: &[i16]) -> Vec<i16> {
+        let half = sig.len() / 2;
+        let mut out = vec![0; sig.len()];
+        let mut h0 = 0i16;
+        for i in 0..half {
+            let a = sig[2 * i];
+            let b = sig[2 * i + 1];
+            let c = sig.get(2 * i + 2).copied().unwrap_or(a);
+            let h = b.wrapping_sub((a + c) / 2);
+            out[half + i] = h;
+            out[i] = a.wrapping_add((h0 + h) / 4);
+            h0 = h;
+        }
+        out
+    }
+
+    #[test]
+    fn daub53_bit_identical() {
+        for len in [2, 4, 6, 16, 64] {
+            for seed in 0..8 {
+                let input = signal(len, seed, |x| (x % 512) as i16 - 256);
+                let mut sig = input.clone();
+                let mut tmp = vec![0; len];
+
+                Daub53.dwt1_slice(&mut sig, &mut tmp);
+                assert_eq!(sig, daub53_reference(&input));
+
+                Daub53.idwt1_slice(&mut sig, &mut tmp);
+                assert_eq!(sig, input);
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        const STEPS: &[Step<'static>] = &[
+            Step::Predict(Filter::new(-1, &[-1, 9, 9, -1], 8, 4)),
+            Step::Update(Filter::new(-1, &[1, 1], 2, 2)),
+            Step::Predict(Filter::new(0, &[3], 1, 1).with_lookahead(&[2, -1])),
+        ];
+        for (rounding, boundary) in Boundary::ALL
+            .into_iter()
+            .flat_map(|b| [(Rounding::Truncate, b), (Rounding::Floor, b)])
+        {
+            let lifting = Lifting::new(STEPS, rounding).with_boundary(boundary);
+            for len in [2, 4, 8, 32] {
+                for seed in 0..8 {
+                    let input = signal(len, seed, |x| x as i8);
+                    let mut sig = input.clone();
+                    let mut tmp = vec![0; len];
+                    lifting.dwt1_slice(&mut sig, &mut tmp);
+                    lifting.idwt1_slice(&mut sig, &mut tmp);
+                    assert_eq!(sig, input);
+
+                    let input = signal(len, seed, |x| x as i16);
+                    let mut sig = input.clone();
+                    let mut tmp = vec![0; len];
+                    lifting.dwt1_slice(&mut sig, &mut tmp);
+                    lifting.idwt1_slice(&mut sig, &mut tmp);
+                    assert_eq!(sig, input);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn overflow_policies() {
+        // Alternating extremes give high band coefficients out of the i8 range
+        let input = [-128i8, 127, -128, 127, -100, 90, 3, -7];
+        let mut tmp = [0; 8];
+
+        // Modular arithmetic is still perfectly reversible
+        let wrapping = Daub53::LIFTING.with_overflow(Overflow::Wrapping);
+        let mut sig = input;
+        wrapping.dwt1_slice(&mut sig, &mut tmp);
+        assert_eq!(sig[4], -1); // 127 - (-128) wraps around
+        wrapping.idwt1_slice(&mut sig, &mut tmp);
+        assert_eq!(sig, input);
+
+        // Clamped coefficients keep their sign, only the samples around them are altered
+        let saturating = Daub53::LIFTING.with_overflow(Overflow::Saturating);
+        let mut sig = input;
+        saturating.dwt1_slice(&mut sig, &mut tmp);
+        assert_eq!(sig[4], 127);
+        saturating.idwt1_slice(&mut sig, &mut tmp);
+        assert_ne!(sig, input);
+        assert_eq!((sig[0], sig[2], sig[4]), (input[0], input[2], input[4]));
+        assert_eq!(sig[6..], input[6..]);
+
+        // Checked arithmetic reports the overflow
+        let widening = Daub53::LIFTING.with_overflow(Overflow::Widening);
+        let mut sig = input;
+        assert_eq!(
+            widening.try_dwt1(sig.as_mut_slice().into(), tmp.as_mut_slice().into()),
+            Err(OverflowError)
+        );
+
+        // The infallible transform wraps around instead
+        let mut sig = input;
+        widening.dwt1_slice(&mut sig, &mut tmp);
+        assert_eq!(sig[4], -1);
+        widening.idwt1_slice(&mut sig, &mut tmp);
+        assert_eq!(sig, input);
+
+        // Every policy is exact when nothing overflows
+        let small = [10i8, -20, 35, 4, -60, 60, 0, 1];
+        for overflow in [Overflow::Wrapping, Overflow::Saturating, Overflow::Widening] {
+            let lifting = Daub53::LIFTING.with_overflow(overflow);
+            let mut sig = small;
+            lifting
+                .try_dwt1(sig.as_mut_slice().into(), tmp.as_mut_slice().into())
+                .unwrap();
+            lifting
+                .try_idwt1(sig.as_mut_slice().into(), tmp.as_mut_slice().into())
+                .unwrap();
+            assert_eq!(sig, small, "{overflow:?}");
+        }
+    }
+
+    #[test]
+    fn i64_extremes() {
+        // The filters of i64 samples overflow i64 before the rounding shift
+        let input = [i64::MIN, i64::MAX, i64::MIN, i64::MAX, i64::MAX, -1, 0, 1];
+        let mut tmp = [0; 8];
+
+        let mut sig = input;
+        Daub53.dwt1_slice(&mut sig, &mut tmp);
+        assert_eq!(sig[4], -1); // MAX - (MIN + MIN) / 2 wraps around
+        Daub53.idwt1_slice(&mut sig, &mut tmp);
+        assert_eq!(sig, input);
+
+        let widening = Daub53::LIFTING.with_overflow(Overflow::Widening);
+        let mut sig = input;
+        assert_eq!(
+            widening.try_dwt1(sig.as_mut_slice().into(), tmp.as_mut_slice().into()),
+            Err(OverflowError)
+        );
+    }
+}
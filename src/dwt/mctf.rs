@@ -0,0 +1,445 @@
+//! Motion compensated temporal filtering.
+//!
+//! The lifting steps of a kernel are applied across frames, like [`Dwt3`](super::Dwt3),
+//! but every sample is read along the motion trajectory of its block instead of at the same position.
+//! Predict steps read the low frames at `p + v`, update steps read the high frames at `p - v`.
+//! As every step only reads the other band, reconstruction stays perfect whatever the motion.
+
+use std::{collections::BTreeSet, convert::Infallible};
+
+use crate::{
+    memory::Strided,
+    numeric::{OverflowError, Sample},
+};
+
+use super::lifting::Lifting;
+
+type Frames<'a, T> = Strided<Strided<Strided<&'a mut T>>>;
+
+/// Displacement from a pixel of the current frame to its match in the reference frame.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MotionVector {
+    pub dx: i16,
+    pub dy: i16,
+}
+
+/// Motion of a frame relative to a reference frame, one vector per block in raster order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MotionField {
+    pub block_size: usize,
+    pub blocks_x: usize,
+    pub blocks_y: usize,
+    pub vectors: Vec<MotionVector>,
+}
+
+/// Reads a pixel, clamping the position inside the frame, or 0 if the frame is empty.
+fn pixel<T: Sample>(frame: &Strided<Strided<&T>>, x: isize, y: isize) -> i64 {
+    let height = frame.len() as isize;
+    let width = frame.first().map_or(0, |row| row.len()) as isize;
+    if width == 0 || height == 0 {
+        return 0;
+    }
+    let row = frame.get(y.clamp(0, height - 1) as usize);
+    row[x.clamp(0, width - 1) as usize].to_i64()
+}
+
+impl MotionField {
+    /// # Panics
+    ///
+    /// If `block_size` is 0.
+    pub fn zero(width: usize, height: usize, block_size: usize) -> Self {
+        assert!(block_size > 0, "Motion blocks cannot be empty");
+        let blocks_x = width.div_ceil(block_size);
+        let blocks_y = height.div_ceil(block_size);
+        Self {
+            block_size,
+            blocks_x,
+            blocks_y,
+            vectors: vec![MotionVector::default(); blocks_x * blocks_y],
+        }
+    }
+
+    /// Vector of the block containing the pixel `(x, y)`.
+    pub fn vector(&self, x: usize, y: usize) -> MotionVector {
+        let block = |x: usize, blocks: usize| {
+            let b = x.checked_div(self.block_size).unwrap_or_default();
+            b.min(blocks.saturating_sub(1))
+        };
+        let bx = block(x, self.blocks_x);
+        let by = block(y, self.blocks_y);
+        self.vectors
+            .get(by * self.blocks_x + bx)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Full search block matching, minimising the sum of absolute differences.
+    ///
+    /// Ties are broken in favour of the shortest vector, so static content gets zero vectors.
+    /// The search range is clamped to `i16::MAX`, the largest component of a [`MotionVector`].
+    pub fn estimate<T: Sample>(
+        current: &Strided<Strided<&T>>,
+        reference: &Strided<Strided<&T>>,
+        block_size: usize,
+        range: usize,
+    ) -> Self {
+        let height = current.len();
+        let width = current.first().map_or(0, |row| row.len());
+        let mut field = Self::zero(width, height, block_size);
+        let range = range.min(i16::MAX as usize) as isize;
+
+        for by in 0..field.blocks_y {
+            for bx in 0..field.blocks_x {
+                let ys = by * block_size..((by + 1) * block_size).min(height);
+                let xs = bx * block_size..((bx + 1) * block_size).min(width);
+                let mut best = (u64::MAX, 0, MotionVector::default());
+
+                for dy in -range..=range {
+                    for dx in -range..=range {
+                        let mut sad = 0;
+                        for y in ys.clone() {
+                            let row = current.get(y);
+                            for x in xs.clone() {
+                                let r = pixel(reference, x as isize + dx, y as isize + dy);
+                                sad += row[x].to_i64().abs_diff(r);
+                            }
+                        }
+                        let key = (sad, dx.unsigned_abs() + dy.unsigned_abs());
+                        if key < (best.0, best.1) {
+                            let v = MotionVector {
+                                dx: dx as i16,
+                                dy: dy as i16,
+                            };
+                            best = (key.0, key.1, v);
+                        }
+                    }
+                }
+                field.vectors[by * field.blocks_x + bx] = best.2;
+            }
+        }
+        field
+    }
+}
+
+/// Motion field used between a high band frame and a low band frame.
+///
+/// Frame indices are positions inside their band: high frame `i` is the input frame `2i + 1`,
+/// low frame `j` is the input frame `2j`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Motion {
+    pub high: usize,
+    pub low: usize,
+    pub field: MotionField,
+}
+
+/// Motion compensated lifting kernel.
+///
+/// Lookahead taps read the co-located pixel, without motion compensation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mctf<'a> {
+    pub lifting: Lifting<'a>,
+    pub block_size: usize,
+    pub search_range: usize,
+}
+
+fn copy_frames<T: Copy>(src: &Frames<'_, T>, dst: &mut Frames<'_, T>) {
+    for (src, dst) in src.iter().zip(dst.iter_mut()) {
+        for (src, dst) in src.into_iter().zip(dst) {
+            for (&src, dst) in src.into_iter().zip(dst) {
+                *dst = src;
+            }
+        }
+    }
+}
+
+impl<'a> Mctf<'a> {
+    pub const fn new(lifting: Lifting<'a>) -> Self {
+        Self {
+            lifting,
+            block_size: 16,
+            search_range: 8,
+        }
+    }
+
+    /// # Panics
+    ///
+    /// If `block_size` is 0.
+    pub const fn with_block_size(self, block_size: usize) -> Self {
+        assert!(block_size > 0, "Motion blocks cannot be empty");
+        Self { block_size, ..self }
+    }
+
+    pub const fn with_search_range(self, search_range: usize) -> Self {
+        Self {
+            search_range,
+            ..self
+        }
+    }
+
+    /// `(high, low)` frame pairs read by the lifting steps for a group of `len` frames.
+    pub fn references(&self, len: usize) -> Vec<(usize, usize)> {
+        let mut pairs = BTreeSet::new();
+        let Ok(()) = self.lifting.run::<Infallible>(len, false, |lift| {
+            for j in lift.sources().flatten() {
+                pairs.insert(if lift.predict {
+                    (lift.target, j)
+                } else {
+                    (j, lift.target)
+                });
+            }
+            Ok(())
+        });
+        pairs.into_iter().collect()
+    }
+
+    /// Estimates the motion needed to transform the frames.
+    pub fn estimate<T: Sample>(&self, vol: &Frames<'_, T>) -> Vec<Motion> {
+        self.references(vol.len())
+            .into_iter()
+            .map(|(high, low)| Motion {
+                high,
+                low,
+                field: MotionField::estimate(
+                    &vol.get(2 * high + 1),
+                    &vol.get(2 * low),
+                    self.block_size,
+                    self.search_range,
+                ),
+            })
+            .collect()
+    }
+
+    /// Estimates the motion then transforms the frames, returning the motion to send to the decoder.
+    pub fn dwt3<T: Sample>(&self, vol: Frames<'_, T>, tmp: Frames<'_, T>) -> Vec<Motion> {
+        let motion = self.estimate(&vol);
+        self.dwt3_with_motion(vol, tmp, &motion);
+        motion
+    }
+
+    pub fn dwt3_with_motion<T: Sample>(
+        &self,
+        mut vol: Frames<'_, T>,
+        mut tmp: Frames<'_, T>,
+        motion: &[Motion],
+    ) {
+        copy_frames(&vol, &mut tmp);
+        let [even, odd] = tmp.into_deinterleave_array();
+        let (mut low, mut high) = vol.split_at_mut(vol.len().div_ceil(2));
+        copy_frames(&even, &mut low);
+        copy_frames(&odd, &mut high);
+
+        // Never fails, see `Overflow::infallible`
+        _ = self.lift(&mut low, &mut high, false, motion);
+    }
+
+    pub fn idwt3<T: Sample>(&self, vol: Frames<'_, T>, mut tmp: Frames<'_, T>, motion: &[Motion]) {
+        copy_frames(&vol, &mut tmp);
+        let (mut low, mut high) = tmp.split_at_mut(tmp.len().div_ceil(2));
+
+        // Never fails, see `Overflow::infallible`
+        _ = self.lift(&mut low, &mut high, true, motion);
+
+        let [mut even, mut odd] = vol.into_deinterleave_array();
+        copy_frames(&low, &mut even);
+        copy_frames(&high, &mut odd);
+    }
+
+    fn lift<'s, T: Sample>(
+        &self,
+        low: &mut Frames<'s, T>,
+        high: &mut Frames<'s, T>,
+        inverse: bool,
+        motion: &[Motion],
+    ) -> Result<(), OverflowError> {
+        let overflow = self.lifting.overflow.infallible();
+        let len = low.len() + high.len();
+        let low_len = low.len();
+        // Field of every `(high, low)` pair, at `high * low.len() + low`
+        let mut fields = vec![None; high.len() * low_len];
+        for m in motion {
+            if m.high < high.len() && m.low < low_len {
+                fields[m.high * low_len + m.low] = Some(&m.field);
+            }
+        }
+
+        self.lifting.run(len, inverse, |lift| {
+            let i = lift.target;
+            let (target, source) = if lift.predict {
+                (&mut *high, &*low)
+            } else {
+                (&mut *low, &*high)
+            };
+            // Predict steps follow the motion from the high frame, update steps go back along it
+            let pair = |j: usize| if lift.predict { (i, j) } else { (j, i) };
+            let sign = if lift.predict { 1 } else { -1 };
+
+            // All the reads are done before writing, as lookahead taps read the target band
+            let mut deltas = Vec::new();
+            for (y, row) in target.get(i).into_iter().enumerate() {
+                for x in 0..row.len() {
+                    let d = lift.filter(
+                        |j| {
+                            let (high, low) = pair(j);
+                            let v = fields[high * low_len + low]
+                                .map_or_else(MotionVector::default, |field| field.vector(x, y));
+                            let dx = sign * v.dx as isize;
+                            let dy = sign * v.dy as isize;
+                            pixel(&source.get(j), x as isize + dx, y as isize + dy)
+                        },
+                        |j| target.get(j).get(y)[x].to_i64(),
+                    );
+                    deltas.push(lift.sign * d);
+                }
+            }
+
+            let mut deltas = deltas.into_iter();
+            for row in target.get_mut(i) {
+                for (cell, d) in row.into_iter().zip(&mut deltas) {
+                    *cell = overflow.add(*cell, d).ok_or(OverflowError)?;
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        dwt::{daub::Daub53, haar::Haar, reversible::SPlusP, Dwt3},
+        memory::Image,
+        numeric::Overflow,
+    };
+
+    use super::{Mctf, MotionField, MotionVector};
+
+    /// Frames of `width x height` stacked vertically, with a texture moving by `(3, -1)` per frame.
+    fn moving(width: usize, height: usize, frames: usize) -> Image<i16> {
+        Image::with_fn(width, height * frames, |x, y| {
+            let t = (y / height) as isize;
+            let x = x as isize - 3 * t;
+            let y = (y % height) as isize + t;
+            let h = (x * 73 + y * 151).wrapping_mul(x * 31 + y * 17 + 5);
+            (h.rem_euclid(201) - 100) as i16
+        })
+    }
+
+    #[test]
+    fn round_trip() {
+        for (lifting, frames) in [
+            (Haar::LIFTING, 4),
+            (Daub53::LIFTING, 5),
+            (SPlusP::LIFTING, 6),
+        ] {
+            let mctf = Mctf::new(lifting).with_block_size(8).with_search_range(4);
+            let input = moving(24, 16, frames);
+            let mut video = input.clone();
+            let mut tmp = input.clone();
+            let motion = mctf.dwt3(
+                video.view_mut().into_frames_mut(16),
+                tmp.view_mut().into_frames_mut(16),
+            );
+            assert!(!motion.is_empty());
+            assert_ne!(video, input);
+
+            mctf.idwt3(
+                video.view_mut().into_frames_mut(16),
+                tmp.view_mut().into_frames_mut(16),
+                &motion,
+            );
+            assert_eq!(video, input);
+        }
+    }
+
+    #[test]
+    fn zero_motion_is_plain_temporal() {
+        let input = moving(20, 12, 5);
+        let mut video = input.clone();
+        let mut expected = input.clone();
+        let mut tmp = input.clone();
+
+        Mctf::new(Daub53::LIFTING).dwt3_with_motion(
+            video.view_mut().into_frames_mut(12),
+            tmp.view_mut().into_frames_mut(12),
+            &[],
+        );
+        Daub53.dwt3(
+            expected.view_mut().into_frames_mut(12),
+            tmp.view_mut().into_frames_mut(12),
+        );
+        assert_eq!(video, expected);
+    }
+
+    #[test]
+    fn sparse_high_frames() {
+        let input = moving(48, 32, 2);
+        let energy = |video: &Image<i16>| {
+            video
+                .subview(0, 32, 48, 32)
+                .rows()
+                .flatten()
+                .map(|&x| x.unsigned_abs() as u64)
+                .sum::<u64>()
+        };
+
+        let mut plain = input.clone();
+        let mut tmp = input.clone();
+        Haar.dwt3(
+            plain.view_mut().into_frames_mut(32),
+            tmp.view_mut().into_frames_mut(32),
+        );
+
+        let mut compensated = input.clone();
+        let mctf = Mctf::new(Haar::LIFTING)
+            .with_block_size(8)
+            .with_search_range(4);
+        let motion = mctf.dwt3(
+            compensated.view_mut().into_frames_mut(32),
+            tmp.view_mut().into_frames_mut(32),
+        );
+
+        // Inner blocks find the exact motion
+        assert_eq!(motion.len(), 1);
+        let v = motion[0].field.vector(20, 20);
+        assert_eq!((v.dx, v.dy), (-3, 1));
+        assert!(energy(&compensated) * 10 < energy(&plain));
+    }
+
+    #[test]
+    fn empty_reference() {
+        let mut current = moving(8, 4, 1);
+        let mut reference = Image::<i16>::new(0, 4);
+        let current = current.view_mut().into_frames_mut(4);
+        let reference = reference.view_mut().into_frames_mut(4);
+
+        let field = MotionField::estimate(&current.get(0), &reference.get(0), 4, 2);
+        assert_eq!(field.vectors, [MotionVector::default(); 2]);
+    }
+
+    #[test]
+    fn overflow_policies() {
+        // Frame 1 minus frame 0 does not fit in `i8`
+        let input = Image::<i8>::with_fn(4, 8, |_, y| if y < 4 { -128 } else { 127 });
+        for (overflow, high) in [(Overflow::Wrapping, -1), (Overflow::Saturating, 127)] {
+            let mctf = Mctf::new(Haar::LIFTING.with_overflow(overflow)).with_block_size(2);
+            let mut video = input.clone();
+            let mut tmp = input.clone();
+            mctf.dwt3_with_motion(
+                video.view_mut().into_frames_mut(4),
+                tmp.view_mut().into_frames_mut(4),
+                &[],
+            );
+            assert!(video
+                .subview(0, 4, 4, 4)
+                .rows()
+                .flatten()
+                .all(|&x| x == high));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn empty_blocks() {
+        _ = Mctf::new(Haar::LIFTING).with_block_size(0);
+    }
+}
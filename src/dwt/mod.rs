@@ -1,8 +1,23 @@
-use crate::memory::{ImageViewMut, Strided};
+use std::convert::Infallible;
 
+use crate::{
+    memory::{strided, ImageViewMut, Strided},
+    numeric::OverflowError,
+};
+
+pub use boundary::Boundary;
+pub use layout::{Orientation, Subband, SubbandLayout};
+
+mod boundary;
 pub mod daub;
 pub mod haar;
+mod layout;
+pub mod lifting;
+pub mod mctf;
+pub mod packet;
 pub mod predict;
+pub mod quantized;
+pub mod reversible;
 
 pub trait Dwt0<T> {
     fn dwt0(&self, a: T, b: T) -> (T, T);
@@ -13,67 +28,465 @@ pub trait Dwt1<T> {
     fn dwt1_slice(&self, sig: &mut [T], tmp: &mut [T]) {
         self.dwt1(sig.into(), tmp.into());
     }
+    /// Like [`Dwt1::dwt1`], but reports overflows of kernels using
+    /// [`Overflow::Widening`](crate::numeric::Overflow::Widening).
+    ///
+    /// [`Dwt1::dwt1`] cannot fail, so these kernels wrap around there instead.
+    /// The signal content is unspecified after an error.
+    fn try_dwt1(&self, sig: Strided<&mut T>, tmp: Strided<&mut T>) -> Result<(), OverflowError> {
+        self.dwt1(sig, tmp);
+        Ok(())
+    }
 
     fn idwt1(&self, sig: Strided<&mut T>, tmp: Strided<&mut T>);
     fn idwt1_slice(&self, sig: &mut [T], tmp: &mut [T]) {
         self.idwt1(sig.into(), tmp.into());
     }
+    fn try_idwt1(&self, sig: Strided<&mut T>, tmp: Strided<&mut T>) -> Result<(), OverflowError> {
+        self.idwt1(sig, tmp);
+        Ok(())
+    }
+
+    /// Kernel transforming the columns of the high band of the rows in 2D, the kernel itself by default.
+    ///
+    /// Kernels leaving quantization indices in their high band transform these columns losslessly,
+    /// so that no coefficient is quantized twice.
+    fn high_band_kernel(&self) -> &dyn Dwt1<T>
+    where
+        Self: Sized,
+    {
+        self
+    }
 }
 pub trait Dwt2<T> {
     fn dwt2(&self, img: ImageViewMut<'_, T>, tmp: ImageViewMut<'_, T>);
     fn idwt2(&self, img: ImageViewMut<'_, T>, tmp: ImageViewMut<'_, T>);
-}
+    /// Like [`Dwt2::dwt2`], but reports overflows, see [`Dwt1::try_dwt1`].
+    fn try_dwt2(
+        &self,
+        img: ImageViewMut<'_, T>,
+        tmp: ImageViewMut<'_, T>,
+    ) -> Result<(), OverflowError> {
+        self.dwt2(img, tmp);
+        Ok(())
+    }
+    fn try_idwt2(
+        &self,
+        img: ImageViewMut<'_, T>,
+        tmp: ImageViewMut<'_, T>,
+    ) -> Result<(), OverflowError> {
+        self.idwt2(img, tmp);
+        Ok(())
+    }
 
-impl<T: Clone + std::fmt::Debug, A: Dwt0<T>> Dwt1<T> for A {
-    fn dwt1(&self, mut sig: Strided<&mut T>, mut tmp: Strided<&mut T>) {
-        for (src, dst) in sig.iter().zip(tmp.iter_mut()) {
-            *dst = src.clone();
+    /// Mallat decomposition: transforms the image, then its `LL` band `levels - 1` more times.
+    fn decompose(
+        &self,
+        mut img: ImageViewMut<'_, T>,
+        mut tmp: ImageViewMut<'_, T>,
+        levels: usize,
+    ) -> SubbandLayout {
+        let layout = SubbandLayout::new(img.width(), img.height(), levels);
+        for level in 0..levels {
+            let (w, h) = layout.low_size(level);
+            self.dwt2(img.subview_mut(0, 0, w, h), tmp.subview_mut(0, 0, w, h));
         }
-        let [src1, src2] = tmp.into_deinterleave_array();
-        let (dst1, dst2) = sig.split_at_mut(sig.len() / 2);
+        layout
+    }
 
-        for (a, (b, (l, h))) in src1
-            .into_iter()
-            .zip(src2.into_iter().zip(dst1.into_iter().zip(dst2)))
-        {
-            (*l, *h) = self.dwt0(a.clone(), b.clone());
+    /// Inverse of [`Dwt2::decompose`].
+    fn reconstruct(
+        &self,
+        mut img: ImageViewMut<'_, T>,
+        mut tmp: ImageViewMut<'_, T>,
+        levels: usize,
+    ) {
+        let layout = SubbandLayout::new(img.width(), img.height(), levels);
+        for level in (0..levels).rev() {
+            let (w, h) = layout.low_size(level);
+            self.idwt2(img.subview_mut(0, 0, w, h), tmp.subview_mut(0, 0, w, h));
         }
     }
+}
+
+/// Temporal transform of a group of frames, indexed by `[frame][row][col]`.
+///
+/// Every pixel is transformed along the time axis, the low frames coming first.
+/// Frames can be transformed spatially with [`Dwt2`] afterwards for a full 3D decomposition.
+pub trait Dwt3<T> {
+    fn dwt3(&self, vol: Strided<Strided<Strided<&mut T>>>, tmp: Strided<Strided<Strided<&mut T>>>);
+    fn idwt3(&self, vol: Strided<Strided<Strided<&mut T>>>, tmp: Strided<Strided<Strided<&mut T>>>);
 
-    fn idwt1(&self, sig: Strided<&mut T>, mut tmp: Strided<&mut T>) {
-        for (src, dst) in sig.iter().zip(tmp.iter_mut()) {
-            *dst = src.clone();
+    /// Dyadic temporal decomposition: transforms the frames, then the low frames `levels - 1` more times.
+    fn decompose3(
+        &self,
+        mut vol: Strided<Strided<Strided<&mut T>>>,
+        mut tmp: Strided<Strided<Strided<&mut T>>>,
+        levels: usize,
+    ) {
+        let len = vol.len();
+        for level in 0..levels {
+            let n = len.div_ceil(1 << level);
+            self.dwt3(
+                vol.partial_mut(0, n, strided::STEP_1),
+                tmp.partial_mut(0, n, strided::STEP_1),
+            );
         }
-        let (src1, src2) = tmp.split_at(tmp.len() / 2);
-        let [dst1, dst2] = sig.into_deinterleave_array();
+    }
 
-        for (l, (h, (a, b))) in src1
-            .into_iter()
-            .zip(src2.into_iter().zip(dst1.into_iter().zip(dst2)))
-        {
-            (*a, *b) = self.idwt0(l.clone(), h.clone());
+    /// Inverse of [`Dwt3::decompose3`].
+    fn reconstruct3(
+        &self,
+        mut vol: Strided<Strided<Strided<&mut T>>>,
+        mut tmp: Strided<Strided<Strided<&mut T>>>,
+        levels: usize,
+    ) {
+        let len = vol.len();
+        for level in (0..levels).rev() {
+            let n = len.div_ceil(1 << level);
+            self.idwt3(
+                vol.partial_mut(0, n, strided::STEP_1),
+                tmp.partial_mut(0, n, strided::STEP_1),
+            );
         }
     }
 }
 
+/// Transforms every pair of samples of `sig` with `f`, storing the low band first.
+fn dwt1_pairs<T: Clone, E>(
+    mut sig: Strided<&mut T>,
+    mut tmp: Strided<&mut T>,
+    mut f: impl FnMut(T, T) -> Result<(T, T), E>,
+) -> Result<(), E> {
+    for (src, dst) in sig.iter().zip(tmp.iter_mut()) {
+        *dst = src.clone();
+    }
+    let [src1, src2] = tmp.into_deinterleave_array();
+    let (mut dst1, dst2) = sig.split_at_mut(sig.len().div_ceil(2));
+
+    // Without a pair, the last sample of an odd length signal is kept in the low band
+    if let (Some(l), Some(a)) = (dst1.last_mut(), src1.last()) {
+        *l = a.clone();
+    }
+
+    for (a, (b, (l, h))) in src1
+        .into_iter()
+        .zip(src2.into_iter().zip(dst1.into_iter().zip(dst2)))
+    {
+        (*l, *h) = f(a.clone(), b.clone())?;
+    }
+    Ok(())
+}
+
+/// Inverse of [`dwt1_pairs`], `f` getting the low and high coefficients of every pair.
+fn idwt1_pairs<T: Clone, E>(
+    sig: Strided<&mut T>,
+    mut tmp: Strided<&mut T>,
+    mut f: impl FnMut(T, T) -> Result<(T, T), E>,
+) -> Result<(), E> {
+    for (src, dst) in sig.iter().zip(tmp.iter_mut()) {
+        *dst = src.clone();
+    }
+    let (src1, src2) = tmp.split_at(tmp.len().div_ceil(2));
+    let [mut dst1, dst2] = sig.into_deinterleave_array();
+
+    if let (Some(a), Some(l)) = (dst1.last_mut(), src1.last()) {
+        *a = l.clone();
+    }
+
+    for (l, (h, (a, b))) in src1
+        .into_iter()
+        .zip(src2.into_iter().zip(dst1.into_iter().zip(dst2)))
+    {
+        (*a, *b) = f(l.clone(), h.clone())?;
+    }
+    Ok(())
+}
+
+impl<T: Clone + std::fmt::Debug, A: Dwt0<T>> Dwt1<T> for A {
+    fn dwt1(&self, sig: Strided<&mut T>, tmp: Strided<&mut T>) {
+        let Ok(()) = dwt1_pairs::<_, Infallible>(sig, tmp, |a, b| Ok(self.dwt0(a, b)));
+    }
+
+    fn idwt1(&self, sig: Strided<&mut T>, tmp: Strided<&mut T>) {
+        let Ok(()) = idwt1_pairs::<_, Infallible>(sig, tmp, |l, h| Ok(self.idwt0(l, h)));
+    }
+}
+
 impl<T, A: Dwt1<T>> Dwt2<T> for A {
     fn dwt2(&self, mut img: ImageViewMut<'_, T>, mut tmp: ImageViewMut<'_, T>) {
         for (row_img, row_tmp) in img.rows_mut().zip(tmp.rows_mut()) {
             self.dwt1_slice(row_img, row_tmp);
         }
 
-        for (col_img, col_tmp) in img.cols_mut().zip(tmp.cols_mut()) {
-            self.dwt1(col_img, col_tmp);
+        let half = img.width().div_ceil(2);
+        for (x, (col_img, col_tmp)) in img.cols_mut().zip(tmp.cols_mut()).enumerate() {
+            if x < half {
+                self.dwt1(col_img, col_tmp);
+            } else {
+                self.high_band_kernel().dwt1(col_img, col_tmp);
+            }
         }
     }
 
     fn idwt2(&self, mut img: ImageViewMut<'_, T>, mut tmp: ImageViewMut<'_, T>) {
-        for (col_img, col_tmp) in img.cols_mut().zip(tmp.cols_mut()) {
-            self.idwt1(col_img, col_tmp);
+        let half = img.width().div_ceil(2);
+        for (x, (col_img, col_tmp)) in img.cols_mut().zip(tmp.cols_mut()).enumerate() {
+            if x < half {
+                self.idwt1(col_img, col_tmp);
+            } else {
+                self.high_band_kernel().idwt1(col_img, col_tmp);
+            }
         }
 
         for (row_img, row_tmp) in img.rows_mut().zip(tmp.rows_mut()) {
             self.idwt1_slice(row_img, row_tmp);
         }
     }
+
+    fn try_dwt2(
+        &self,
+        mut img: ImageViewMut<'_, T>,
+        mut tmp: ImageViewMut<'_, T>,
+    ) -> Result<(), OverflowError> {
+        for (row_img, row_tmp) in img.rows_mut().zip(tmp.rows_mut()) {
+            self.try_dwt1(row_img.into(), row_tmp.into())?;
+        }
+
+        let half = img.width().div_ceil(2);
+        for (x, (col_img, col_tmp)) in img.cols_mut().zip(tmp.cols_mut()).enumerate() {
+            if x < half {
+                self.try_dwt1(col_img, col_tmp)?;
+            } else {
+                self.high_band_kernel().try_dwt1(col_img, col_tmp)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn try_idwt2(
+        &self,
+        mut img: ImageViewMut<'_, T>,
+        mut tmp: ImageViewMut<'_, T>,
+    ) -> Result<(), OverflowError> {
+        let half = img.width().div_ceil(2);
+        for (x, (col_img, col_tmp)) in img.cols_mut().zip(tmp.cols_mut()).enumerate() {
+            if x < half {
+                self.try_idwt1(col_img, col_tmp)?;
+            } else {
+                self.high_band_kernel().try_idwt1(col_img, col_tmp)?;
+            }
+        }
+
+        for (row_img, row_tmp) in img.rows_mut().zip(tmp.rows_mut()) {
+            self.try_idwt1(row_img.into(), row_tmp.into())?;
+        }
+        Ok(())
+    }
+}
+
+impl<T, A: Dwt1<T>> Dwt3<T> for A {
+    fn dwt3(&self, vol: Strided<Strided<Strided<&mut T>>>, tmp: Strided<Strided<Strided<&mut T>>>) {
+        // [frame][row][col] -> [row][col][frame]
+        let vol = vol.into_transpose02().into_transpose01();
+        let tmp = tmp.into_transpose02().into_transpose01();
+        for (row_vol, row_tmp) in vol.into_iter().zip(tmp) {
+            for (pix_vol, pix_tmp) in row_vol.into_iter().zip(row_tmp) {
+                self.dwt1(pix_vol, pix_tmp);
+            }
+        }
+    }
+
+    fn idwt3(
+        &self,
+        vol: Strided<Strided<Strided<&mut T>>>,
+        tmp: Strided<Strided<Strided<&mut T>>>,
+    ) {
+        let vol = vol.into_transpose02().into_transpose01();
+        let tmp = tmp.into_transpose02().into_transpose01();
+        for (row_vol, row_tmp) in vol.into_iter().zip(tmp) {
+            for (pix_vol, pix_tmp) in row_vol.into_iter().zip(row_tmp) {
+                self.idwt1(pix_vol, pix_tmp);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::memory::Image;
+
+    use super::{
+        daub::{Daub53, Daub97, LossyDaub53},
+        haar::{Haar, LossyHaar},
+        predict::Predict,
+        reversible::{SPlusP, W137T, W210, W26, W511A, W511C, W97M},
+        Boundary, Dwt1, Dwt2, Dwt3,
+    };
+
+    #[test]
+    fn odd_length_bands() {
+        let mut sig = [1i16, 3, 5, 7, 9];
+        let mut tmp = [0; 5];
+        Haar.dwt1_slice(&mut sig, &mut tmp);
+        assert_eq!(sig, [2, 6, 9, 2, 2]);
+        Haar.idwt1_slice(&mut sig, &mut tmp);
+        assert_eq!(sig, [1, 3, 5, 7, 9]);
+
+        // A constant signal only has energy in its low band
+        let mut sig = [10i16; 7];
+        let mut tmp = [0; 7];
+        Daub53.dwt1_slice(&mut sig, &mut tmp);
+        assert_eq!(sig, [10, 10, 10, 10, 0, 0, 0]);
+    }
+
+    fn round_trip_1d<A: Dwt1<i16>>(kernel: A) {
+        for len in [1, 3, 5, 7, 9, 15, 135] {
+            let input = (0..len)
+                .map(|i| ((i * 37 + 11) % 97) as i16 - 48)
+                .collect::<Vec<_>>();
+            let mut sig = input.clone();
+            let mut tmp = vec![0; len];
+            kernel.dwt1_slice(&mut sig, &mut tmp);
+            kernel.idwt1_slice(&mut sig, &mut tmp);
+            assert_eq!(sig, input, "length {len}");
+        }
+    }
+
+    #[test]
+    fn odd_length_round_trip() {
+        round_trip_1d(Haar);
+        round_trip_1d(Daub53);
+        round_trip_1d(Daub97);
+        round_trip_1d(SPlusP);
+        round_trip_1d(Predict(Daub53));
+    }
+
+    #[test]
+    fn boundary_round_trip() {
+        for boundary in Boundary::ALL {
+            round_trip_1d(Haar.with_boundary(boundary));
+            round_trip_1d(Daub53.with_boundary(boundary));
+            round_trip_1d(Daub97.with_boundary(boundary));
+            round_trip_1d(W26.with_boundary(boundary));
+            round_trip_1d(W511C.with_boundary(boundary));
+            round_trip_1d(W511A.with_boundary(boundary));
+            round_trip_1d(W97M.with_boundary(boundary));
+            round_trip_1d(W137T.with_boundary(boundary));
+            round_trip_1d(W210.with_boundary(boundary));
+            round_trip_1d(SPlusP.with_boundary(boundary));
+            round_trip_1d(Predict(Daub97).with_boundary(boundary));
+        }
+    }
+
+    #[test]
+    fn i32_round_trip() {
+        // 16-bit samples, beyond the range of the i16 kernels
+        let input = Image::with_fn(23, 17, |x, y| {
+            ((x * 7919 + y * 104729) % 65536) as i32 - 32768
+        });
+        fn check<A: Dwt2<i32>>(kernel: A, input: &Image<i32>) {
+            let mut image = input.clone();
+            let mut tmp = input.clone();
+            kernel.decompose(image.view_mut(), tmp.view_mut(), 3);
+            kernel.reconstruct(image.view_mut(), tmp.view_mut(), 3);
+            assert_eq!(&image, input);
+        }
+        check(Haar, &input);
+        check(LossyHaar, &input);
+        check(Daub53, &input);
+        check(Daub97, &input);
+        check(SPlusP, &input);
+        check(Predict(Daub53), &input);
+
+        // The lossy kernel only loses the high band precision
+        let mut image = input.clone();
+        let mut tmp = input.clone();
+        LossyDaub53::default().dwt2(image.view_mut(), tmp.view_mut());
+        LossyDaub53::default().idwt2(image.view_mut(), tmp.view_mut());
+        let error = image
+            .rows()
+            .zip(input.rows())
+            .flat_map(|(a, b)| a.iter().zip(b).map(|(a, b)| a.abs_diff(*b)))
+            .max();
+        assert!(error.unwrap() < 2000);
+    }
+
+    #[test]
+    fn any_sample_type() {
+        // Kernels are generic, so unsigned samples only need a `Sample` impl
+        let input = Image::with_fn(19, 11, |x, y| ((x * 4099 + y * 257) % 65536) as u16);
+        fn check<A: Dwt2<u16>>(kernel: A, input: &Image<u16>) {
+            let mut image = input.clone();
+            let mut tmp = input.clone();
+            kernel.decompose(image.view_mut(), tmp.view_mut(), 2);
+            assert_ne!(&image, input);
+            kernel.reconstruct(image.view_mut(), tmp.view_mut(), 2);
+            assert_eq!(&image, input);
+        }
+        check(Haar, &input);
+        check(LossyHaar, &input);
+        check(Daub53, &input);
+        check(SPlusP, &input);
+        check(Predict(Daub97), &input);
+    }
+
+    #[test]
+    fn odd_size_multilevel() {
+        // 1080 lines give 540, 270, 135 then 68 lines at the 4th level
+        let input = Image::with_fn(45, 1080, |x, y| ((x * 13 + y * 7) % 251) as i16 - 125);
+        let mut image = input.clone();
+        let mut tmp = input.clone();
+
+        Daub97.decompose(image.view_mut(), tmp.view_mut(), 5);
+        Daub97.reconstruct(image.view_mut(), tmp.view_mut(), 5);
+
+        assert_eq!(image, input);
+    }
+
+    #[test]
+    fn temporal_round_trip() {
+        // 5 frames of 7x6 stacked vertically
+        let input = Image::with_fn(7, 30, |x, y| ((x * 13 + y * 7) % 251) as i16 - 125);
+        for levels in [1, 3] {
+            let mut video = input.clone();
+            let mut tmp = input.clone();
+            Daub53.decompose3(
+                video.view_mut().into_frames_mut(6),
+                tmp.view_mut().into_frames_mut(6),
+                levels,
+            );
+            assert_ne!(video, input);
+            Daub53.reconstruct3(
+                video.view_mut().into_frames_mut(6),
+                tmp.view_mut().into_frames_mut(6),
+                levels,
+            );
+            assert_eq!(video, input);
+        }
+    }
+
+    #[test]
+    fn temporal_static_video() {
+        // Identical frames only have energy in the low frames
+        let mut video = Image::with_fn(8, 16, |x, y| ((x * 5 + (y % 4) * 3) % 17) as i16);
+        let mut tmp = video.clone();
+        let frame = video
+            .subview(0, 0, 8, 4)
+            .rows()
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        Haar.dwt3(
+            video.view_mut().into_frames_mut(4),
+            tmp.view_mut().into_frames_mut(4),
+        );
+
+        for f in 0..2 {
+            let low = video.subview(0, 4 * f, 8, 4);
+            assert!(low.rows().flatten().eq(frame.iter()));
+            let high = video.subview(0, 4 * (f + 2), 8, 4);
+            assert!(high.rows().flatten().all(|&x| x == 0));
+        }
+    }
 }
@@ -0,0 +1,352 @@
+use std::io;
+
+use crate::memory::{ImageView, ImageViewMut};
+
+use super::Dwt2;
+
+/// Splits deeper than this are rejected when reading a tree.
+pub const MAX_DEPTH: usize = 32;
+
+/// Additive cost of a set of coefficients, used to choose between a band and its split.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Cost {
+    /// `sum(|x|)`
+    L1,
+    /// Entropy estimate `sum(log2(1 + |x|))`, roughly the number of bits needed to code the coefficients
+    ///
+    /// Unlike the Coifman-Wickerhauser entropy, it does not assume an orthonormal kernel.
+    #[default]
+    Entropy,
+}
+
+impl Cost {
+    pub fn cost<T: Copy + Into<f64>>(self, band: ImageView<'_, T>) -> f64 {
+        let mut sum = 0.;
+        for row in band.into_rows() {
+            for &x in row {
+                let x: f64 = x.into();
+                sum += match self {
+                    Cost::L1 => x.abs(),
+                    Cost::Entropy => x.abs().ln_1p() / std::f64::consts::LN_2,
+                };
+            }
+        }
+        sum
+    }
+}
+
+/// Band of a wavelet packet decomposition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PacketBand {
+    /// Number of splits leading to the band
+    pub depth: usize,
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Shape of a wavelet packet decomposition.
+///
+/// Any band can be split again, not only the `LL` band of the dyadic pyramid.
+/// The children of a split are stored in the `LL`, `LH`, `HL`, `HH` order,
+/// which are the top left, top right, bottom left and bottom right quadrants.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub enum PacketTree {
+    #[default]
+    Leaf,
+    Split(Box<[PacketTree; 4]>),
+}
+
+/// Rectangles of the 4 children of a `width x height` region, relative to the region.
+fn quadrants(width: usize, height: usize) -> [(usize, usize, usize, usize); 4] {
+    let (lw, lh) = (width.div_ceil(2), height.div_ceil(2));
+    [
+        (0, 0, lw, lh),
+        (lw, 0, width - lw, lh),
+        (0, lh, lw, height - lh),
+        (lw, lh, width - lw, height - lh),
+    ]
+}
+
+impl PacketTree {
+    pub fn split(children: [PacketTree; 4]) -> Self {
+        Self::Split(Box::new(children))
+    }
+
+    /// Dyadic pyramid, where only the `LL` band is split.
+    pub fn pyramid(levels: usize) -> Self {
+        (0..levels).fold(Self::Leaf, |ll, _| {
+            Self::split([ll, Self::Leaf, Self::Leaf, Self::Leaf])
+        })
+    }
+
+    /// Every band split down to `levels`.
+    pub fn full(levels: usize) -> Self {
+        (0..levels).fold(Self::Leaf, |child, _| {
+            Self::split([child.clone(), child.clone(), child.clone(), child])
+        })
+    }
+
+    pub fn depth(&self) -> usize {
+        match self {
+            Self::Leaf => 0,
+            Self::Split(children) => 1 + children.iter().map(Self::depth).max().unwrap_or(0),
+        }
+    }
+
+    /// Leaves of the tree for a `width x height` image, in depth first order.
+    pub fn bands(&self, width: usize, height: usize) -> Vec<PacketBand> {
+        let mut bands = Vec::new();
+        self.collect_bands(0, 0, 0, width, height, &mut bands);
+        bands
+    }
+
+    fn collect_bands(
+        &self,
+        depth: usize,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        bands: &mut Vec<PacketBand>,
+    ) {
+        match self {
+            Self::Leaf => bands.push(PacketBand {
+                depth,
+                x,
+                y,
+                width,
+                height,
+            }),
+            Self::Split(children) => {
+                for (child, (cx, cy, cw, ch)) in children.iter().zip(quadrants(width, height)) {
+                    child.collect_bands(depth + 1, x + cx, y + cy, cw, ch, bands);
+                }
+            }
+        }
+    }
+
+    pub fn decompose<T>(
+        &self,
+        kernel: &impl Dwt2<T>,
+        mut img: ImageViewMut<'_, T>,
+        mut tmp: ImageViewMut<'_, T>,
+    ) {
+        if let Self::Split(children) = self {
+            kernel.dwt2(img.view_mut(), tmp.view_mut());
+            for (child, (x, y, w, h)) in children.iter().zip(quadrants(img.width(), img.height())) {
+                child.decompose(
+                    kernel,
+                    img.subview_mut(x, y, w, h),
+                    tmp.subview_mut(x, y, w, h),
+                );
+            }
+        }
+    }
+
+    pub fn reconstruct<T>(
+        &self,
+        kernel: &impl Dwt2<T>,
+        mut img: ImageViewMut<'_, T>,
+        mut tmp: ImageViewMut<'_, T>,
+    ) {
+        if let Self::Split(children) = self {
+            for (child, (x, y, w, h)) in children.iter().zip(quadrants(img.width(), img.height())) {
+                child.reconstruct(
+                    kernel,
+                    img.subview_mut(x, y, w, h),
+                    tmp.subview_mut(x, y, w, h),
+                );
+            }
+            kernel.idwt2(img.view_mut(), tmp.view_mut());
+        }
+    }
+
+    /// Best basis search: decomposes the image and returns the tree of minimal cost.
+    ///
+    /// A band is split when the cost of its best split children is lower than its own cost.
+    /// The image is left decomposed along the returned tree.
+    pub fn best_basis<T: Copy + Into<f64>>(
+        kernel: &impl Dwt2<T>,
+        mut img: ImageViewMut<'_, T>,
+        mut tmp: ImageViewMut<'_, T>,
+        levels: usize,
+        cost: Cost,
+    ) -> Self {
+        Self::best_basis_rec(kernel, img.view_mut(), tmp.view_mut(), levels, cost).0
+    }
+
+    fn best_basis_rec<T: Copy + Into<f64>>(
+        kernel: &impl Dwt2<T>,
+        mut img: ImageViewMut<'_, T>,
+        mut tmp: ImageViewMut<'_, T>,
+        levels: usize,
+        cost: Cost,
+    ) -> (Self, f64) {
+        let own = cost.cost(img.view());
+        if levels == 0 || (img.width() < 2 && img.height() < 2) {
+            return (Self::Leaf, own);
+        }
+
+        kernel.dwt2(img.view_mut(), tmp.view_mut());
+        let mut split = 0.;
+        let children = quadrants(img.width(), img.height()).map(|(x, y, w, h)| {
+            let (child, child_cost) = Self::best_basis_rec(
+                kernel,
+                img.subview_mut(x, y, w, h),
+                tmp.subview_mut(x, y, w, h),
+                levels - 1,
+                cost,
+            );
+            split += child_cost;
+            child
+        });
+        let tree = Self::split(children);
+
+        if split < own {
+            (tree, split)
+        } else {
+            tree.reconstruct(kernel, img, tmp);
+            (Self::Leaf, own)
+        }
+    }
+
+    /// Serialises the tree in pre-order, one bit per node (`1` for a split), MSB first.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bits = Vec::new();
+        self.push_bits(&mut bits);
+        bits.chunks(8)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0u8, |byte, (i, &bit)| byte | ((bit as u8) << (7 - i)))
+            })
+            .collect()
+    }
+
+    fn push_bits(&self, bits: &mut Vec<bool>) {
+        match self {
+            Self::Leaf => bits.push(false),
+            Self::Split(children) => {
+                bits.push(true);
+                children.iter().for_each(|child| child.push_bits(bits));
+            }
+        }
+    }
+
+    /// Reads a tree written by [`PacketTree::to_bytes`], returning it with the number of bytes read.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<(Self, usize)> {
+        let mut pos = 0;
+        let tree = Self::read_bits(bytes, &mut pos, 0)?;
+        Ok((tree, pos.div_ceil(8)))
+    }
+
+    fn read_bits(bytes: &[u8], pos: &mut usize, depth: usize) -> io::Result<Self> {
+        let byte = bytes
+            .get(*pos / 8)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated packet tree"))?;
+        let bit = (byte >> (7 - *pos % 8)) & 1 != 0;
+        *pos += 1;
+
+        if !bit {
+            return Ok(Self::Leaf);
+        }
+        if depth >= MAX_DEPTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Packet tree deeper than {MAX_DEPTH} levels"),
+            ));
+        }
+        let mut child = || Self::read_bits(bytes, pos, depth + 1);
+        Ok(Self::split([child()?, child()?, child()?, child()?]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        dwt::{daub::Daub53, Dwt2, SubbandLayout},
+        memory::Image,
+    };
+
+    use super::{Cost, PacketTree};
+
+    #[test]
+    fn round_trip() {
+        let input = Image::with_fn(37, 29, |x, y| ((x * 13 + y * 7) % 251) as i16 - 125);
+        let leaf = PacketTree::Leaf;
+        let tree = PacketTree::split([
+            PacketTree::pyramid(2),
+            PacketTree::full(2),
+            leaf.clone(),
+            PacketTree::split([leaf.clone(), PacketTree::full(1), leaf.clone(), leaf]),
+        ]);
+
+        let mut image = input.clone();
+        let mut tmp = input.clone();
+        tree.decompose(&Daub53, image.view_mut(), tmp.view_mut());
+        tree.reconstruct(&Daub53, image.view_mut(), tmp.view_mut());
+        assert_eq!(image, input);
+
+        let area = tree
+            .bands(37, 29)
+            .iter()
+            .map(|b| b.width * b.height)
+            .sum::<usize>();
+        assert_eq!(area, 37 * 29);
+    }
+
+    #[test]
+    fn pyramid_matches_decompose() {
+        let input = Image::with_fn(40, 24, |x, y| ((x * x + y * 3) % 97) as i16);
+        let mut packet = input.clone();
+        let mut pyramid = input.clone();
+        let mut tmp = input.clone();
+        PacketTree::pyramid(3).decompose(&Daub53, packet.view_mut(), tmp.view_mut());
+        let layout = Daub53.decompose(pyramid.view_mut(), tmp.view_mut(), 3);
+        assert_eq!(packet, pyramid);
+        assert_eq!(layout, SubbandLayout::new(40, 24, 3));
+        assert_eq!(PacketTree::pyramid(3).bands(40, 24).len(), 10);
+    }
+
+    #[test]
+    fn serialisation() {
+        let tree = PacketTree::split([
+            PacketTree::pyramid(3),
+            PacketTree::Leaf,
+            PacketTree::full(1),
+            PacketTree::Leaf,
+        ]);
+        let mut bytes = tree.to_bytes();
+        bytes.push(0xff);
+        assert_eq!(PacketTree::from_bytes(&bytes).unwrap(), (tree, 3));
+
+        assert!(PacketTree::from_bytes(&[0b1100_0000]).is_err());
+        assert!(PacketTree::from_bytes(&[0xff; 16]).is_err());
+    }
+
+    #[test]
+    fn best_basis_splits_textures() {
+        // Fine vertical stripes put all their energy in the horizontal details
+        let input = Image::with_fn(32, 32, |x, y| [40i16, -40, 25, -25][x % 4] + (y as i16 / 4));
+        for cost in [Cost::L1, Cost::Entropy] {
+            let mut image = input.clone();
+            let mut tmp = input.clone();
+            let tree = PacketTree::best_basis(&Daub53, image.view_mut(), tmp.view_mut(), 3, cost);
+
+            let PacketTree::Split(children) = &tree else {
+                panic!("Unsplit tree with {cost:?}");
+            };
+            assert_ne!(children[1], PacketTree::Leaf, "{cost:?}");
+
+            let mut expected = input.clone();
+            tree.decompose(&Daub53, expected.view_mut(), tmp.view_mut());
+            assert_eq!(image, expected);
+
+            tree.reconstruct(&Daub53, image.view_mut(), tmp.view_mut());
+            assert_eq!(image, input);
+        }
+    }
+}
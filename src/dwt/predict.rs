@@ -1,63 +1,212 @@
-use super::Dwt1;
+use crate::{
+    memory::Strided,
+    numeric::{for_each_sample, Overflow, OverflowError, Sample},
+};
 
+use super::{Boundary, Dwt1};
+
+/// Extra prediction of the high band from the slope of the low band, after the wrapped kernel.
+///
+/// The low band repeats its edge samples and arithmetic wraps,
+/// use [`Predict::with_boundary`] or [`Predict::with_overflow`] for other policies.
 pub struct Predict<A>(pub A);
 
-impl<A: Dwt1<i8>> Dwt1<i8> for Predict<A> {
-    fn dwt1(&self, mut sig: crate::memory::Strided<&mut i8>, tmp: crate::memory::Strided<&mut i8>) {
-        self.0.dwt1(sig.as_strided_mut(), tmp);
+impl<A> Predict<A> {
+    pub fn with_boundary(self, boundary: Boundary) -> PredictWith<A> {
+        PredictWith::from(self).with_boundary(boundary)
+    }
 
-        let (low, high) = sig.split_at_mut(sig.len() / 2);
-        for (i, h) in high.into_iter().enumerate() {
-            let prev = low[i.max(1) - 1] as i16;
-            let next = low[i.min(low.len() - 2) + 1] as i16;
-            *h = h.wrapping_add(((2 + prev - next) / 4) as i8);
-        }
+    pub fn with_overflow(self, overflow: Overflow) -> PredictWith<A> {
+        PredictWith::from(self).with_overflow(overflow)
     }
+}
 
-    fn idwt1(
-        &self,
-        mut sig: crate::memory::Strided<&mut i8>,
-        tmp: crate::memory::Strided<&mut i8>,
-    ) {
-        let (low, high) = sig.split_at_mut(sig.len() / 2);
-        for (i, h) in high.into_iter().enumerate() {
-            let prev = low[i.max(1) - 1] as i16;
-            let next = low[i.min(low.len() - 2) + 1] as i16;
-            *h = h.wrapping_sub(((2 + prev - next) / 4) as i8);
+/// [`Predict`] with explicit boundary and overflow policies.
+pub struct PredictWith<A> {
+    pub kernel: A,
+    pub boundary: Boundary,
+    /// Policy of the extra prediction step, the kernel has its own
+    pub overflow: Overflow,
+}
+
+impl<A> From<Predict<A>> for PredictWith<A> {
+    fn from(Predict(kernel): Predict<A>) -> Self {
+        Self {
+            kernel,
+            boundary: Boundary::default(),
+            overflow: Overflow::default(),
         }
+    }
+}
 
-        self.0.idwt1(sig, tmp);
+impl<A> PredictWith<A> {
+    pub fn with_boundary(self, boundary: Boundary) -> Self {
+        Self { boundary, ..self }
+    }
+
+    pub fn with_overflow(self, overflow: Overflow) -> Self {
+        Self { overflow, ..self }
     }
 }
 
-impl<A: Dwt1<i16>> Dwt1<i16> for Predict<A> {
-    fn dwt1(
-        &self,
-        mut sig: crate::memory::Strided<&mut i16>,
-        tmp: crate::memory::Strided<&mut i16>,
-    ) {
-        self.0.dwt1(sig.as_strided_mut(), tmp);
+/// Adds the prediction to the high band, or subtracts it when `inverse`.
+fn predict<T: Sample>(
+    sig: &mut Strided<&mut T>,
+    boundary: Boundary,
+    overflow: Overflow,
+    inverse: bool,
+) -> Result<(), OverflowError> {
+    predict_with(sig, boundary, overflow, inverse, |p| p)
+}
 
-        let (low, high) = sig.split_at_mut(sig.len() / 2);
-        for (i, h) in high.into_iter().enumerate() {
-            let prev = low[i.max(1) - 1];
-            let next = low[i.min(low.len() - 2) + 1];
-            *h += (2 + prev - next) / 4;
+/// [`predict`] of a high band holding quantization indices, the prediction being quantized by `map`.
+pub(super) fn predict_with<T: Sample>(
+    sig: &mut Strided<&mut T>,
+    boundary: Boundary,
+    overflow: Overflow,
+    inverse: bool,
+    map: impl Fn(i64) -> i64,
+) -> Result<(), OverflowError> {
+    let len = sig.len();
+    let (low, high) = sig.split_at_mut(len.div_ceil(2));
+    let get = |j| {
+        boundary
+            .extend(j, len, false)
+            .map_or(0, |j| low[j].to_i64() as i128)
+    };
+    for (i, h) in high.into_iter().enumerate() {
+        let p = map(((2 + get(i as isize - 1) - get(i as isize + 1)) / 4) as i64) as i128;
+        *h = overflow
+            .add(*h, if inverse { -p } else { p })
+            .ok_or(OverflowError)?;
+    }
+    Ok(())
+}
+
+fn forward<T: Sample, A: Dwt1<T>>(
+    kernel: &A,
+    boundary: Boundary,
+    overflow: Overflow,
+    mut sig: Strided<&mut T>,
+    tmp: Strided<&mut T>,
+) -> Result<(), OverflowError> {
+    kernel.try_dwt1(sig.as_strided_mut(), tmp)?;
+    predict(&mut sig, boundary, overflow, false)
+}
+
+fn inverse<T: Sample, A: Dwt1<T>>(
+    kernel: &A,
+    boundary: Boundary,
+    overflow: Overflow,
+    mut sig: Strided<&mut T>,
+    tmp: Strided<&mut T>,
+) -> Result<(), OverflowError> {
+    predict(&mut sig, boundary, overflow, true)?;
+    kernel.try_idwt1(sig, tmp)
+}
+
+macro_rules! predict_impl {
+    ($t:ty) => {
+        impl<A: Dwt1<$t>> Dwt1<$t> for Predict<A> {
+            fn dwt1(&self, mut sig: Strided<&mut $t>, tmp: Strided<&mut $t>) {
+                self.0.dwt1(sig.as_strided_mut(), tmp);
+                _ = predict(&mut sig, Boundary::default(), Overflow::default(), false);
+            }
+            fn try_dwt1(
+                &self,
+                sig: Strided<&mut $t>,
+                tmp: Strided<&mut $t>,
+            ) -> Result<(), OverflowError> {
+                forward(&self.0, Boundary::default(), Overflow::default(), sig, tmp)
+            }
+
+            fn idwt1(&self, mut sig: Strided<&mut $t>, tmp: Strided<&mut $t>) {
+                _ = predict(&mut sig, Boundary::default(), Overflow::default(), true);
+                self.0.idwt1(sig, tmp);
+            }
+            fn try_idwt1(
+                &self,
+                sig: Strided<&mut $t>,
+                tmp: Strided<&mut $t>,
+            ) -> Result<(), OverflowError> {
+                inverse(&self.0, Boundary::default(), Overflow::default(), sig, tmp)
+            }
+        }
+
+        impl<A: Dwt1<$t>> Dwt1<$t> for PredictWith<A> {
+            fn dwt1(&self, mut sig: Strided<&mut $t>, tmp: Strided<&mut $t>) {
+                self.kernel.dwt1(sig.as_strided_mut(), tmp);
+                // Never fails, see `Overflow::infallible`
+                _ = predict(&mut sig, self.boundary, self.overflow.infallible(), false);
+            }
+            fn try_dwt1(
+                &self,
+                sig: Strided<&mut $t>,
+                tmp: Strided<&mut $t>,
+            ) -> Result<(), OverflowError> {
+                forward(&self.kernel, self.boundary, self.overflow, sig, tmp)
+            }
+
+            fn idwt1(&self, mut sig: Strided<&mut $t>, tmp: Strided<&mut $t>) {
+                _ = predict(&mut sig, self.boundary, self.overflow.infallible(), true);
+                self.kernel.idwt1(sig, tmp);
+            }
+            fn try_idwt1(
+                &self,
+                sig: Strided<&mut $t>,
+                tmp: Strided<&mut $t>,
+            ) -> Result<(), OverflowError> {
+                inverse(&self.kernel, self.boundary, self.overflow, sig, tmp)
+            }
+        }
+    };
+}
+for_each_sample!(predict_impl);
+
+#[cfg(test)]
+mod test {
+    use crate::dwt::{daub::Daub53, haar::Haar, Boundary, Dwt1};
+
+    use super::Predict;
+
+    #[test]
+    fn round_trip() {
+        let input = [3i16, -7, 12, 40, 41, 38, -2, 0, 5, 5, 90, -100];
+        for boundary in Boundary::ALL {
+            let mut sig = input;
+            let mut tmp = [0; 12];
+            let predict = Predict(Daub53).with_boundary(boundary);
+            predict.dwt1_slice(&mut sig, &mut tmp);
+            predict.idwt1_slice(&mut sig, &mut tmp);
+            assert_eq!(sig, input);
+
+            let mut sig = input.map(|x| x as i8);
+            let mut tmp = [0; 12];
+            let predict = Predict(Haar).with_boundary(boundary);
+            predict.dwt1_slice(&mut sig, &mut tmp);
+            predict.idwt1_slice(&mut sig, &mut tmp);
+            assert_eq!(sig, input.map(|x| x as i8));
         }
     }
 
-    fn idwt1(
-        &self,
-        mut sig: crate::memory::Strided<&mut i16>,
-        tmp: crate::memory::Strided<&mut i16>,
-    ) {
-        let (low, high) = sig.split_at_mut(sig.len() / 2);
-        for (i, h) in high.into_iter().enumerate() {
+    #[test]
+    fn clamped_by_default() {
+        let input = [3i16, -7, 12, 40, 41, 38, -2, 0, 5, 5, 90, -100];
+        let mut expected = input;
+        let mut tmp = [0; 12];
+        Daub53.dwt1_slice(&mut expected, &mut tmp);
+        // Prediction with clamped indices into the low band
+        let (low, high) = expected.split_at_mut(6);
+        for (i, h) in high.iter_mut().enumerate() {
             let prev = low[i.max(1) - 1];
             let next = low[i.min(low.len() - 2) + 1];
-            *h -= (2 + prev - next) / 4;
+            *h += (2 + prev - next) / 4;
         }
 
-        self.0.idwt1(sig, tmp);
+        let mut sig = input;
+        Predict(Daub53).dwt1_slice(&mut sig, &mut tmp);
+        assert_eq!(sig, expected);
+        Predict(Daub53).idwt1_slice(&mut sig, &mut tmp);
+        assert_eq!(sig, input);
     }
 }
@@ -0,0 +1,394 @@
+use crate::{
+    memory::Strided,
+    numeric::{for_each_sample, Overflow, OverflowError, Sample},
+    quant::Quantizer,
+};
+
+use super::{
+    lifting::LiftingKernel,
+    predict::{predict_with, Predict, PredictWith},
+    Boundary, Dwt1,
+};
+
+/// Lossy kernel quantizing the high band inside the lifting steps of its kernel.
+///
+/// The first prediction quantizes the high band, the following ones are quantized as well,
+/// and every other step reads the dequantized high band, exactly like the inverse transform.
+/// Encoder and decoder then agree on every value the steps are computed from,
+/// and the reconstruction error is only the quantization error of the first prediction:
+/// the even samples are reconstructed exactly, whatever the number of steps.
+///
+/// The transformed high band holds quantization indices.
+/// In 2D, the columns of the high band of the rows are transformed losslessly by the kernel,
+/// so that no coefficient is quantized twice.
+pub struct Quantized<A, Q> {
+    pub kernel: A,
+    pub quantizer: Q,
+}
+
+impl<A, Q> Quantized<A, Q> {
+    pub fn new(kernel: A, quantizer: Q) -> Self {
+        Self { kernel, quantizer }
+    }
+}
+
+/// Kernel able to quantize its high band inside its lifting steps, see [`Quantized`].
+///
+/// Implemented by the [`LiftingKernel`]s and by [`Predict`] around one of them.
+pub trait QuantizedKernel<T>: Dwt1<T> {
+    /// Forward transform leaving quantization indices in the high band.
+    ///
+    /// With `infallible`, overflows wrap around instead of failing, see [`Overflow::infallible`].
+    fn forward_quantized(
+        &self,
+        sig: Strided<&mut T>,
+        tmp: Strided<&mut T>,
+        quantizer: &impl Quantizer,
+        infallible: bool,
+    ) -> Result<(), OverflowError>;
+
+    fn inverse_quantized(
+        &self,
+        sig: Strided<&mut T>,
+        tmp: Strided<&mut T>,
+        quantizer: &impl Quantizer,
+        infallible: bool,
+    ) -> Result<(), OverflowError>;
+}
+
+impl<T: Sample, K: LiftingKernel + Dwt1<T>> QuantizedKernel<T> for K {
+    fn forward_quantized(
+        &self,
+        sig: Strided<&mut T>,
+        tmp: Strided<&mut T>,
+        quantizer: &impl Quantizer,
+        infallible: bool,
+    ) -> Result<(), OverflowError> {
+        let lifting = self.lifting();
+        let lifting = if infallible {
+            lifting.infallible()
+        } else {
+            lifting
+        };
+        lifting.forward_quantized(sig, tmp, quantizer)
+    }
+
+    fn inverse_quantized(
+        &self,
+        sig: Strided<&mut T>,
+        tmp: Strided<&mut T>,
+        quantizer: &impl Quantizer,
+        infallible: bool,
+    ) -> Result<(), OverflowError> {
+        let lifting = self.lifting();
+        let lifting = if infallible {
+            lifting.infallible()
+        } else {
+            lifting
+        };
+        lifting.inverse_quantized(sig, tmp, quantizer)
+    }
+}
+
+/// The extra prediction adds the quantized prediction to the indices.
+fn forward_predict<T: Sample, A: QuantizedKernel<T>>(
+    kernel: &A,
+    boundary: Boundary,
+    overflow: Overflow,
+    mut sig: Strided<&mut T>,
+    tmp: Strided<&mut T>,
+    quantizer: &impl Quantizer,
+    infallible: bool,
+) -> Result<(), OverflowError> {
+    kernel.forward_quantized(sig.as_strided_mut(), tmp, quantizer, infallible)?;
+    let overflow = if infallible {
+        overflow.infallible()
+    } else {
+        overflow
+    };
+    predict_with(&mut sig, boundary, overflow, false, |p| {
+        quantizer.quantize(p)
+    })
+}
+
+fn inverse_predict<T: Sample, A: QuantizedKernel<T>>(
+    kernel: &A,
+    boundary: Boundary,
+    overflow: Overflow,
+    mut sig: Strided<&mut T>,
+    tmp: Strided<&mut T>,
+    quantizer: &impl Quantizer,
+    infallible: bool,
+) -> Result<(), OverflowError> {
+    let overflow = if infallible {
+        overflow.infallible()
+    } else {
+        overflow
+    };
+    predict_with(&mut sig, boundary, overflow, true, |p| {
+        quantizer.quantize(p)
+    })?;
+    kernel.inverse_quantized(sig, tmp, quantizer, infallible)
+}
+
+impl<T: Sample, A: QuantizedKernel<T>> QuantizedKernel<T> for Predict<A>
+where
+    Predict<A>: Dwt1<T>,
+{
+    fn forward_quantized(
+        &self,
+        sig: Strided<&mut T>,
+        tmp: Strided<&mut T>,
+        quantizer: &impl Quantizer,
+        infallible: bool,
+    ) -> Result<(), OverflowError> {
+        let (boundary, overflow) = (Boundary::default(), Overflow::default());
+        forward_predict(&self.0, boundary, overflow, sig, tmp, quantizer, infallible)
+    }
+
+    fn inverse_quantized(
+        &self,
+        sig: Strided<&mut T>,
+        tmp: Strided<&mut T>,
+        quantizer: &impl Quantizer,
+        infallible: bool,
+    ) -> Result<(), OverflowError> {
+        let (boundary, overflow) = (Boundary::default(), Overflow::default());
+        inverse_predict(&self.0, boundary, overflow, sig, tmp, quantizer, infallible)
+    }
+}
+
+impl<T: Sample, A: QuantizedKernel<T>> QuantizedKernel<T> for PredictWith<A>
+where
+    PredictWith<A>: Dwt1<T>,
+{
+    fn forward_quantized(
+        &self,
+        sig: Strided<&mut T>,
+        tmp: Strided<&mut T>,
+        quantizer: &impl Quantizer,
+        infallible: bool,
+    ) -> Result<(), OverflowError> {
+        let (boundary, overflow) = (self.boundary, self.overflow);
+        forward_predict(
+            &self.kernel,
+            boundary,
+            overflow,
+            sig,
+            tmp,
+            quantizer,
+            infallible,
+        )
+    }
+
+    fn inverse_quantized(
+        &self,
+        sig: Strided<&mut T>,
+        tmp: Strided<&mut T>,
+        quantizer: &impl Quantizer,
+        infallible: bool,
+    ) -> Result<(), OverflowError> {
+        let (boundary, overflow) = (self.boundary, self.overflow);
+        inverse_predict(
+            &self.kernel,
+            boundary,
+            overflow,
+            sig,
+            tmp,
+            quantizer,
+            infallible,
+        )
+    }
+}
+
+macro_rules! quantized_impl {
+    ($t:ty) => {
+        impl<A: QuantizedKernel<$t>, Q: Quantizer> Dwt1<$t> for Quantized<A, Q> {
+            fn dwt1(&self, sig: Strided<&mut $t>, tmp: Strided<&mut $t>) {
+                // Never fails, see `Overflow::infallible`
+                _ = self
+                    .kernel
+                    .forward_quantized(sig, tmp, &self.quantizer, true);
+            }
+            fn try_dwt1(
+                &self,
+                sig: Strided<&mut $t>,
+                tmp: Strided<&mut $t>,
+            ) -> Result<(), OverflowError> {
+                self.kernel
+                    .forward_quantized(sig, tmp, &self.quantizer, false)
+            }
+
+            fn idwt1(&self, sig: Strided<&mut $t>, tmp: Strided<&mut $t>) {
+                _ = self
+                    .kernel
+                    .inverse_quantized(sig, tmp, &self.quantizer, true);
+            }
+            fn try_idwt1(
+                &self,
+                sig: Strided<&mut $t>,
+                tmp: Strided<&mut $t>,
+            ) -> Result<(), OverflowError> {
+                self.kernel
+                    .inverse_quantized(sig, tmp, &self.quantizer, false)
+            }
+
+            fn high_band_kernel(&self) -> &dyn Dwt1<$t> {
+                &self.kernel
+            }
+        }
+    };
+}
+for_each_sample!(quantized_impl);
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        dwt::{
+            daub::{Daub53, Daub97},
+            haar::Haar,
+            lifting::Lifting,
+            predict::Predict,
+            reversible::{SPlusP, W210, W26},
+            Dwt1, Dwt2,
+        },
+        memory::Image,
+        quant::{DeadZone, Quantizer},
+        testing,
+    };
+
+    use super::{Quantized, QuantizedKernel};
+
+    fn signal(len: usize) -> Vec<i16> {
+        testing::signal(len, 11, |x| x as i16 % 512)
+    }
+
+    #[test]
+    fn unit_step_is_lossless() {
+        let input = signal(37);
+        let mut expected = input.clone();
+        let mut sig = input.clone();
+        let mut tmp = vec![0; input.len()];
+
+        Daub97.dwt1_slice(&mut expected, &mut tmp);
+        let quantized = Quantized::new(Daub97, DeadZone::new(1));
+        quantized.dwt1_slice(&mut sig, &mut tmp);
+        assert_eq!(sig, expected);
+
+        quantized.idwt1_slice(&mut sig, &mut tmp);
+        assert_eq!(sig, input);
+    }
+
+    #[test]
+    fn drift_free() {
+        let input = signal(64);
+        let quantizer = DeadZone::new(8);
+
+        fn check(kernel: impl QuantizedKernel<i16>, input: &[i16], quantizer: DeadZone) {
+            let quantized = Quantized::new(kernel, quantizer);
+            let mut sig = input.to_vec();
+            let mut tmp = vec![0; input.len()];
+            quantized.dwt1_slice(&mut sig, &mut tmp);
+            quantized.idwt1_slice(&mut sig, &mut tmp);
+            for (i, (&x, &y)) in input.iter().zip(&sig).enumerate() {
+                let error = (x - y).abs();
+                assert!(error < if i % 2 == 0 { 1 } else { 8 }, "{i}: {error}");
+            }
+        }
+        for kernel in [
+            Haar::LIFTING,
+            Daub53::LIFTING,
+            Daub97::LIFTING,
+            W26::LIFTING,
+            W210::LIFTING,
+            SPlusP::LIFTING,
+        ] {
+            check(kernel, &input, quantizer);
+        }
+        check(Predict(Daub53), &input, quantizer);
+        check(
+            Predict(Daub97).with_overflow(Default::default()),
+            &input,
+            quantizer,
+        );
+
+        // Quantizing after the transform makes the inverse update read other values than the forward one
+        let mut sig = input.clone();
+        let mut tmp = vec![0; input.len()];
+        Daub53.dwt1_slice(&mut sig, &mut tmp);
+        for h in &mut sig[32..] {
+            *h = quantizer.dequantize(quantizer.quantize(*h as i64)) as i16;
+        }
+        Daub53.idwt1_slice(&mut sig, &mut tmp);
+        assert!(input.iter().zip(&sig).step_by(2).any(|(x, y)| x != y));
+    }
+
+    #[test]
+    fn daub97_agreement() {
+        // The decoder reconstructs the odd samples from the first prediction and its quantized residual
+        let input = signal(64);
+        let quantizer = DeadZone::new(8).with_offset(4);
+        let first = Lifting {
+            steps: &Daub97::LIFTING.steps[..1],
+            ..Daub97::LIFTING
+        };
+        let mut residual = input.clone();
+        let mut tmp = vec![0; input.len()];
+        first.dwt1_slice(&mut residual, &mut tmp);
+        let expected = input
+            .iter()
+            .enumerate()
+            .map(|(i, &x)| {
+                if i % 2 == 0 {
+                    return x;
+                }
+                let h = residual[32 + i / 2] as i64;
+                x - h as i16 + quantizer.dequantize(quantizer.quantize(h)) as i16
+            })
+            .collect::<Vec<_>>();
+
+        let quantized = Quantized::new(Daub97, quantizer);
+        let mut sig = input.clone();
+        quantized.dwt1_slice(&mut sig, &mut tmp);
+        quantized.idwt1_slice(&mut sig, &mut tmp);
+        assert_eq!(sig, expected);
+        assert_ne!(sig, input);
+    }
+
+    #[test]
+    fn multilevel() {
+        let input = Image::with_fn(33, 30, |x, y| ((x * 9 + y * y) % 256) as i16);
+        let quantized = Quantized::new(Daub97, DeadZone::new(4).with_offset(2));
+        let mut coefs = input.clone();
+        let mut tmp = input.clone();
+        quantized.decompose(coefs.view_mut(), tmp.view_mut(), 3);
+        let mut image = coefs.clone();
+        quantized.reconstruct(image.view_mut(), tmp.view_mut(), 3);
+        assert_ne!(image, input);
+
+        // Encoding the reconstruction gives back the same coefficients, as every index is quantized once
+        let mut again = image.clone();
+        quantized.decompose(again.view_mut(), tmp.view_mut(), 3);
+        assert_eq!(again, coefs);
+    }
+
+    #[test]
+    fn high_band_quantized_once() {
+        let input = Image::with_fn(16, 12, |x, y| ((x * x * 7 + y * 31) % 256) as i16);
+        let quantized = Quantized::new(Daub53, DeadZone::new(16));
+        let mut image = input.clone();
+        let mut tmp = input.clone();
+        quantized.dwt2(image.view_mut(), tmp.view_mut());
+
+        // The columns of the row indices are only transformed losslessly
+        let mut expected = input.clone();
+        for (row, tmp) in expected.rows_mut().zip(tmp.rows_mut()) {
+            quantized.dwt1_slice(row, tmp);
+        }
+        let mut high = image.view_mut().into_subview_mut(8, 0, 8, 12);
+        for (col, tmp) in high.cols_mut().zip(tmp.cols_mut()) {
+            Daub53.idwt1(col, tmp);
+        }
+        assert_eq!(image.subview(8, 0, 8, 12), expected.subview(8, 0, 8, 12));
+    }
+}
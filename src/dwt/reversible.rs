@@ -0,0 +1,201 @@
+//! Reversible integer-to-integer kernels compared in the following paper
+//!
+//! > Adams, Michael D., and Faouzi Kossentini.
+//! > "Reversible integer-to-integer wavelet transforms for image coding: Performance evaluation and analysis."
+//! > IEEE Transactions on image processing 9.6 (2000): 1010-1024.
+
+use super::lifting::{lifting_kernel_impl, Filter, Lifting, Rounding, Step};
+
+/// 2/6 kernel
+///
+/// ```text
+/// d1[n] = x[2n+1] - x[2n]
+/// s[n] = x[2n] + floor(1/2 d1[n])
+/// d[n] = d1[n] + floor(1/4 (-s[n+1] + s[n-1]) + 1/2)
+/// ```
+pub struct W26;
+
+impl W26 {
+    pub const LIFTING: Lifting<'static> = Lifting::new(
+        &[
+            Step::Predict(Filter::new(0, &[1], 0, 0)),
+            Step::Update(Filter::new(0, &[1], 0, 1)),
+            Step::Predict(Filter::new(-1, &[-1, 0, 1], 1, 2)),
+        ],
+        Rounding::Floor,
+    );
+}
+
+/// 5/11-C kernel
+///
+/// ```text
+/// d1[n] = x[2n+1] - floor(1/2 (x[2n+2] + x[2n]))
+/// s[n] = x[2n] + floor(1/4 (d1[n] + d1[n-1]) + 1/2)
+/// d[n] = d1[n] + floor(1/16 (s[n+2] - s[n+1] - s[n] + s[n-1]) + 1/2)
+/// ```
+pub struct W511C;
+
+impl W511C {
+    pub const LIFTING: Lifting<'static> = Lifting::new(
+        &[
+            Step::Predict(Filter::new(0, &[1, 1], 0, 1)),
+            Step::Update(Filter::new(-1, &[1, 1], 2, 2)),
+            Step::Predict(Filter::new(-1, &[-1, 1, 1, -1], 7, 4)),
+        ],
+        Rounding::Floor,
+    );
+}
+
+/// 5/11-A kernel
+///
+/// ```text
+/// d1[n] = x[2n+1] - floor(1/2 (x[2n+2] + x[2n]))
+/// s[n] = x[2n] + floor(1/4 (d1[n] + d1[n-1]) + 1/2)
+/// d[n] = d1[n] + floor(1/32 (s[n+2] - s[n+1] - s[n] + s[n-1]) + 1/2)
+/// ```
+pub struct W511A;
+
+impl W511A {
+    pub const LIFTING: Lifting<'static> = Lifting::new(
+        &[
+            Step::Predict(Filter::new(0, &[1, 1], 0, 1)),
+            Step::Update(Filter::new(-1, &[1, 1], 2, 2)),
+            Step::Predict(Filter::new(-1, &[-1, 1, 1, -1], 15, 5)),
+        ],
+        Rounding::Floor,
+    );
+}
+
+/// 9/7-M kernel
+///
+/// ```text
+/// d[n] = x[2n+1] + floor(1/16 ((x[2n+4] + x[2n-2]) - 9 (x[2n+2] + x[2n])) + 1/2)
+/// s[n] = x[2n] + floor(1/4 (d[n] + d[n-1]) + 1/2)
+/// ```
+pub struct W97M;
+
+impl W97M {
+    pub const LIFTING: Lifting<'static> = Lifting::new(
+        &[
+            Step::Predict(Filter::new(-1, &[-1, 9, 9, -1], 7, 4)),
+            Step::Update(Filter::new(-1, &[1, 1], 2, 2)),
+        ],
+        Rounding::Floor,
+    );
+}
+
+/// 13/7-T kernel
+///
+/// ```text
+/// d[n] = x[2n+1] + floor(1/16 ((x[2n+4] + x[2n-2]) - 9 (x[2n+2] + x[2n])) + 1/2)
+/// s[n] = x[2n] + floor(1/32 (9 (d[n] + d[n-1]) - (d[n+1] + d[n-2])) + 1/2)
+/// ```
+pub struct W137T;
+
+impl W137T {
+    pub const LIFTING: Lifting<'static> = Lifting::new(
+        &[
+            Step::Predict(Filter::new(-1, &[-1, 9, 9, -1], 7, 4)),
+            Step::Update(Filter::new(-2, &[-1, 9, 9, -1], 16, 5)),
+        ],
+        Rounding::Floor,
+    );
+}
+
+/// 2/10 kernel
+///
+/// ```text
+/// d1[n] = x[2n+1] - x[2n]
+/// s[n] = x[2n] + floor(1/2 d1[n])
+/// d[n] = d1[n] + floor(1/64 (22 (s[n-1] - s[n+1]) + 3 (s[n+2] - s[n-2])) + 1/2)
+/// ```
+pub struct W210;
+
+impl W210 {
+    pub const LIFTING: Lifting<'static> = Lifting::new(
+        &[
+            Step::Predict(Filter::new(0, &[1], 0, 0)),
+            Step::Update(Filter::new(0, &[1], 0, 1)),
+            Step::Predict(Filter::new(-2, &[3, -22, 0, 22, -3], 31, 6)),
+        ],
+        Rounding::Floor,
+    );
+}
+
+/// S+P kernel with predictor B from the following paper
+///
+/// > Said, Amir, and William A. Pearlman.
+/// > "An image multiresolution representation for lossless and lossy compression."
+/// > IEEE Transactions on image processing 5.9 (1996): 1303-1310.
+///
+/// ```text
+/// d1[n] = x[2n+1] - x[2n]
+/// s[n] = x[2n] + floor(1/2 d1[n])
+/// d[n] = d1[n] + floor(1/8 (2 s[n-1] + s[n] - 3 s[n+1] + 2 d1[n+1]) + 1/2)
+/// ```
+pub struct SPlusP;
+
+impl SPlusP {
+    pub const LIFTING: Lifting<'static> = Lifting::new(
+        &[
+            Step::Predict(Filter::new(0, &[1], 0, 0)),
+            Step::Update(Filter::new(0, &[1], 0, 1)),
+            Step::Predict(Filter::new(-1, &[-2, -1, 3], 3, 3).with_lookahead(&[-2])),
+        ],
+        Rounding::Floor,
+    );
+}
+
+lifting_kernel_impl!(W26);
+lifting_kernel_impl!(W511C);
+lifting_kernel_impl!(W511A);
+lifting_kernel_impl!(W97M);
+lifting_kernel_impl!(W137T);
+lifting_kernel_impl!(W210);
+lifting_kernel_impl!(SPlusP);
+
+#[cfg(test)]
+mod test {
+    use crate::{dwt::Dwt1, testing::signal};
+
+    use super::{SPlusP, W137T, W210, W26, W511A, W511C, W97M};
+
+    fn check<A: Dwt1<i16> + Dwt1<i32>>(kernel: A) {
+        for len in [2, 4, 8, 12, 64] {
+            let input = signal(len, 3 + len as u32, |x| x as i16 % 2048);
+
+            let mut sig = input.clone();
+            let mut tmp = vec![0; len];
+            kernel.dwt1_slice(&mut sig, &mut tmp);
+            kernel.idwt1_slice(&mut sig, &mut tmp);
+            assert_eq!(sig, input);
+
+            let input = input.iter().map(|&x| x as i32 * 997).collect::<Vec<_>>();
+            let mut sig = input.clone();
+            let mut tmp = vec![0; len];
+            kernel.dwt1_slice(&mut sig, &mut tmp);
+            kernel.idwt1_slice(&mut sig, &mut tmp);
+            assert_eq!(sig, input);
+        }
+
+        // Ramps are predicted exactly away from the borders
+        let input = (0..32).map(|x| x * 5 - 40).collect::<Vec<i32>>();
+        let mut sig = input.clone();
+        let mut tmp = vec![0; input.len()];
+        kernel.dwt1_slice(&mut sig, &mut tmp);
+        for &h in &sig[19..29] {
+            assert!(h.abs() <= 1, "{h}");
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        check(W26);
+        check(W511C);
+        check(W511A);
+        check(W97M);
+        check(W137T);
+        check(W210);
+        check(SPlusP);
+    }
+}
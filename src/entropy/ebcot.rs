@@ -0,0 +1,645 @@
+//! Embedded bitplane coder of the subbands, after the tier-1 coder of JPEG 2000 described in
+//!
+//! > Taubman, David.
+//! > "High performance scalable image compression with EBCOT."
+//! > IEEE Transactions on image processing 9.7 (2000): 1158-1170.
+
+use std::io;
+
+use crate::{
+    bitio::{BitReader, BitWriter},
+    dwt::{Orientation, SubbandLayout},
+    memory::{ImageView, ImageViewMut},
+    numeric::Sample,
+};
+
+use super::mq::{Context, MqDecoder, MqEncoder};
+
+const SIGNIFICANT: u8 = 1;
+/// Coded by the significance propagation pass of the current bitplane
+const VISITED: u8 = 2;
+const REFINED: u8 = 4;
+
+/// Context indices: 9 zero coding, 5 sign coding, 3 magnitude refinement, run and uniform.
+const ZC: usize = 0;
+const SC: usize = 9;
+const MR: usize = 14;
+const RUN: usize = 17;
+const UNIFORM: usize = 18;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pass {
+    Significance,
+    Refinement,
+    Cleanup,
+}
+
+/// Either half of the MQ coder: the encoder codes `bit`, the decoder ignores it and returns the decoded bit.
+trait Coder {
+    fn code(&mut self, cx: &mut Context, bit: bool) -> bool;
+}
+
+impl Coder for MqEncoder {
+    fn code(&mut self, cx: &mut Context, bit: bool) -> bool {
+        self.encode(bit, cx);
+        bit
+    }
+}
+
+impl Coder for MqDecoder<'_> {
+    fn code(&mut self, cx: &mut Context, _bit: bool) -> bool {
+        self.decode(cx)
+    }
+}
+
+/// Scan order of a code block: stripes of 4 rows, column by column inside a stripe.
+fn scan(width: usize, height: usize) -> impl Iterator<Item = (usize, usize)> {
+    (0..height).step_by(4).flat_map(move |y0| {
+        (0..width).flat_map(move |x| (y0..height.min(y0 + 4)).map(move |y| (x, y)))
+    })
+}
+
+/// Coding state of a code block, shared by the encoder and the decoder.
+struct Block {
+    orientation: Orientation,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    /// Number of magnitude bitplanes, the block has no pass above them
+    planes: u32,
+    magnitudes: Vec<u64>,
+    negative: Vec<bool>,
+    flags: Vec<u8>,
+    /// Lowest bitplane known of each significant coefficient
+    known: Vec<u32>,
+    contexts: [Context; 19],
+}
+
+impl Block {
+    fn new(orientation: Orientation, x: usize, y: usize, width: usize, height: usize) -> Self {
+        let mut contexts = [Context::default(); 19];
+        contexts[ZC] = Context::new(4, false);
+        contexts[RUN] = Context::new(3, false);
+        contexts[UNIFORM] = Context::UNIFORM;
+        let size = width * height;
+        Self {
+            orientation,
+            x,
+            y,
+            width,
+            height,
+            planes: 0,
+            magnitudes: vec![0; size],
+            negative: vec![false; size],
+            flags: vec![0; size],
+            known: vec![0; size],
+            contexts,
+        }
+    }
+
+    fn flags(&self, x: isize, y: isize) -> u8 {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return 0;
+        }
+        self.flags[y as usize * self.width + x as usize]
+    }
+
+    /// Significance of a neighbour: 0 if not significant, else 1 or -1 with the sign of the coefficient.
+    fn sign(&self, x: isize, y: isize) -> i32 {
+        if self.flags(x, y) & SIGNIFICANT == 0 {
+            0
+        } else if self.negative[y as usize * self.width + x as usize] {
+            -1
+        } else {
+            1
+        }
+    }
+
+    /// Numbers of significant horizontal, vertical and diagonal neighbours.
+    fn neighbours(&self, x: usize, y: usize) -> (u32, u32, u32) {
+        let (x, y) = (x as isize, y as isize);
+        let s = |dx, dy| (self.flags(x + dx, y + dy) & SIGNIFICANT) as u32;
+        (
+            s(-1, 0) + s(1, 0),
+            s(0, -1) + s(0, 1),
+            s(-1, -1) + s(1, -1) + s(-1, 1) + s(1, 1),
+        )
+    }
+
+    fn zero_context(&self, x: usize, y: usize) -> usize {
+        let (h, v, d) = self.neighbours(x, y);
+        // `LH` holds horizontal high frequencies, whose coefficients are correlated vertically
+        let (h, v) = match self.orientation {
+            Orientation::LH => (v, h),
+            _ => (h, v),
+        };
+        ZC + match self.orientation {
+            Orientation::HH => match (d, h + v) {
+                (3.., _) => 8,
+                (2, 1..) => 7,
+                (2, 0) => 6,
+                (1, 2..) => 5,
+                (1, 1) => 4,
+                (1, 0) => 3,
+                (0, 2..) => 2,
+                (0, 1) => 1,
+                (0, 0) => 0,
+            },
+            _ => match (h, v, d) {
+                (2.., _, _) => 8,
+                (1, 1.., _) => 7,
+                (1, 0, 1..) => 6,
+                (1, 0, 0) => 5,
+                (0, 2.., _) => 4,
+                (0, 1, _) => 3,
+                (0, 0, 2..) => 2,
+                (0, 0, 1) => 1,
+                (0, 0, 0) => 0,
+            },
+        }
+    }
+
+    /// Sign coding context, and the bit predicted by the neighbours, to be xored with the sign.
+    fn sign_context(&self, x: usize, y: usize) -> (usize, bool) {
+        let (x, y) = (x as isize, y as isize);
+        let h = (self.sign(x - 1, y) + self.sign(x + 1, y)).clamp(-1, 1);
+        let v = (self.sign(x, y - 1) + self.sign(x, y + 1)).clamp(-1, 1);
+        let (cx, xor) = match (h, v) {
+            (1, 1) => (4, false),
+            (1, 0) => (3, false),
+            (1, -1) => (2, false),
+            (0, 1) => (1, false),
+            (0, 0) => (0, false),
+            (0, -1) => (1, true),
+            (-1, 1) => (2, true),
+            (-1, 0) => (3, true),
+            _ => (4, true),
+        };
+        (SC + cx, xor)
+    }
+
+    fn code_sign(&mut self, coder: &mut impl Coder, x: usize, y: usize, p: u32) {
+        let i = y * self.width + x;
+        let (cx, xor) = self.sign_context(x, y);
+        let bit = coder.code(&mut self.contexts[cx], self.negative[i] ^ xor);
+        self.negative[i] = bit ^ xor;
+        self.magnitudes[i] |= 1 << p;
+        self.flags[i] |= SIGNIFICANT;
+        self.known[i] = p;
+    }
+
+    fn code_significance(&mut self, coder: &mut impl Coder, x: usize, y: usize, p: u32) {
+        let i = y * self.width + x;
+        let cx = self.zero_context(x, y);
+        if coder.code(&mut self.contexts[cx], self.magnitudes[i] >> p & 1 != 0) {
+            self.code_sign(coder, x, y, p);
+        }
+    }
+
+    /// Whether a pass codes no symbol, in which case it is not stored at all.
+    fn is_empty(&self, pass: Pass) -> bool {
+        let (width, height) = (self.width, self.height);
+        let mut coefficients = scan(width, height).map(|(x, y)| (x, y, self.flags[y * width + x]));
+        match pass {
+            Pass::Significance => !coefficients.any(|(x, y, flags)| {
+                let (h, v, d) = self.neighbours(x, y);
+                flags & SIGNIFICANT == 0 && h + v + d > 0
+            }),
+            Pass::Refinement => {
+                !coefficients.any(|(_, _, f)| f & (SIGNIFICANT | VISITED) == SIGNIFICANT)
+            }
+            Pass::Cleanup => !coefficients.any(|(_, _, f)| f & (SIGNIFICANT | VISITED) == 0),
+        }
+    }
+
+    fn code_pass(&mut self, coder: &mut impl Coder, pass: Pass, p: u32) {
+        match pass {
+            Pass::Significance => self.significance_pass(coder, p),
+            Pass::Refinement => self.refinement_pass(coder, p),
+            Pass::Cleanup => self.cleanup_pass(coder, p),
+        }
+    }
+
+    /// Codes the insignificant coefficients with a significant neighbour.
+    fn significance_pass(&mut self, coder: &mut impl Coder, p: u32) {
+        for (x, y) in scan(self.width, self.height) {
+            let i = y * self.width + x;
+            let (h, v, d) = self.neighbours(x, y);
+            if self.flags[i] & SIGNIFICANT == 0 && h + v + d > 0 {
+                self.code_significance(coder, x, y, p);
+                self.flags[i] |= VISITED;
+            }
+        }
+    }
+
+    /// Codes bitplane `p` of the coefficients significant before this bitplane.
+    fn refinement_pass(&mut self, coder: &mut impl Coder, p: u32) {
+        for (x, y) in scan(self.width, self.height) {
+            let i = y * self.width + x;
+            if self.flags[i] & (SIGNIFICANT | VISITED) != SIGNIFICANT {
+                continue;
+            }
+            let (h, v, d) = self.neighbours(x, y);
+            let cx = if self.flags[i] & REFINED != 0 {
+                MR + 2
+            } else if h + v + d > 0 {
+                MR + 1
+            } else {
+                MR
+            };
+            if coder.code(&mut self.contexts[cx], self.magnitudes[i] >> p & 1 != 0) {
+                self.magnitudes[i] |= 1 << p;
+            }
+            self.flags[i] |= REFINED;
+            self.known[i] = p;
+        }
+    }
+
+    /// Codes the remaining coefficients, with a run mode for columns of insignificant neighbourhoods.
+    fn cleanup_pass(&mut self, coder: &mut impl Coder, p: u32) {
+        let width = self.width;
+        for y0 in (0..self.height).step_by(4) {
+            let rows = (self.height - y0).min(4);
+            for x in 0..width {
+                let mut start = 0;
+                let run = rows == 4
+                    && (y0..y0 + 4).all(|y| {
+                        let (h, v, d) = self.neighbours(x, y);
+                        self.flags[y * width + x] == 0 && h + v + d == 0
+                    });
+                if run {
+                    let first = (y0..y0 + 4)
+                        .position(|y| self.magnitudes[y * width + x] >> p & 1 != 0)
+                        .unwrap_or(0);
+                    let any = self.magnitudes[(y0 + first) * width + x] >> p & 1 != 0;
+                    if !coder.code(&mut self.contexts[RUN], any) {
+                        continue;
+                    }
+                    let high = coder.code(&mut self.contexts[UNIFORM], first & 2 != 0);
+                    let low = coder.code(&mut self.contexts[UNIFORM], first & 1 != 0);
+                    let first = (high as usize) << 1 | low as usize;
+                    self.code_sign(coder, x, y0 + first, p);
+                    start = first + 1;
+                }
+                for y in y0 + start..y0 + rows {
+                    if self.flags[y * width + x] & (SIGNIFICANT | VISITED) == 0 {
+                        self.code_significance(coder, x, y, p);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Clears the coefficients visited by the passes of the current bitplane.
+    fn end_plane(&mut self) {
+        for flags in &mut self.flags {
+            *flags &= !VISITED;
+        }
+    }
+
+    /// Coefficient reconstructed from the known bitplanes, at the middle of the remaining interval.
+    ///
+    /// With 64 bitplanes, the magnitude does not fit in `i64`, so the value is clamped to its range.
+    fn value(&self, i: usize) -> i64 {
+        let magnitude = self.magnitudes[i];
+        if magnitude == 0 {
+            return 0;
+        }
+        let value = magnitude as i128 + ((1i128 << self.known[i]) >> 1);
+        let value = if self.negative[i] { -value } else { value };
+        value.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+    }
+}
+
+fn write_length(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 0x80 {
+        out.push(len as u8 | 0x80);
+        len >>= 7;
+    }
+    out.push(len as u8);
+}
+
+/// Reads a segment length, `None` if the data ends before it.
+fn read_length(data: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut len = 0usize;
+    for shift in (0..usize::BITS).step_by(7) {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        len |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Some(len);
+        }
+    }
+    None
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Embedded bitplane coder of a decomposed image, split into code blocks of each subband.
+///
+/// Each bitplane of a code block is coded in a significance propagation pass,
+/// a magnitude refinement pass and a cleanup pass, each one terminated as its own MQ codeword.
+/// The passes of all the code blocks are ordered by bitplane, from the most significant,
+/// so any prefix of the stream ending on a pass decodes into a lower quality image,
+/// and [`Ebcot::truncate`] cuts the stream to a byte budget.
+///
+/// The stream starts with a header giving the number of bitplanes of each code block,
+/// then each pass is stored as its length, on 7 bits per byte, followed by its codeword.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ebcot {
+    pub block_size: usize,
+}
+
+impl Default for Ebcot {
+    fn default() -> Self {
+        Self { block_size: 64 }
+    }
+}
+
+impl Ebcot {
+    pub fn with_block_size(self, block_size: usize) -> Self {
+        assert!(block_size > 0, "Code blocks cannot be empty");
+        Self { block_size }
+    }
+
+    /// Code blocks of every band, in band order then raster order.
+    fn blocks(&self, layout: &SubbandLayout) -> Vec<Block> {
+        let mut blocks = Vec::new();
+        for band in layout.bands() {
+            for y in (0..band.height).step_by(self.block_size) {
+                for x in (0..band.width).step_by(self.block_size) {
+                    blocks.push(Block::new(
+                        band.orientation,
+                        band.x + x,
+                        band.y + y,
+                        self.block_size.min(band.width - x),
+                        self.block_size.min(band.height - y),
+                    ));
+                }
+            }
+        }
+        blocks
+    }
+
+    pub fn encode<T: Sample>(&self, img: ImageView<'_, T>, layout: &SubbandLayout) -> Vec<u8> {
+        let mut blocks = self.blocks(layout);
+        for block in &mut blocks {
+            let view = img.subview(block.x, block.y, block.width, block.height);
+            for (y, row) in view.rows().enumerate() {
+                for (x, &c) in row.iter().enumerate() {
+                    let i = y * block.width + x;
+                    block.magnitudes[i] = c.to_i64().unsigned_abs();
+                    block.negative[i] = c.to_i64() < 0;
+                }
+            }
+            let max = block.magnitudes.iter().max().copied().unwrap_or(0);
+            block.planes = u64::BITS - max.leading_zeros();
+        }
+        let planes = blocks.iter().map(|b| b.planes).max().unwrap_or(0);
+
+        let mut header = BitWriter::new(Vec::new());
+        // Writing to a vector never fails
+        header.write_exp_golomb(planes as u64, 0).unwrap();
+        header.write_exp_golomb(blocks.len() as u64, 0).unwrap();
+        for block in &blocks {
+            header
+                .write_exp_golomb((planes - block.planes) as u64, 0)
+                .unwrap();
+        }
+        let mut out = header.finish().unwrap();
+
+        // Coding bits the magnitudes already have, the encoder goes through the states of the decoder
+        for p in (0..planes).rev() {
+            for pass in [Pass::Significance, Pass::Refinement, Pass::Cleanup] {
+                for block in &mut blocks {
+                    if p >= block.planes || block.is_empty(pass) {
+                        continue;
+                    }
+                    let mut encoder = MqEncoder::new();
+                    block.code_pass(&mut encoder, pass, p);
+                    let bytes = encoder.finish();
+                    write_length(&mut out, bytes.len());
+                    out.extend_from_slice(&bytes);
+                }
+            }
+            blocks.iter_mut().for_each(Block::end_plane);
+        }
+        out
+    }
+
+    /// Reads the header, returning the number of bitplanes and the length of the header.
+    fn read_header(data: &[u8], blocks: &mut [Block]) -> io::Result<(u32, usize)> {
+        let mut reader = BitReader::new(data);
+        let planes = reader.read_exp_golomb(0)?;
+        if planes > u64::BITS as u64 {
+            return Err(invalid("More than 64 bitplanes"));
+        }
+        if reader.read_exp_golomb(0)? != blocks.len() as u64 {
+            return Err(invalid("Number of code blocks does not match the layout"));
+        }
+        for block in blocks {
+            let skipped = reader.read_exp_golomb(0)?;
+            if skipped > planes {
+                return Err(invalid("Code block with a negative number of bitplanes"));
+            }
+            block.planes = (planes - skipped) as u32;
+        }
+        reader.align();
+        Ok((planes as u32, (reader.bits_read() / 8) as usize))
+    }
+
+    /// Decodes the coefficients of a stream written by [`Ebcot::encode`] with the same layout.
+    ///
+    /// Decoding stops at the first incomplete pass, and returns the number of passes decoded.
+    /// Only an invalid header is an error.
+    pub fn decode<T: Sample>(
+        &self,
+        data: &[u8],
+        layout: &SubbandLayout,
+        mut img: ImageViewMut<'_, T>,
+    ) -> io::Result<usize> {
+        let mut blocks = self.blocks(layout);
+        let (planes, mut pos) = Self::read_header(data, &mut blocks)?;
+
+        let mut passes = 0;
+        'planes: for p in (0..planes).rev() {
+            for pass in [Pass::Significance, Pass::Refinement, Pass::Cleanup] {
+                for block in &mut blocks {
+                    // An empty refinement pass has no significant coefficient, so no `known` to update
+                    if p >= block.planes || block.is_empty(pass) {
+                        continue;
+                    }
+                    let Some(len) = read_length(data, &mut pos) else {
+                        break 'planes;
+                    };
+                    let Some(bytes) = data.get(pos..pos.saturating_add(len)) else {
+                        break 'planes;
+                    };
+                    pos += len;
+                    block.code_pass(&mut MqDecoder::new(bytes), pass, p);
+                    passes += 1;
+                }
+            }
+            blocks.iter_mut().for_each(Block::end_plane);
+        }
+
+        for block in &blocks {
+            let mut view = img.subview_mut(block.x, block.y, block.width, block.height);
+            for (y, row) in view.rows_mut().enumerate() {
+                for (x, c) in row.iter_mut().enumerate() {
+                    *c = T::saturating_from_i64(block.value(y * block.width + x));
+                }
+            }
+        }
+        Ok(passes)
+    }
+
+    /// Longest prefix of the stream made of whole passes and fitting in `budget` bytes.
+    ///
+    /// The header is always kept, even if it does not fit in the budget.
+    pub fn truncate(
+        &self,
+        data: &[u8],
+        layout: &SubbandLayout,
+        budget: usize,
+    ) -> io::Result<usize> {
+        let mut blocks = self.blocks(layout);
+        let (_, mut end) = Self::read_header(data, &mut blocks)?;
+        let mut pos = end;
+        while let Some(len) = read_length(data, &mut pos) {
+            pos = pos.saturating_add(len);
+            if pos > budget.min(data.len()) {
+                break;
+            }
+            end = pos;
+        }
+        Ok(end)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        dwt::{daub::Daub53, Dwt2, SubbandLayout},
+        memory::Image,
+    };
+
+    use super::Ebcot;
+
+    fn decomposed() -> (Image<i32>, SubbandLayout) {
+        let mut image = Image::with_fn(75, 50, |x, y| {
+            let (x, y) = (x as i32, y as i32);
+            ((x - 30).pow(2) + (y - 20).pow(2)) / 16 + if (x / 8 + y / 8) % 2 == 0 { 40 } else { 0 }
+        });
+        let mut tmp = image.clone();
+        let layout = Daub53.decompose(image.view_mut(), tmp.view_mut(), 3);
+        (image, layout)
+    }
+
+    fn squared_error(a: &Image<i32>, b: &Image<i32>) -> i64 {
+        let mut sum = 0;
+        for (a, b) in a.rows().zip(b.rows()) {
+            for (&a, &b) in a.iter().zip(b) {
+                sum += ((a - b) as i64).pow(2);
+            }
+        }
+        sum
+    }
+
+    #[test]
+    fn lossless() {
+        let (image, layout) = decomposed();
+        for ebcot in [Ebcot::default(), Ebcot::default().with_block_size(16)] {
+            let data = ebcot.encode(image.view(), &layout);
+            assert!(data.len() < image.size(), "{}", data.len());
+
+            let mut decoded = Image::with_value(75, 50, &0);
+            ebcot.decode(&data, &layout, decoded.view_mut()).unwrap();
+            assert_eq!(squared_error(&decoded, &image), 0);
+        }
+    }
+
+    #[test]
+    fn embedded() {
+        let (image, layout) = decomposed();
+        let ebcot = Ebcot::default().with_block_size(32);
+        let data = ebcot.encode(image.view(), &layout);
+
+        let header = ebcot.truncate(&data, &layout, 0).unwrap();
+        let mut last = i64::MAX;
+        let mut last_passes = 0;
+        for budget in [0, 20, 50, 100, 200, 400, 800, data.len()] {
+            let len = ebcot.truncate(&data, &layout, budget).unwrap();
+            assert!(len <= budget.max(header) && len <= data.len());
+
+            let mut decoded = Image::with_value(75, 50, &0);
+            let passes = ebcot
+                .decode(&data[..len], &layout, decoded.view_mut())
+                .unwrap();
+            let error = squared_error(&decoded, &image);
+            assert!(passes >= last_passes && error <= last, "{budget}: {error}");
+            last = error;
+            last_passes = passes;
+        }
+        assert_eq!(last, 0);
+
+        // Cutting inside a pass decodes the passes before it
+        for len in (10..data.len()).step_by(97) {
+            let mut decoded = Image::with_value(75, 50, &0);
+            ebcot
+                .decode(&data[..len], &layout, decoded.view_mut())
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn midpoint() {
+        // Only the cleanup pass of bitplane 3 and the refinement passes after it code -13 = -0b1101
+        let layout = SubbandLayout::new(1, 1, 0);
+        let image = Image::with_value(1, 1, &-13);
+        let ebcot = Ebcot::default();
+        let data = ebcot.encode(image.view(), &layout);
+
+        let mut values = Vec::new();
+        for len in 0..=data.len() {
+            let mut decoded = Image::with_value(1, 1, &0);
+            let Ok(passes) = ebcot.decode(&data[..len], &layout, decoded.view_mut()) else {
+                continue;
+            };
+            let value = decoded.rows().next().unwrap()[0];
+            if values.last() != Some(&(passes, value)) {
+                values.push((passes, value));
+            }
+        }
+        assert_eq!(values, [(0, 0), (1, -12), (2, -14), (3, -13), (4, -13)]);
+    }
+
+    #[test]
+    fn extremes() {
+        let layout = SubbandLayout::new(4, 1, 0);
+        let image = Image::with_fn(4, 1, |x, _| [i64::MIN, i64::MAX, -1, 0][x]);
+        let ebcot = Ebcot::default();
+        let data = ebcot.encode(image.view(), &layout);
+        for len in 0..=data.len() {
+            let mut decoded = Image::with_value(4, 1, &0);
+            _ = ebcot.decode(&data[..len], &layout, decoded.view_mut());
+        }
+        let mut decoded = Image::with_value(4, 1, &0);
+        ebcot.decode(&data, &layout, decoded.view_mut()).unwrap();
+        assert_eq!(decoded.rows().next().unwrap(), image.rows().next().unwrap());
+    }
+
+    #[test]
+    fn invalid_header() {
+        let (image, layout) = decomposed();
+        let ebcot = Ebcot::default();
+        let data = ebcot.encode(image.view(), &layout);
+        let mut decoded = Image::with_value(75, 50, &0);
+        let other = SubbandLayout::new(75, 50, 2);
+        assert!(ebcot.decode(&data, &other, decoded.view_mut()).is_err());
+        assert!(ebcot.decode(&[], &layout, decoded.view_mut()).is_err());
+    }
+}
@@ -0,0 +1,295 @@
+//! Embedded zerotree wavelet coder, from the following paper
+//!
+//! > Shapiro, Jerome M.
+//! > "Embedded image coding using zerotrees of wavelet coefficients."
+//! > IEEE Transactions on signal processing 41.12 (1993): 3445-3462.
+
+use std::io;
+
+use crate::{
+    dwt::SubbandLayout,
+    memory::{ImageView, ImageViewMut},
+    numeric::Sample,
+};
+
+use super::zerotree::{reconstruct, Coder, Decoder, Encoder, Trees};
+
+/// Coding state, shared by the encoder and the decoder.
+struct State {
+    trees: Trees,
+    /// Scan order: band by band from the `LL` band, so that parents come before their children
+    order: Vec<usize>,
+    magnitudes: Vec<u64>,
+    negative: Vec<bool>,
+    /// Lowest bitplane known of each significant coefficient
+    known: Vec<u32>,
+    significant: Vec<bool>,
+    /// Largest magnitude of the descendants not yet significant, only known by the encoder
+    descendants: Option<Vec<u64>>,
+    /// Descendants of a zerotree root of the current dominant pass
+    skipped: Vec<bool>,
+    /// Subordinate list: the significant coefficients, in the order they were found
+    subordinate: Vec<usize>,
+}
+
+impl State {
+    fn new(layout: &SubbandLayout) -> Self {
+        let trees = Trees::new(layout);
+        let size = trees.len();
+        let order = layout
+            .bands()
+            .flat_map(|band| {
+                (band.y..band.y + band.height).flat_map(move |y| {
+                    (band.x..band.x + band.width).map(move |x| y * layout.width() + x)
+                })
+            })
+            .collect();
+        Self {
+            trees,
+            order,
+            magnitudes: vec![0; size],
+            negative: vec![false; size],
+            known: vec![0; size],
+            significant: vec![false; size],
+            descendants: None,
+            skipped: vec![false; size],
+            subordinate: Vec::new(),
+        }
+    }
+
+    /// Computes the maxima of the insignificant descendants, from the finest coefficients to the roots.
+    fn compute_descendants(&mut self) {
+        let Some(descendants) = &mut self.descendants else {
+            return;
+        };
+        for &i in self.order.iter().rev() {
+            let max = self
+                .trees
+                .children(i)
+                .map(|c| {
+                    let magnitude = if self.significant[c] {
+                        0
+                    } else {
+                        self.magnitudes[c]
+                    };
+                    magnitude.max(descendants[c])
+                })
+                .max()
+                .unwrap_or(0);
+            descendants[i] = max;
+        }
+    }
+
+    /// Codes the insignificant coefficients outside of the zerotrees: positive, negative, isolated zero or zerotree root.
+    ///
+    /// The symbols are binarized as a significance bit, then either a sign bit, or for coefficients with children,
+    /// a bit telling whether all the descendants are insignificant.
+    fn dominant_pass(&mut self, coder: &mut impl Coder, n: u32) -> Option<()> {
+        self.compute_descendants();
+        self.skipped.fill(false);
+        for k in 0..self.order.len() {
+            let i = self.order[k];
+            if self.skipped[i] {
+                self.skip_children(i);
+                continue;
+            }
+            if self.significant[i] {
+                continue;
+            }
+            if coder.code(self.magnitudes[i] >> n != 0)? {
+                self.negative[i] = coder.code(self.negative[i])?;
+                self.magnitudes[i] |= 1 << n;
+                self.known[i] = n;
+                self.significant[i] = true;
+                self.subordinate.push(i);
+            } else if self.trees.has_children(i) {
+                let zerotree = self.descendants.as_ref().is_some_and(|d| d[i] >> n == 0);
+                if coder.code(zerotree)? {
+                    self.skip_children(i);
+                }
+            }
+        }
+        Some(())
+    }
+
+    fn skip_children(&mut self, i: usize) {
+        for c in self.trees.children(i) {
+            self.skipped[c] = true;
+        }
+    }
+
+    fn subordinate_pass(&mut self, coder: &mut impl Coder, n: u32, len: usize) -> Option<()> {
+        for k in 0..len {
+            let i = self.subordinate[k];
+            if coder.code(self.magnitudes[i] >> n & 1 != 0)? {
+                self.magnitudes[i] |= 1 << n;
+            }
+            self.known[i] = n;
+        }
+        Some(())
+    }
+
+    fn code(&mut self, coder: &mut impl Coder, planes: u32) {
+        for n in (0..planes).rev() {
+            let len = self.subordinate.len();
+            if self.dominant_pass(coder, n).is_none()
+                || self.subordinate_pass(coder, n, len).is_none()
+            {
+                return;
+            }
+        }
+    }
+}
+
+/// Embedded zerotree codec of a decomposed image.
+///
+/// Bitplanes are coded from the most significant one, each with a dominant pass finding the newly significant
+/// coefficients, where a zerotree root symbol codes a coefficient and all its descendants as insignificant,
+/// then a subordinate pass refining the previously significant coefficients.
+/// The stream can be stopped after any bit: the header gives the number of bitplanes and of coded bits.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Ezw;
+
+impl Ezw {
+    /// Encodes every bitplane, down to lossless.
+    pub fn encode<T: Sample>(&self, img: ImageView<'_, T>, layout: &SubbandLayout) -> Vec<u8> {
+        self.encode_with_budget(img, layout, u64::MAX)
+    }
+
+    /// Encodes the coefficients, stopping after `budget` bits, the header excluded.
+    pub fn encode_with_budget<T: Sample>(
+        &self,
+        img: ImageView<'_, T>,
+        layout: &SubbandLayout,
+        budget: u64,
+    ) -> Vec<u8> {
+        let mut state = State::new(layout);
+        for (y, row) in img.rows().enumerate() {
+            for (x, &c) in row.iter().enumerate() {
+                let i = y * layout.width() + x;
+                state.magnitudes[i] = c.to_i64().unsigned_abs();
+                state.negative[i] = c.to_i64() < 0;
+            }
+        }
+        state.descendants = Some(vec![0; state.trees.len()]);
+        let max = state.magnitudes.iter().max().copied().unwrap_or(0);
+        let planes = u64::BITS - max.leading_zeros();
+
+        let mut encoder = Encoder::new(budget);
+        state.code(&mut encoder, planes);
+        encoder.finish(planes)
+    }
+
+    /// Decodes a stream written by [`Ezw::encode`] with the same layout, returning the number of bits decoded.
+    ///
+    /// A truncated stream decodes into a lower quality image, only a truncated header is an error.
+    pub fn decode<T: Sample>(
+        &self,
+        data: &[u8],
+        layout: &SubbandLayout,
+        mut img: ImageViewMut<'_, T>,
+    ) -> io::Result<u64> {
+        let (planes, mut decoder) = Decoder::new(data)?;
+        let mut state = State::new(layout);
+        state.code(&mut decoder, planes);
+
+        for (y, row) in img.rows_mut().enumerate() {
+            for (x, c) in row.iter_mut().enumerate() {
+                let i = y * layout.width() + x;
+                let value = reconstruct(state.magnitudes[i], state.negative[i], state.known[i]);
+                *c = T::saturating_from_i64(value);
+            }
+        }
+        Ok(decoder.bits_read())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        dwt::{daub::Daub97, haar::Haar, Dwt2, SubbandLayout},
+        entropy::spiht::Spiht,
+        memory::Image,
+    };
+
+    use super::Ezw;
+
+    fn squared_error(a: &Image<i32>, b: &Image<i32>) -> i64 {
+        let mut sum = 0;
+        for (a, b) in a.rows().zip(b.rows()) {
+            for (&a, &b) in a.iter().zip(b) {
+                sum += (a as i64 - b as i64).pow(2);
+            }
+        }
+        sum
+    }
+
+    #[test]
+    fn lossless() {
+        for (width, height, levels) in [(64, 64, 4), (45, 31, 3), (7, 1, 2), (20, 20, 0)] {
+            let mut image = Image::with_fn(width, height, |x, y| {
+                ((x as i32 - 20).pow(2) - (y as i32 - 10).pow(2)) / 8
+            });
+            let mut tmp = image.clone();
+            let layout = Haar.decompose(image.view_mut(), tmp.view_mut(), levels);
+
+            let data = Ezw.encode(image.view(), &layout);
+            let mut decoded = Image::with_value(width, height, &0);
+            Ezw.decode(&data, &layout, decoded.view_mut()).unwrap();
+            assert_eq!(squared_error(&decoded, &image), 0, "{width}x{height}");
+        }
+    }
+
+    #[test]
+    fn extremes() {
+        let layout = SubbandLayout::new(4, 1, 0);
+        let image = Image::with_fn(4, 1, |x, _| [i64::MIN, i64::MAX, -1, 0][x]);
+        let row = image.rows().next().unwrap();
+
+        let data = Ezw.encode(image.view(), &layout);
+        let mut decoded = Image::with_value(4, 1, &0);
+        Ezw.decode(&data, &layout, decoded.view_mut()).unwrap();
+        assert_eq!(decoded.rows().next().unwrap(), row);
+
+        // Only the most significant bitplane is known: the middle of the interval of `i64::MIN`,
+        // `-(2^63 + 2^62)`, does not fit and is clamped
+        let data = Ezw.encode_with_budget(image.view(), &layout, 8);
+        let mut decoded = Image::with_value(4, 1, &0);
+        Ezw.decode(&data, &layout, decoded.view_mut()).unwrap();
+        assert_eq!(decoded.rows().next().unwrap()[0], i64::MIN);
+    }
+
+    #[test]
+    fn bit_budget() {
+        let mut image = Image::with_fn(50, 40, |x, y| {
+            let (x, y) = (x as i32, y as i32);
+            (x * x + y * y) / 16 + if (x / 8 + y / 8) % 2 == 0 { 60 } else { 0 }
+        });
+        let mut tmp = image.clone();
+        let layout = Daub97.decompose(image.view_mut(), tmp.view_mut(), 3);
+        let full = Ezw.encode(image.view(), &layout);
+        let mut decoded = Image::with_value(50, 40, &0);
+        let total = Ezw.decode(&full, &layout, decoded.view_mut()).unwrap();
+        assert_eq!(squared_error(&decoded, &image), 0);
+
+        let mut last = i64::MAX;
+        for budget in [0, 1, 7, 100, 1001, 5000, total - 1] {
+            let data = Ezw.encode_with_budget(image.view(), &layout, budget);
+            let mut decoded = Image::with_value(50, 40, &0);
+            let bits = Ezw.decode(&data, &layout, decoded.view_mut()).unwrap();
+            assert_eq!(bits, budget);
+            let error = squared_error(&decoded, &image);
+            assert!(error <= last, "{budget}: {error}");
+            last = error;
+        }
+
+        // Both zerotree coders code the same trees, within a small factor
+        let spiht = Spiht.encode(image.view(), &layout);
+        assert!(
+            full.len() < spiht.len() * 3 / 2,
+            "{} {}",
+            full.len(),
+            spiht.len()
+        );
+    }
+}
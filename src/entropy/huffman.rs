@@ -0,0 +1,435 @@
+//! Canonical Huffman coding of the quantized subbands, with a code table built per subband.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, HashMap},
+    io::{self, Read, Write},
+};
+
+use crate::{
+    bitio::{BitReader, BitWriter},
+    dwt::SubbandLayout,
+    memory::{ImageView, ImageViewMut},
+    numeric::Sample,
+};
+
+/// Longest code, the frequencies are scaled down until the optimal code fits.
+pub const MAX_LENGTH: u32 = 32;
+
+/// Number of bits indexing the lookup table of the decoder, longer codes are decoded bit by bit.
+const LOOKUP_BITS: u32 = 8;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Number of occurrences of each symbol.
+pub type Histogram = BTreeMap<i64, u64>;
+
+/// Histogram of the coefficients of an image.
+pub fn histogram<T: Sample>(img: ImageView<'_, T>) -> Histogram {
+    let mut histogram = Histogram::new();
+    for row in img.into_rows() {
+        for &x in row {
+            *histogram.entry(x.to_i64()).or_default() += 1;
+        }
+    }
+    histogram
+}
+
+/// Code lengths of an optimal prefix code of the symbols.
+fn code_lengths(counts: &[u64]) -> Vec<u32> {
+    let n = counts.len();
+    let mut parent = vec![usize::MAX; n];
+    let mut heap = counts
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| Reverse((c, i)))
+        .collect::<BinaryHeap<_>>();
+    while heap.len() > 1 {
+        let Reverse((a, i)) = heap.pop().unwrap();
+        let Reverse((b, j)) = heap.pop().unwrap();
+        let node = parent.len();
+        parent.push(usize::MAX);
+        parent[i] = node;
+        parent[j] = node;
+        heap.push(Reverse((a.saturating_add(b), node)));
+    }
+    (0..n)
+        .map(|mut i| {
+            let mut length = 0;
+            while parent[i] != usize::MAX {
+                i = parent[i];
+                length += 1;
+            }
+            length
+        })
+        .collect()
+}
+
+/// Canonical prefix code: the codes of a given length are consecutive, in increasing order of the symbols,
+/// so the table is fully described by the number of codes of each length and the sorted symbols.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HuffmanTable {
+    /// Symbols sorted by code length, then by value
+    symbols: Vec<i64>,
+    /// Number of codes of each length, from 0
+    counts: Vec<u64>,
+    codes: HashMap<i64, (u64, u32)>,
+    /// Symbol and code length of each value of the next `lookup_bits` bits, `None` for longer codes
+    lookup: Vec<Option<(i64, u32)>>,
+    lookup_bits: u32,
+}
+
+impl HuffmanTable {
+    /// Optimal code of the symbols of `histogram` with a nonzero count, limited to [`MAX_LENGTH`] bits.
+    ///
+    /// With a single symbol, its code is empty.
+    pub fn new(histogram: &Histogram) -> Self {
+        let symbols = histogram
+            .iter()
+            .filter(|&(_, &c)| c > 0)
+            .map(|(&s, _)| s)
+            .collect::<Vec<_>>();
+        let mut counts = histogram
+            .values()
+            .copied()
+            .filter(|&c| c > 0)
+            .collect::<Vec<_>>();
+        let mut lengths = code_lengths(&counts);
+        while lengths.iter().any(|&l| l > MAX_LENGTH) {
+            for c in &mut counts {
+                *c = c.div_ceil(2);
+            }
+            lengths = code_lengths(&counts);
+        }
+
+        let mut sorted = symbols.into_iter().zip(lengths).collect::<Vec<_>>();
+        sorted.sort_by_key(|&(s, l)| (l, s));
+        let max = sorted.last().map_or(0, |&(_, l)| l);
+        let mut counts = vec![0; max as usize + 1];
+        for &(_, l) in &sorted {
+            counts[l as usize] += 1;
+        }
+        Self::from_canonical(sorted.into_iter().map(|(s, _)| s).collect(), counts)
+            .expect("Huffman code lengths satisfy the Kraft inequality")
+    }
+
+    fn from_canonical(symbols: Vec<i64>, counts: Vec<u64>) -> Option<Self> {
+        let mut codes = HashMap::with_capacity(symbols.len());
+        let mut code = 0u64;
+        let mut index = 0;
+        for (length, &count) in counts.iter().enumerate() {
+            if length > 0 {
+                code <<= 1;
+            }
+            if code.checked_add(count)? > 1 << length {
+                return None;
+            }
+            for &symbol in &symbols[index..index + count as usize] {
+                codes.insert(symbol, (code, length as u32));
+                code += 1;
+            }
+            index += count as usize;
+        }
+
+        let lookup_bits = (counts.len().saturating_sub(1) as u32).min(LOOKUP_BITS);
+        let mut lookup = vec![None; 1 << lookup_bits];
+        for (&symbol, &(code, length)) in &codes {
+            if length <= lookup_bits {
+                let shift = lookup_bits - length;
+                let start = (code << shift) as usize;
+                lookup[start..start + (1 << shift)].fill(Some((symbol, length)));
+            }
+        }
+        Some(Self {
+            symbols,
+            counts,
+            codes,
+            lookup,
+            lookup_bits,
+        })
+    }
+
+    /// Length of the longest code.
+    pub fn max_length(&self) -> u32 {
+        self.counts.len().saturating_sub(1) as u32
+    }
+
+    /// Code and code length of a symbol, `None` if the symbol is not in the table.
+    pub fn code(&self, symbol: i64) -> Option<(u64, u32)> {
+        self.codes.get(&symbol).copied()
+    }
+
+    /// Number of bits of the symbols of `histogram` with this code, the table excluded.
+    ///
+    /// # Panics
+    ///
+    /// If a symbol of the histogram is not in the table.
+    pub fn cost(&self, histogram: &Histogram) -> u64 {
+        histogram
+            .iter()
+            .filter(|&(_, &c)| c > 0)
+            .map(|(&s, &c)| c * self.code(s).expect("Symbol not in the Huffman table").1 as u64)
+            .sum()
+    }
+
+    /// Writes the table: the number of codes of each length, then the sorted symbols as differences.
+    pub fn write<W: Write>(&self, writer: &mut BitWriter<W>) -> io::Result<()> {
+        writer.write_exp_golomb(self.counts.len() as u64, 0)?;
+        for &count in &self.counts {
+            writer.write_exp_golomb(count, 0)?;
+        }
+        let mut previous = 0i64;
+        for &symbol in &self.symbols {
+            writer.write_signed_exp_golomb(symbol.wrapping_sub(previous), 0)?;
+            previous = symbol;
+        }
+        Ok(())
+    }
+
+    /// Reads a table written by [`HuffmanTable::write`].
+    pub fn read<R: Read>(reader: &mut BitReader<R>) -> io::Result<Self> {
+        let lengths = reader.read_exp_golomb(0)?;
+        if lengths > MAX_LENGTH as u64 + 1 {
+            return Err(invalid("Huffman code longer than the maximal length"));
+        }
+        let counts = (0..lengths)
+            .map(|_| reader.read_exp_golomb(0))
+            .collect::<io::Result<Vec<_>>>()?;
+        // Bounded by the Kraft inequality checked below
+        let total = counts.iter().fold(0u64, |a, &c| a.saturating_add(c));
+        if total > 1 << MAX_LENGTH {
+            return Err(invalid("Oversubscribed Huffman table"));
+        }
+        let mut symbols = Vec::new();
+        let mut previous = 0i64;
+        for _ in 0..total {
+            previous = previous.wrapping_add(reader.read_signed_exp_golomb(0)?);
+            symbols.push(previous);
+        }
+        Self::from_canonical(symbols, counts).ok_or_else(|| invalid("Oversubscribed Huffman table"))
+    }
+
+    /// # Panics
+    ///
+    /// If the symbol is not in the table.
+    pub fn encode<W: Write>(&self, symbol: i64, writer: &mut BitWriter<W>) -> io::Result<()> {
+        let (code, length) = self.code(symbol).expect("Symbol not in the Huffman table");
+        writer.write_bits(code, length)
+    }
+
+    /// Decodes a symbol, looking up the short codes in a table indexed by the next bits.
+    ///
+    /// Longer codes are decoded bit by bit, comparing the code against the range of the codes of each length.
+    pub fn decode<R: Read>(&self, reader: &mut BitReader<R>) -> io::Result<i64> {
+        let (bits, available) = reader.peek_bits(self.lookup_bits)?;
+        if let Some((symbol, length)) = self.lookup[bits as usize] {
+            if length <= available {
+                reader.read_bits(length)?;
+                return Ok(symbol);
+            }
+        }
+
+        let mut code = 0u64;
+        let mut first = 0u64;
+        let mut index = 0u64;
+        for (length, &count) in self.counts.iter().enumerate() {
+            if length > 0 {
+                code = (code << 1) | reader.read_bit()? as u64;
+                first <<= 1;
+            }
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            first += count;
+            index += count;
+        }
+        Err(invalid("Invalid Huffman code"))
+    }
+}
+
+/// Coder of the subbands with a canonical Huffman code per subband, written before its symbols.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Huffman;
+
+impl Huffman {
+    /// Encodes a single subband, table included.
+    pub fn encode_band<T: Sample, W: Write>(
+        &self,
+        band: ImageView<'_, T>,
+        writer: &mut BitWriter<W>,
+    ) -> io::Result<()> {
+        let table = HuffmanTable::new(&histogram(band));
+        table.write(writer)?;
+        for row in band.into_rows() {
+            for &x in row {
+                table.encode(x.to_i64(), writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes a subband written by [`Huffman::encode_band`].
+    pub fn decode_band<T: Sample, R: Read>(
+        &self,
+        reader: &mut BitReader<R>,
+        mut band: ImageViewMut<'_, T>,
+    ) -> io::Result<()> {
+        let table = HuffmanTable::read(reader)?;
+        for row in band.rows_mut() {
+            for x in row {
+                *x = T::checked_from_i64(table.decode(reader)?)
+                    .ok_or_else(|| invalid("Coefficient out of the sample range"))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Encodes all the subbands of a decomposed image, from the `LL` band to the finest details.
+    pub fn encode<T: Sample>(&self, img: ImageView<'_, T>, layout: &SubbandLayout) -> Vec<u8> {
+        let mut writer = BitWriter::new(Vec::new());
+        // Writing to a vector never fails
+        for band in layout.bands() {
+            self.encode_band(band.view(img), &mut writer).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    /// Decodes a stream written by [`Huffman::encode`] with the same layout.
+    pub fn decode<T: Sample>(
+        &self,
+        data: &[u8],
+        layout: &SubbandLayout,
+        mut img: ImageViewMut<'_, T>,
+    ) -> io::Result<()> {
+        let mut reader = BitReader::new(data);
+        for band in layout.bands() {
+            self.decode_band(&mut reader, band.view_mut(img.view_mut()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::ErrorKind;
+
+    use crate::{
+        bitio::{BitReader, BitWriter},
+        dwt::{daub::Daub53, Dwt2},
+        memory::Image,
+        quant::{DeadZone, SubbandQuantizer},
+    };
+
+    use super::{histogram, Histogram, Huffman, HuffmanTable, MAX_LENGTH};
+
+    #[test]
+    fn canonical_codes() {
+        let histogram = Histogram::from([(-1, 1), (5, 1), (0, 4), (1, 2)]);
+        let table = HuffmanTable::new(&histogram);
+        assert_eq!(table.code(0), Some((0b0, 1)));
+        assert_eq!(table.code(1), Some((0b10, 2)));
+        assert_eq!(table.code(-1), Some((0b110, 3)));
+        assert_eq!(table.code(5), Some((0b111, 3)));
+        assert_eq!(table.code(2), None);
+        assert_eq!(table.cost(&histogram), 4 + 4 + 3 + 3);
+
+        // The last code ends before the lookup bits
+        let mut writer = BitWriter::new(Vec::new());
+        for symbol in [5, 0, 1, -1, 0] {
+            table.encode(symbol, &mut writer).unwrap();
+        }
+        let data = writer.finish().unwrap();
+        let mut reader = BitReader::new(data.as_slice());
+        for symbol in [5, 0, 1, -1, 0] {
+            assert_eq!(table.decode(&mut reader).unwrap(), symbol);
+        }
+        assert_eq!(reader.bits_read(), 3 + 1 + 2 + 3 + 1);
+
+        let table = HuffmanTable::new(&Histogram::from([(7, 100)]));
+        assert_eq!(table.code(7), Some((0, 0)));
+        assert_eq!(table.max_length(), 0);
+    }
+
+    #[test]
+    fn length_limit() {
+        // Fibonacci frequencies give the deepest trees
+        let mut histogram = Histogram::new();
+        let (mut a, mut b) = (1u64, 1u64);
+        for symbol in 0..60 {
+            histogram.insert(symbol, a);
+            (a, b) = (b, a + b);
+        }
+        let table = HuffmanTable::new(&histogram);
+        assert!(table.max_length() <= MAX_LENGTH);
+
+        let mut writer = BitWriter::new(Vec::new());
+        table.write(&mut writer).unwrap();
+        for symbol in 0..60 {
+            table.encode(symbol, &mut writer).unwrap();
+        }
+        let data = writer.finish().unwrap();
+
+        let mut reader = BitReader::new(data.as_slice());
+        let read = HuffmanTable::read(&mut reader).unwrap();
+        assert_eq!(read, table);
+        for symbol in 0..60 {
+            assert_eq!(read.decode(&mut reader).unwrap(), symbol);
+        }
+    }
+
+    #[test]
+    fn quantized_subbands() {
+        let input = Image::with_fn(61, 47, |x, y| {
+            ((x as i16 - 30).pow(2) + (y as i16 - 20).pow(2)) / 8 + ((x * 7 + y * 3) % 13) as i16
+        });
+        let mut image = input.clone();
+        let mut tmp = input.clone();
+        let layout = Daub53.decompose(image.view_mut(), tmp.view_mut(), 3);
+        SubbandQuantizer::new(DeadZone::new(4)).quantize(&layout, image.view_mut());
+
+        let data = Huffman.encode(image.view(), &layout);
+        let mut decoded = Image::with_value(61, 47, &0i16);
+        Huffman.decode(&data, &layout, decoded.view_mut()).unwrap();
+        assert!(decoded.rows().eq(image.rows()));
+
+        // The payload matches the cost of the tables
+        let payload = layout
+            .bands()
+            .map(|band| {
+                let histogram = histogram(band.view(image.view()));
+                HuffmanTable::new(&histogram).cost(&histogram)
+            })
+            .sum::<u64>();
+        assert!(payload < 61 * 47 * 3, "{payload}");
+        assert!((data.len() as u64) * 8 > payload);
+    }
+
+    #[test]
+    fn invalid_tables() {
+        // Three codes of length 1
+        let mut writer = BitWriter::new(Vec::new());
+        for n in [2, 0, 3, 0, 1, 1] {
+            writer.write_exp_golomb(n, 0).unwrap();
+        }
+        let data = writer.finish().unwrap();
+        let err = HuffmanTable::read(&mut BitReader::new(data.as_slice())).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // Incomplete code: the code 1 is not assigned
+        let table = HuffmanTable::new(&Histogram::from([(3, 10)]));
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_exp_golomb(2, 0).unwrap();
+        for n in [0, 1, 3] {
+            writer.write_exp_golomb(n, 0).unwrap();
+        }
+        writer.write_bit(true).unwrap();
+        let data = writer.finish().unwrap();
+        let mut reader = BitReader::new(data.as_slice());
+        let read = HuffmanTable::read(&mut reader).unwrap();
+        assert_ne!(read, table);
+        let err = read.decode(&mut reader).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,9 @@
+//! Entropy coders of the quantized subbands.
+
+pub mod ebcot;
+pub mod ezw;
+pub mod huffman;
+pub mod mq;
+pub mod rice;
+pub mod spiht;
+mod zerotree;
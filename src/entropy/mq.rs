@@ -0,0 +1,381 @@
+//! Adaptive binary arithmetic coder of JPEG 2000, from the following standard
+//!
+//! > ITU-T Rec. T.800 | ISO/IEC 15444-1, "JPEG 2000 image coding system: Core coding system",
+//! > Annex C "Arithmetic entropy coding procedure".
+
+/// Probability estimation state machine: `(Qe, next state after an MPS, next state after an LPS, switch MPS)`.
+const STATES: [(u32, u8, u8, bool); 47] = [
+    (0x5601, 1, 1, true),
+    (0x3401, 2, 6, false),
+    (0x1801, 3, 9, false),
+    (0x0ac1, 4, 12, false),
+    (0x0521, 5, 29, false),
+    (0x0221, 38, 33, false),
+    (0x5601, 7, 6, true),
+    (0x5401, 8, 14, false),
+    (0x4801, 9, 14, false),
+    (0x3801, 10, 14, false),
+    (0x3001, 11, 17, false),
+    (0x2401, 12, 18, false),
+    (0x1c01, 13, 20, false),
+    (0x1601, 29, 21, false),
+    (0x5601, 15, 14, true),
+    (0x5401, 16, 14, false),
+    (0x5101, 17, 15, false),
+    (0x4801, 18, 16, false),
+    (0x3801, 19, 17, false),
+    (0x3401, 20, 18, false),
+    (0x3001, 21, 19, false),
+    (0x2801, 22, 19, false),
+    (0x2401, 23, 20, false),
+    (0x2201, 24, 21, false),
+    (0x1c01, 25, 22, false),
+    (0x1801, 26, 23, false),
+    (0x1601, 27, 24, false),
+    (0x1401, 28, 25, false),
+    (0x1201, 29, 26, false),
+    (0x1101, 30, 27, false),
+    (0x0ac1, 31, 28, false),
+    (0x09c1, 32, 29, false),
+    (0x08a1, 33, 30, false),
+    (0x0521, 34, 31, false),
+    (0x0441, 35, 32, false),
+    (0x02a1, 36, 33, false),
+    (0x0221, 37, 34, false),
+    (0x0141, 38, 35, false),
+    (0x0111, 39, 36, false),
+    (0x0085, 40, 37, false),
+    (0x0049, 41, 38, false),
+    (0x0025, 42, 39, false),
+    (0x0015, 43, 40, false),
+    (0x0009, 44, 41, false),
+    (0x0005, 45, 42, false),
+    (0x0001, 45, 43, false),
+    (0x5601, 46, 46, false),
+];
+
+/// Adaptive probability of a binary symbol, shared by the encoder and the decoder.
+///
+/// Both sides must use the same contexts, initialized the same way, in the same order.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Context {
+    state: u8,
+    mps: bool,
+}
+
+impl Context {
+    /// Non-adaptive context with both symbols equally likely.
+    pub const UNIFORM: Self = Self::new(46, false);
+
+    /// Context starting at `state` of the estimation state machine, `mps` being the most probable symbol.
+    pub const fn new(state: u8, mps: bool) -> Self {
+        assert!((state as usize) < STATES.len(), "Invalid MQ state");
+        Self { state, mps }
+    }
+
+    fn qe(&self) -> u32 {
+        STATES[self.state as usize].0
+    }
+
+    fn on_mps(&mut self) {
+        self.state = STATES[self.state as usize].1;
+    }
+
+    fn on_lps(&mut self) {
+        let (_, _, next, switch) = STATES[self.state as usize];
+        self.mps ^= switch;
+        self.state = next;
+    }
+}
+
+/// Encoding half of the MQ coder.
+#[derive(Debug, Clone)]
+pub struct MqEncoder {
+    a: u32,
+    c: u32,
+    ct: u32,
+    /// Output bytes, after a leading byte that receives no carry and is dropped at the end
+    out: Vec<u8>,
+}
+
+impl Default for MqEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MqEncoder {
+    pub fn new() -> Self {
+        Self {
+            a: 0x8000,
+            c: 0,
+            ct: 12,
+            out: vec![0],
+        }
+    }
+
+    /// Number of bytes output so far, the bytes still in the coder registers excluded.
+    pub fn len(&self) -> usize {
+        self.out.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn encode(&mut self, bit: bool, cx: &mut Context) {
+        let qe = cx.qe();
+        self.a -= qe;
+        if bit == cx.mps {
+            if self.a & 0x8000 != 0 {
+                self.c += qe;
+                return;
+            }
+            if self.a < qe {
+                self.a = qe;
+            } else {
+                self.c += qe;
+            }
+            cx.on_mps();
+        } else {
+            if self.a < qe {
+                self.c += qe;
+            } else {
+                self.a = qe;
+            }
+            cx.on_lps();
+        }
+        self.renormalize();
+    }
+
+    fn renormalize(&mut self) {
+        while self.a & 0x8000 == 0 {
+            self.a <<= 1;
+            self.c <<= 1;
+            self.ct -= 1;
+            if self.ct == 0 {
+                self.byte_out();
+            }
+        }
+    }
+
+    fn byte_out(&mut self) {
+        let last = self.out.last_mut().unwrap();
+        if *last == 0xff {
+            // Bit stuffing: only 7 bits follow a 0xff byte, so that it is never followed by a marker
+            self.emit(20, 7);
+        } else if self.c < 0x800_0000 {
+            self.emit(19, 8);
+        } else {
+            // Carry propagation into the last byte
+            *last += 1;
+            if *last == 0xff {
+                self.c &= 0x7ff_ffff;
+                self.emit(20, 7);
+            } else {
+                self.emit(19, 8);
+            }
+        }
+    }
+
+    fn emit(&mut self, shift: u32, ct: u32) {
+        self.out.push((self.c >> shift) as u8);
+        self.c &= (1 << shift) - 1;
+        self.ct = ct;
+    }
+
+    /// Terminates the codeword, with as many bits as needed to decode every symbol.
+    ///
+    /// The codeword never ends with a 0xff byte, and no 0xff byte is followed by a byte above 0x8f,
+    /// so it can be embedded in a stream using these as markers.
+    pub fn finish(mut self) -> Vec<u8> {
+        let max = self.c + self.a;
+        self.c |= 0xffff;
+        if self.c >= max {
+            self.c -= 0x8000;
+        }
+        self.c <<= self.ct;
+        self.byte_out();
+        self.c <<= self.ct;
+        self.byte_out();
+
+        if self.out.last() == Some(&0xff) {
+            self.out.pop();
+        }
+        self.out.remove(0);
+        self.out
+    }
+}
+
+/// Decoding half of the MQ coder.
+///
+/// Past the end of the codeword, the decoder reads 1 bits, so truncated codewords decode without failure.
+#[derive(Debug, Clone)]
+pub struct MqDecoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    a: u32,
+    c: u32,
+    ct: u32,
+}
+
+impl<'a> MqDecoder<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        let mut decoder = Self {
+            data,
+            pos: 0,
+            a: 0x8000,
+            c: 0,
+            ct: 0,
+        };
+        decoder.c = (decoder.byte(0) as u32) << 16;
+        decoder.byte_in();
+        decoder.c <<= 7;
+        decoder.ct -= 7;
+        decoder
+    }
+
+    fn byte(&self, i: usize) -> u8 {
+        self.data.get(i).copied().unwrap_or(0xff)
+    }
+
+    fn byte_in(&mut self) {
+        if self.byte(self.pos) == 0xff {
+            let next = self.byte(self.pos + 1);
+            if next > 0x8f {
+                // Marker or end of data
+                self.c = self.c.wrapping_add(0xff00);
+                self.ct = 8;
+            } else {
+                self.pos += 1;
+                self.c = self.c.wrapping_add((next as u32) << 9);
+                self.ct = 7;
+            }
+        } else {
+            self.pos += 1;
+            self.c = self.c.wrapping_add((self.byte(self.pos) as u32) << 8);
+            self.ct = 8;
+        }
+    }
+
+    pub fn decode(&mut self, cx: &mut Context) -> bool {
+        let qe = cx.qe();
+        self.a -= qe;
+        let bit;
+        if (self.c >> 16) < qe {
+            // The LPS sub-interval is on top, unless it is the largest one
+            if self.a < qe {
+                bit = cx.mps;
+                cx.on_mps();
+            } else {
+                bit = !cx.mps;
+                cx.on_lps();
+            }
+            self.a = qe;
+        } else {
+            self.c -= qe << 16;
+            if self.a & 0x8000 != 0 {
+                return cx.mps;
+            }
+            if self.a < qe {
+                bit = !cx.mps;
+                cx.on_lps();
+            } else {
+                bit = cx.mps;
+                cx.on_mps();
+            }
+        }
+        self.renormalize();
+        bit
+    }
+
+    fn renormalize(&mut self) {
+        while self.a & 0x8000 == 0 {
+            if self.ct == 0 {
+                self.byte_in();
+            }
+            self.a <<= 1;
+            self.c <<= 1;
+            self.ct -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::testing::signal;
+
+    use super::{Context, MqDecoder, MqEncoder};
+
+    fn random_bits(len: usize, seed: u32, one_in: u32) -> Vec<bool> {
+        signal(len, seed, |x| x.is_multiple_of(one_in))
+    }
+
+    fn round_trip(bits: &[bool], contexts: usize) -> Vec<u8> {
+        let mut cx = vec![Context::default(); contexts];
+        let mut encoder = MqEncoder::new();
+        for (i, &bit) in bits.iter().enumerate() {
+            encoder.encode(bit, &mut cx[i % contexts]);
+        }
+        let bytes = encoder.finish();
+
+        let mut cx = vec![Context::default(); contexts];
+        let mut decoder = MqDecoder::new(&bytes);
+        for (i, &bit) in bits.iter().enumerate() {
+            assert_eq!(decoder.decode(&mut cx[i % contexts]), bit, "{i}");
+        }
+        bytes
+    }
+
+    #[test]
+    fn random_round_trip() {
+        for (seed, one_in) in [(1, 2), (2, 3), (3, 10), (4, 1000)] {
+            for len in [0, 1, 7, 100, 10000] {
+                round_trip(&random_bits(len, seed, one_in), 3);
+            }
+        }
+        round_trip(&[true; 5000], 1);
+        round_trip(&[false; 5000], 1);
+    }
+
+    #[test]
+    fn compression() {
+        let bits = random_bits(80000, 5, 20);
+        let bytes = round_trip(&bits, 1);
+        // The entropy of a 1/20 source is about 0.29 bit per symbol
+        assert!(bytes.len() < 80000 / 8 * 33 / 100, "{}", bytes.len());
+
+        let bits = random_bits(8000, 6, 2);
+        let bytes = round_trip(&bits, 1);
+        assert!(bytes.len() < 1000 * 105 / 100, "{}", bytes.len());
+    }
+
+    #[test]
+    fn marker_free() {
+        for seed in 0..20 {
+            let bytes = round_trip(&random_bits(3000, seed, 2 + seed % 5), 4);
+            assert_ne!(bytes.last(), Some(&0xff));
+            for pair in bytes.windows(2) {
+                assert!(pair[0] != 0xff || pair[1] <= 0x8f, "{pair:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn uniform_context() {
+        let bits = random_bits(1000, 9, 2);
+        let mut cx = Context::UNIFORM;
+        let mut encoder = MqEncoder::new();
+        for &bit in &bits {
+            encoder.encode(bit, &mut cx);
+        }
+        assert_eq!(cx, Context::UNIFORM);
+        let bytes = encoder.finish();
+
+        let mut cx = Context::UNIFORM;
+        let mut decoder = MqDecoder::new(&bytes);
+        for &bit in &bits {
+            assert_eq!(decoder.decode(&mut cx), bit);
+        }
+    }
+}
@@ -0,0 +1,373 @@
+//! Low complexity coder of the subbands for the low latency mode, with the adaptive Golomb-Rice codes and the
+//! run-length coding of zeros of
+//!
+//! > Weinberger, Marcelo J., Gadiel Seroussi, and Guillermo Sapiro.
+//! > "The LOCO-I lossless image compression algorithm: Principles and standardization into JPEG-LS."
+//! > IEEE Transactions on Image processing 9.8 (2000): 1309-1324.
+
+use std::io::{self, Read, Write};
+
+use crate::{
+    bitio::{BitReader, BitWriter},
+    dwt::{Subband, SubbandLayout},
+    memory::{ImageView, ImageViewMut},
+    numeric::Sample,
+};
+
+/// Longest unary prefix, larger values are escaped with `LIMIT` zeros and an Exp-Golomb code.
+const LIMIT: u64 = 24;
+/// Number of coded values after which the statistics are halved, to follow the changes inside a band.
+const RESET: u64 = 64;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Rice parameter estimated from the running mean of the coded values.
+#[derive(Debug, Clone, Copy)]
+struct Adaptive {
+    sum: u64,
+    count: u64,
+}
+
+impl Adaptive {
+    fn new() -> Self {
+        Self { sum: 4, count: 1 }
+    }
+
+    /// Smallest `k` such that `2^k` is at least the mean.
+    fn k(&self) -> u32 {
+        let mut k = 0;
+        while ((self.count as u128) << k) < self.sum as u128 {
+            k += 1;
+        }
+        k
+    }
+
+    fn update(&mut self, value: u64) {
+        self.sum = self.sum.saturating_add(value);
+        self.count += 1;
+        if self.count == RESET {
+            self.sum /= 2;
+            self.count /= 2;
+        }
+    }
+}
+
+/// Adaptive state of a band: one parameter for the magnitudes, one for the lengths of the zero runs.
+#[derive(Debug, Clone, Copy)]
+struct BandState {
+    magnitude: Adaptive,
+    run: Adaptive,
+}
+
+/// Either side of the bitstream: the encoder writes the given values, the decoder ignores them and reads them.
+trait Coder {
+    fn bit(&mut self, bit: bool) -> io::Result<bool>;
+    fn rice(&mut self, value: u64, k: u32) -> io::Result<u64>;
+}
+
+impl<W: Write> Coder for BitWriter<W> {
+    fn bit(&mut self, bit: bool) -> io::Result<bool> {
+        self.write_bit(bit)?;
+        Ok(bit)
+    }
+
+    fn rice(&mut self, value: u64, k: u32) -> io::Result<u64> {
+        let q = value >> k;
+        if q < LIMIT {
+            self.write_unary(q)?;
+            self.write_bits(value, k)?;
+        } else {
+            self.write_bits(0, LIMIT as u32)?;
+            self.write_exp_golomb(value - (LIMIT << k), 0)?;
+        }
+        Ok(value)
+    }
+}
+
+impl<R: Read> Coder for BitReader<R> {
+    fn bit(&mut self, _bit: bool) -> io::Result<bool> {
+        self.read_bit()
+    }
+
+    fn rice(&mut self, _value: u64, k: u32) -> io::Result<u64> {
+        let mut q = 0;
+        while q < LIMIT && !self.read_bit()? {
+            q += 1;
+        }
+        if q < LIMIT {
+            Ok((q << k) | self.read_bits(k)?)
+        } else {
+            let escape = self.read_exp_golomb(0)? as u128 + ((LIMIT as u128) << k);
+            u64::try_from(escape).map_err(|_| invalid("Rice code does not fit in 64 bits"))
+        }
+    }
+}
+
+/// Codes a coefficient whose magnitude is known to be at least `offset`.
+fn code_value(
+    coder: &mut impl Coder,
+    adaptive: &mut Adaptive,
+    value: &mut i64,
+    offset: u64,
+) -> io::Result<()> {
+    let magnitude = coder
+        .rice(value.unsigned_abs().wrapping_sub(offset), adaptive.k())?
+        .checked_add(offset)
+        .ok_or_else(|| invalid("Coefficient does not fit in 64 bits"))?;
+    adaptive.update(magnitude);
+    let negative = magnitude != 0 && coder.bit(*value < 0)?;
+    *value = if negative {
+        0i64.checked_sub_unsigned(magnitude)
+    } else {
+        i64::try_from(magnitude).ok()
+    }
+    .ok_or_else(|| invalid("Coefficient does not fit in 64 bits"))?;
+    Ok(())
+}
+
+/// Codes the coefficients of a row inside a band.
+///
+/// After a zero, the length of the run of zeros that follows is coded,
+/// then the next coefficient, known to be nonzero, unless the run reaches the end of the band.
+fn code_segment(
+    coder: &mut impl Coder,
+    state: &mut BandState,
+    values: &mut [i64],
+) -> io::Result<()> {
+    let mut x = 0;
+    while x < values.len() {
+        code_value(coder, &mut state.magnitude, &mut values[x], 0)?;
+        x += 1;
+        if values[x - 1] != 0 {
+            continue;
+        }
+        let run = values[x..].iter().take_while(|&&v| v == 0).count();
+        let run = coder.rice(run as u64, state.run.k())?;
+        if run > (values.len() - x) as u64 {
+            return Err(invalid("Run of zeros past the end of the band"));
+        }
+        state.run.update(run);
+        values[x..x + run as usize].fill(0);
+        x += run as usize;
+        if x < values.len() {
+            code_value(coder, &mut state.magnitude, &mut values[x], 1)?;
+            x += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Subbands of the decomposition and their adaptive states.
+#[derive(Debug, Clone)]
+struct Bands {
+    bands: Vec<Subband>,
+    states: Vec<BandState>,
+    width: usize,
+    height: usize,
+    row: usize,
+    values: Vec<i64>,
+}
+
+impl Bands {
+    fn new(layout: &SubbandLayout) -> Self {
+        let bands = layout
+            .bands()
+            .filter(|band| band.width > 0 && band.height > 0)
+            .collect::<Vec<_>>();
+        let state = BandState {
+            magnitude: Adaptive::new(),
+            run: Adaptive::new(),
+        };
+        Self {
+            states: vec![state; bands.len()],
+            bands,
+            width: layout.width(),
+            height: layout.height(),
+            row: 0,
+            values: vec![0; layout.width()],
+        }
+    }
+
+    /// Codes the next row, held in `values`, band by band.
+    fn code_row(&mut self, coder: &mut impl Coder) -> io::Result<()> {
+        assert!(self.row < self.height, "All the rows are already coded");
+        let y = self.row;
+        self.row += 1;
+        for (band, state) in self.bands.iter().zip(&mut self.states) {
+            if (band.y..band.y + band.height).contains(&y) {
+                code_segment(coder, state, &mut self.values[band.x..band.x + band.width])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Encoder of a decomposed image, row by row.
+///
+/// Each row is coded as soon as it is given, with a few adaptive parameters per subband,
+/// so the latency and the cost of coding a coefficient are constant.
+#[derive(Debug)]
+pub struct RiceEncoder<W> {
+    writer: BitWriter<W>,
+    bands: Bands,
+}
+
+impl<W: Write> RiceEncoder<W> {
+    pub fn new(inner: W, layout: &SubbandLayout) -> Self {
+        Self {
+            writer: BitWriter::new(inner),
+            bands: Bands::new(layout),
+        }
+    }
+
+    /// Encodes the next row of the decomposed image.
+    pub fn encode_row<T: Sample>(&mut self, row: &[T]) -> io::Result<()> {
+        assert_eq!(
+            row.len(),
+            self.bands.width,
+            "Row length differs from the layout width"
+        );
+        for (v, &x) in self.bands.values.iter_mut().zip(row) {
+            *v = x.to_i64();
+        }
+        self.bands.code_row(&mut self.writer)
+    }
+
+    /// Encodes all the remaining rows.
+    pub fn encode<T: Sample>(&mut self, img: ImageView<'_, T>) -> io::Result<()> {
+        for row in img.rows().skip(self.bands.row) {
+            self.encode_row(row)?;
+        }
+        Ok(())
+    }
+
+    /// Pads the last byte and returns the sink.
+    pub fn finish(self) -> io::Result<W> {
+        self.writer.finish()
+    }
+}
+
+/// Decoder of a stream written by [`RiceEncoder`] with the same layout.
+#[derive(Debug)]
+pub struct RiceDecoder<R> {
+    reader: BitReader<R>,
+    bands: Bands,
+}
+
+impl<R: Read> RiceDecoder<R> {
+    pub fn new(inner: R, layout: &SubbandLayout) -> Self {
+        Self {
+            reader: BitReader::new(inner),
+            bands: Bands::new(layout),
+        }
+    }
+
+    /// Decodes the next row of the decomposed image.
+    pub fn decode_row<T: Sample>(&mut self, row: &mut [T]) -> io::Result<()> {
+        assert_eq!(
+            row.len(),
+            self.bands.width,
+            "Row length differs from the layout width"
+        );
+        self.bands.code_row(&mut self.reader)?;
+        for (x, &v) in row.iter_mut().zip(&self.bands.values) {
+            *x = T::checked_from_i64(v)
+                .ok_or_else(|| invalid("Coefficient out of the sample range"))?;
+        }
+        Ok(())
+    }
+
+    /// Decodes all the remaining rows.
+    pub fn decode<T: Sample>(&mut self, mut img: ImageViewMut<'_, T>) -> io::Result<()> {
+        for row in img.rows_mut().skip(self.bands.row) {
+            self.decode_row(row)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::ErrorKind;
+
+    use crate::{
+        dwt::{daub::Daub53, Dwt2, SubbandLayout},
+        memory::Image,
+        numeric::Sample,
+    };
+
+    use super::{RiceDecoder, RiceEncoder};
+
+    fn round_trip<T: Sample + Default>(image: &Image<T>, layout: &SubbandLayout) -> Vec<u8> {
+        let mut encoder = RiceEncoder::new(Vec::new(), layout);
+        encoder.encode(image.view()).unwrap();
+        let data = encoder.finish().unwrap();
+
+        let mut decoded = Image::with_value(image.width(), image.height(), &T::default());
+        let mut decoder = RiceDecoder::new(data.as_slice(), layout);
+        decoder.decode(decoded.view_mut()).unwrap();
+        assert!(decoded.rows().eq(image.rows()));
+        data
+    }
+
+    #[test]
+    fn lossless() {
+        for (width, height, levels) in [(64, 48, 4), (37, 29, 3), (5, 1, 1), (9, 9, 0)] {
+            let mut image = Image::with_fn(width, height, |x, y| {
+                ((x as i16 - 20).pow(2) + (y as i16 - 10).pow(2)) / 4
+                    + ((x * 7 + y * 13) % 11) as i16
+            });
+            let mut tmp = image.clone();
+            let layout = Daub53.decompose(image.view_mut(), tmp.view_mut(), levels);
+            round_trip(&image, &layout);
+        }
+    }
+
+    #[test]
+    fn compression() {
+        let mut image = Image::with_fn(64, 64, |x, y| ((x * x + y * y) / 32) as i16);
+        let mut tmp = image.clone();
+        let layout = Daub53.decompose(image.view_mut(), tmp.view_mut(), 4);
+        let data = round_trip(&image, &layout);
+        assert!(data.len() < 64 * 64 / 4, "{}", data.len());
+
+        // Runs of zeros cost a few bits per row and band
+        let zeros = Image::with_value(64, 64, &0i32);
+        let data = round_trip(&zeros, &layout);
+        assert!(data.len() < 256, "{}", data.len());
+    }
+
+    #[test]
+    fn extreme_values() {
+        let image = Image::with_fn(16, 8, |x, y| match (x + y) % 4 {
+            0 => i32::MIN,
+            1 => i32::MAX,
+            2 => 0,
+            _ => -1,
+        });
+        round_trip(&image, &SubbandLayout::new(16, 8, 2));
+        let image = Image::with_fn(8, 8, |x, y| if x == y { i64::MIN } else { i64::MAX });
+        round_trip(&image, &SubbandLayout::new(8, 8, 1));
+    }
+
+    #[test]
+    fn invalid_data() {
+        let layout = SubbandLayout::new(8, 8, 1);
+        let image = Image::with_value(8, 8, &1000i32);
+        let mut encoder = RiceEncoder::new(Vec::new(), &layout);
+        encoder.encode(image.view()).unwrap();
+        let data = encoder.finish().unwrap();
+
+        let mut decoded = Image::with_value(8, 8, &0i8);
+        let mut decoder = RiceDecoder::new(data.as_slice(), &layout);
+        let err = decoder.decode(decoded.view_mut()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let mut decoded = Image::with_value(8, 8, &0i32);
+        let mut decoder = RiceDecoder::new(&data[..data.len() / 2], &layout);
+        let err = decoder.decode(decoded.view_mut()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,324 @@
+//! Set partitioning in hierarchical trees, from the following paper
+//!
+//! > Said, Amir, and William A. Pearlman.
+//! > "A new, fast, and efficient image codec based on set partitioning in hierarchical trees."
+//! > IEEE Transactions on circuits and systems for video technology 6.3 (1996): 243-250.
+
+use std::io;
+
+use crate::{
+    dwt::SubbandLayout,
+    memory::{ImageView, ImageViewMut},
+    numeric::Sample,
+};
+
+use super::zerotree::{reconstruct, Coder, Decoder, Encoder, Trees};
+
+/// Coding state, shared by the encoder and the decoder.
+struct State {
+    trees: Trees,
+    magnitudes: Vec<u64>,
+    negative: Vec<bool>,
+    /// Lowest bitplane known of each significant coefficient
+    known: Vec<u32>,
+    /// Largest magnitude of the descendants of each coefficient, only known by the encoder
+    descendants: Vec<u64>,
+    /// Largest magnitude of the descendants of the children of each coefficient, only known by the encoder
+    grandchildren: Vec<u64>,
+    /// List of insignificant pixels
+    lip: Vec<usize>,
+    /// List of significant pixels
+    lsp: Vec<usize>,
+    /// List of insignificant sets, `true` for the descendants of the children only (type B)
+    lis: Vec<(usize, bool)>,
+}
+
+impl State {
+    fn new(layout: &SubbandLayout) -> Self {
+        let trees = Trees::new(layout);
+        let size = trees.len();
+        let lip = trees.roots.clone();
+        let lis = lip
+            .iter()
+            .filter(|&&i| trees.has_children(i))
+            .map(|&i| (i, false))
+            .collect();
+        Self {
+            trees,
+            magnitudes: vec![0; size],
+            negative: vec![false; size],
+            known: vec![0; size],
+            descendants: vec![0; size],
+            grandchildren: vec![0; size],
+            lip,
+            lsp: Vec::new(),
+            lis,
+        }
+    }
+
+    /// Computes the maxima of the trees, from the finest coefficients to the roots.
+    fn compute_descendants(&mut self, layout: &SubbandLayout) {
+        let bands = layout.bands().collect::<Vec<_>>();
+        for band in bands.iter().rev() {
+            for y in band.y..band.y + band.height {
+                for x in band.x..band.x + band.width {
+                    let i = y * self.trees.width + x;
+                    let (mut descendants, mut grandchildren) = (0, 0);
+                    for c in self.trees.children(i) {
+                        descendants = descendants.max(self.magnitudes[c]).max(self.descendants[c]);
+                        grandchildren = grandchildren.max(self.descendants[c]);
+                    }
+                    self.descendants[i] = descendants;
+                    self.grandchildren[i] = grandchildren;
+                }
+            }
+        }
+    }
+
+    fn code_sign(&mut self, coder: &mut impl Coder, i: usize, n: u32) -> Option<()> {
+        self.negative[i] = coder.code(self.negative[i])?;
+        self.magnitudes[i] |= 1 << n;
+        self.known[i] = n;
+        self.lsp.push(i);
+        Some(())
+    }
+
+    /// Codes the significance of a coefficient, adding it to the LSP or the LIP.
+    fn code_pixel(&mut self, coder: &mut impl Coder, i: usize, n: u32) -> Option<bool> {
+        let significant = coder.code(self.magnitudes[i] >> n != 0)?;
+        if significant {
+            self.code_sign(coder, i, n)?;
+        }
+        Some(significant)
+    }
+
+    fn sorting_pass(&mut self, coder: &mut impl Coder, n: u32) -> Option<()> {
+        let lip = std::mem::take(&mut self.lip);
+        for (k, &i) in lip.iter().enumerate() {
+            let significant = self.code_pixel(coder, i, n);
+            if significant != Some(true) {
+                self.lip.push(i);
+            }
+            if significant.is_none() {
+                self.lip.extend_from_slice(&lip[k + 1..]);
+                return None;
+            }
+        }
+
+        let mut k = 0;
+        let mut kept = Vec::new();
+        while k < self.lis.len() {
+            let (i, grand) = self.lis[k];
+            k += 1;
+            let max = if grand {
+                self.grandchildren[i]
+            } else {
+                self.descendants[i]
+            };
+            let Some(significant) = coder.code(max >> n != 0) else {
+                kept.extend_from_slice(&self.lis[k - 1..]);
+                self.lis = kept;
+                return None;
+            };
+            if !significant {
+                kept.push((i, grand));
+            } else if grand {
+                for c in self.trees.children(i).collect::<Vec<_>>() {
+                    self.lis.push((c, false));
+                }
+            } else {
+                for c in self.trees.children(i).collect::<Vec<_>>() {
+                    match self.code_pixel(coder, c, n) {
+                        Some(true) => (),
+                        Some(false) => self.lip.push(c),
+                        None => {
+                            self.lip.push(c);
+                            return None;
+                        }
+                    }
+                }
+                if self.trees.has_grandchildren(i) {
+                    self.lis.push((i, true));
+                }
+            }
+        }
+        self.lis = kept;
+        Some(())
+    }
+
+    fn refinement_pass(&mut self, coder: &mut impl Coder, n: u32, len: usize) -> Option<()> {
+        for k in 0..len {
+            let i = self.lsp[k];
+            if coder.code(self.magnitudes[i] >> n & 1 != 0)? {
+                self.magnitudes[i] |= 1 << n;
+            }
+            self.known[i] = n;
+        }
+        Some(())
+    }
+
+    fn code(&mut self, coder: &mut impl Coder, planes: u32) {
+        for n in (0..planes).rev() {
+            let len = self.lsp.len();
+            if self.sorting_pass(coder, n).is_none()
+                || self.refinement_pass(coder, n, len).is_none()
+            {
+                return;
+            }
+        }
+    }
+}
+
+/// Embedded SPIHT codec of a decomposed image.
+///
+/// Bitplanes are coded from the most significant one, each with a sorting pass finding the newly significant coefficients
+/// by splitting the insignificant trees, then a refinement pass of the previously significant coefficients.
+/// The stream can be stopped after any bit: the header gives the number of bitplanes and of coded bits.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Spiht;
+
+impl Spiht {
+    /// Encodes every bitplane, down to lossless.
+    pub fn encode<T: Sample>(&self, img: ImageView<'_, T>, layout: &SubbandLayout) -> Vec<u8> {
+        self.encode_with_budget(img, layout, u64::MAX)
+    }
+
+    /// Encodes the coefficients, stopping after `budget` bits, the header excluded.
+    pub fn encode_with_budget<T: Sample>(
+        &self,
+        img: ImageView<'_, T>,
+        layout: &SubbandLayout,
+        budget: u64,
+    ) -> Vec<u8> {
+        let mut state = State::new(layout);
+        for (y, row) in img.rows().enumerate() {
+            for (x, &c) in row.iter().enumerate() {
+                let i = y * layout.width() + x;
+                state.magnitudes[i] = c.to_i64().unsigned_abs();
+                state.negative[i] = c.to_i64() < 0;
+            }
+        }
+        state.compute_descendants(layout);
+        let max = state.magnitudes.iter().max().copied().unwrap_or(0);
+        let planes = u64::BITS - max.leading_zeros();
+
+        let mut encoder = Encoder::new(budget);
+        state.code(&mut encoder, planes);
+        encoder.finish(planes)
+    }
+
+    /// Decodes a stream written by [`Spiht::encode`] with the same layout, returning the number of bits decoded.
+    ///
+    /// A truncated stream decodes into a lower quality image, only a truncated header is an error.
+    pub fn decode<T: Sample>(
+        &self,
+        data: &[u8],
+        layout: &SubbandLayout,
+        mut img: ImageViewMut<'_, T>,
+    ) -> io::Result<u64> {
+        let (planes, mut decoder) = Decoder::new(data)?;
+        let mut state = State::new(layout);
+        state.code(&mut decoder, planes);
+
+        for (y, row) in img.rows_mut().enumerate() {
+            for (x, c) in row.iter_mut().enumerate() {
+                let i = y * layout.width() + x;
+                let value = reconstruct(state.magnitudes[i], state.negative[i], state.known[i]);
+                *c = T::saturating_from_i64(value);
+            }
+        }
+        Ok(decoder.bits_read())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        dwt::{daub::Daub97, haar::Haar, Dwt2, SubbandLayout},
+        memory::Image,
+    };
+
+    use super::Spiht;
+
+    fn squared_error<T: Copy + Into<i64>>(a: &Image<T>, b: &Image<T>) -> i64 {
+        let mut sum = 0;
+        for (a, b) in a.rows().zip(b.rows()) {
+            for (&a, &b) in a.iter().zip(b) {
+                sum += (a.into() - b.into()).pow(2);
+            }
+        }
+        sum
+    }
+
+    #[test]
+    fn lossless() {
+        for (width, height, levels) in [(64, 64, 4), (45, 31, 3), (7, 1, 2), (20, 20, 0)] {
+            let mut image = Image::with_fn(width, height, |x, y| {
+                ((x as i16 - 20).pow(2) + (y as i16 - 10).pow(2)) / 8 - 50
+            });
+            let mut tmp = image.clone();
+            let layout = Haar.decompose(image.view_mut(), tmp.view_mut(), levels);
+
+            let data = Spiht.encode(image.view(), &layout);
+            let mut decoded = Image::with_value(width, height, &0i16);
+            Spiht.decode(&data, &layout, decoded.view_mut()).unwrap();
+            assert_eq!(squared_error(&decoded, &image), 0, "{width}x{height}");
+        }
+    }
+
+    #[test]
+    fn extremes() {
+        // 63 bitplanes, the most `i64` magnitudes need but for `i64::MIN`
+        let layout = SubbandLayout::new(4, 1, 0);
+        let image = Image::with_fn(4, 1, |x, _| [-i64::MAX, i64::MAX, -1, 0][x]);
+        let data = Spiht.encode(image.view(), &layout);
+        let mut decoded = Image::with_value(4, 1, &0);
+        let total = Spiht.decode(&data, &layout, decoded.view_mut()).unwrap();
+        assert_eq!(decoded.rows().next().unwrap(), image.rows().next().unwrap());
+
+        // Every prefix reconstructs the coefficients with their sign, without overflowing
+        for budget in 0..total {
+            let data = Spiht.encode_with_budget(image.view(), &layout, budget);
+            let mut decoded = Image::with_value(4, 1, &0i64);
+            Spiht.decode(&data, &layout, decoded.view_mut()).unwrap();
+            for (&x, &y) in decoded.rows().flatten().zip(image.rows().flatten()) {
+                assert!(x == 0 || x.signum() == y.signum(), "{budget}: {x} {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn bit_budget() {
+        let mut image = Image::with_fn(50, 40, |x, y| {
+            let (x, y) = (x as i32, y as i32);
+            (x * x + y * y) / 16 + if (x / 8 + y / 8) % 2 == 0 { 60 } else { 0 }
+        });
+        let mut tmp = image.clone();
+        let layout = Daub97.decompose(image.view_mut(), tmp.view_mut(), 3);
+        let full = Spiht.encode(image.view(), &layout);
+        let mut decoded = Image::with_value(50, 40, &0);
+        let total = Spiht.decode(&full, &layout, decoded.view_mut()).unwrap();
+        assert_eq!(squared_error(&decoded, &image), 0);
+
+        let mut last = i64::MAX;
+        for budget in [0, 1, 7, 100, 1001, 5000, total - 1] {
+            let data = Spiht.encode_with_budget(image.view(), &layout, budget);
+            let mut decoded = Image::with_value(50, 40, &0);
+            let bits = Spiht.decode(&data, &layout, decoded.view_mut()).unwrap();
+            assert_eq!(bits, budget);
+            let error = squared_error(&decoded, &image);
+            assert!(error <= last, "{budget}: {error}");
+            last = error;
+        }
+        assert!(last > 0);
+
+        // A stream truncated inside the payload decodes like a smaller budget
+        let truncated = &full[..full.len() / 2];
+        let mut decoded = Image::with_value(50, 40, &0);
+        let bits = Spiht
+            .decode(truncated, &layout, decoded.view_mut())
+            .unwrap();
+        assert!(bits < total);
+        assert!(squared_error(&decoded, &image) > 0);
+    }
+}
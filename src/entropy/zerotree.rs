@@ -0,0 +1,178 @@
+//! Parent-child trees of the dyadic pyramid and embedded bitstreams, shared by the zerotree coders.
+
+use std::io;
+
+use crate::{
+    bitio::{BitReader, BitWriter},
+    dwt::{Orientation, SubbandLayout},
+};
+
+const NONE: usize = usize::MAX;
+
+/// Either side of the bitstream: the encoder writes `bit`, the decoder ignores it and reads one.
+///
+/// `None` once the bit budget is spent, which stops both sides at the same symbol.
+pub(super) trait Coder {
+    fn code(&mut self, bit: bool) -> Option<bool>;
+}
+
+pub(super) struct Encoder {
+    writer: BitWriter<Vec<u8>>,
+    budget: u64,
+}
+
+impl Coder for Encoder {
+    fn code(&mut self, bit: bool) -> Option<bool> {
+        if self.writer.bits_written() >= self.budget {
+            return None;
+        }
+        // Writing to a vector never fails
+        self.writer.write_bit(bit).unwrap();
+        Some(bit)
+    }
+}
+
+pub(super) struct Decoder<'a> {
+    reader: BitReader<&'a [u8]>,
+    len: u64,
+}
+
+impl Coder for Decoder<'_> {
+    fn code(&mut self, _bit: bool) -> Option<bool> {
+        if self.reader.bits_read() >= self.len {
+            return None;
+        }
+        self.reader.read_bit().ok()
+    }
+}
+
+/// Parent-child trees of a Mallat decomposition.
+///
+/// Each `LL` coefficient has a child at the same position in each of the coarsest detail bands,
+/// and each detail coefficient has the 2x2 coefficients at twice its position in the finer band of the same orientation.
+/// With odd sizes, the coefficients past the children of the coarser band have no parent and are roots as well.
+pub(super) struct Trees {
+    pub width: usize,
+    children: Vec<[usize; 4]>,
+    pub roots: Vec<usize>,
+}
+
+impl Trees {
+    pub fn new(layout: &SubbandLayout) -> Self {
+        let width = layout.width();
+        let mut children = vec![[NONE; 4]; width * layout.height()];
+        let mut roots = Vec::new();
+
+        for band in layout.bands() {
+            for y in 0..band.height {
+                for x in 0..band.width {
+                    let i = (band.y + y) * width + band.x + x;
+                    let parent = match (band.orientation, band.level) {
+                        (Orientation::LL, _) => None,
+                        (o, level) if level == layout.levels() => {
+                            let ll = layout.band(level, Orientation::LL);
+                            let k = Orientation::DETAILS.iter().position(|&d| d == o).unwrap();
+                            Some((ll.y + y, ll.x + x, k))
+                        }
+                        (o, level) => {
+                            let parent = layout.band(level + 1, o);
+                            (x / 2 < parent.width && y / 2 < parent.height)
+                                .then(|| (parent.y + y / 2, parent.x + x / 2, x % 2 + 2 * (y % 2)))
+                        }
+                    };
+                    match parent {
+                        Some((py, px, k)) => children[py * width + px][k] = i,
+                        None => roots.push(i),
+                    }
+                }
+            }
+        }
+        // Holes appear with the 3 children of `LL` coefficients and next to the borders
+        for node in &mut children {
+            node.sort_unstable();
+        }
+        Self {
+            width,
+            children,
+            roots,
+        }
+    }
+
+    /// Number of coefficients.
+    pub fn len(&self) -> usize {
+        self.children.len()
+    }
+
+    pub fn children(&self, i: usize) -> impl Iterator<Item = usize> + '_ {
+        self.children[i].iter().copied().take_while(|&c| c != NONE)
+    }
+
+    pub fn has_children(&self, i: usize) -> bool {
+        self.children[i][0] != NONE
+    }
+
+    pub fn has_grandchildren(&self, i: usize) -> bool {
+        self.children(i).any(|c| self.has_children(c))
+    }
+}
+
+impl Encoder {
+    pub fn new(budget: u64) -> Self {
+        Self {
+            writer: BitWriter::new(Vec::new()),
+            budget,
+        }
+    }
+
+    /// Prepends the header to the coded bits: the number of bitplanes and of coded bits.
+    pub fn finish(self, planes: u32) -> Vec<u8> {
+        // Writing to a vector never fails
+        let mut header = BitWriter::new(Vec::new());
+        header.write_exp_golomb(planes as u64, 0).unwrap();
+        header
+            .write_exp_golomb(self.writer.bits_written(), 0)
+            .unwrap();
+        let mut out = header.finish().unwrap();
+        out.extend(self.writer.finish().unwrap());
+        out
+    }
+}
+
+impl<'a> Decoder<'a> {
+    /// Reads the header written by [`Encoder::finish`], returning the number of bitplanes and the decoder of the bits.
+    pub fn new(data: &'a [u8]) -> io::Result<(u32, Self)> {
+        let mut reader = BitReader::new(data);
+        let planes = reader.read_exp_golomb(0)?;
+        if planes > u64::BITS as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "More than 64 bitplanes",
+            ));
+        }
+        let len = reader.read_exp_golomb(0)?;
+        reader.align();
+        let payload = &data[(reader.bits_read() / 8) as usize..];
+        let decoder = Self {
+            reader: BitReader::new(payload),
+            len,
+        };
+        Ok((planes as u32, decoder))
+    }
+
+    /// Number of bits decoded, the header excluded.
+    pub fn bits_read(&self) -> u64 {
+        self.reader.bits_read()
+    }
+}
+
+/// Coefficient reconstructed from the bitplanes known down to `known`, at the middle of the remaining interval.
+///
+/// With 64 bitplanes, the magnitude does not fit in `i64`, so the value is clamped to its range.
+pub(super) fn reconstruct(magnitude: u64, negative: bool, known: u32) -> i64 {
+    if magnitude == 0 {
+        return 0;
+    }
+    let value = magnitude as i128 + ((1i128 << known) >> 1);
+    let value = if negative { -value } else { value };
+    value.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+}
@@ -0,0 +1,583 @@
+//! Video streams: a sequence of chunks holding the stream header, the compressed frames and their index.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use super::image::{from_id, id, invalid, Header, Kernel};
+
+/// First bytes of a video stream.
+pub const STREAM_MAGIC: [u8; 4] = *b"WVPV";
+/// Version written by [`ContainerWriter`], and the only one [`ContainerReader`] reads.
+pub const STREAM_VERSION: u8 = 1;
+
+/// Stream header, first chunk of the stream.
+pub const HEAD: [u8; 4] = *b"HEAD";
+/// Frame header followed by a compressed image.
+pub const FRAME: [u8; 4] = *b"FRAM";
+/// Index of all the frames.
+pub const INDEX: [u8; 4] = *b"INDX";
+/// Offset of the index, last chunk of the stream.
+pub const TAIL: [u8; 4] = *b"TAIL";
+
+/// Size of the type and length of a chunk.
+const CHUNK_HEADER: u64 = 8;
+const TAIL_SIZE: u64 = CHUNK_HEADER + 8;
+const INDEX_ENTRY: usize = 17;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PixelFormat {
+    #[default]
+    Gray8,
+    Gray10,
+    Gray12,
+    Gray16,
+}
+
+impl PixelFormat {
+    pub const ALL: [PixelFormat; 4] = [
+        PixelFormat::Gray8,
+        PixelFormat::Gray10,
+        PixelFormat::Gray12,
+        PixelFormat::Gray16,
+    ];
+
+    pub fn bit_depth(self) -> u8 {
+        match self {
+            PixelFormat::Gray8 => 8,
+            PixelFormat::Gray10 => 10,
+            PixelFormat::Gray12 => 12,
+            PixelFormat::Gray16 => 16,
+        }
+    }
+}
+
+/// Properties shared by all the frames of a stream.
+///
+/// The image of each frame has the size, the bit depth and the kernel of the stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StreamHeader {
+    pub width: u32,
+    pub height: u32,
+    /// Frames per second, as a fraction `(numerator, denominator)` with a nonzero denominator
+    pub frame_rate: (u32, u32),
+    pub pixel_format: PixelFormat,
+    pub kernel: Kernel,
+}
+
+impl StreamHeader {
+    const SIZE: usize = 18;
+
+    fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::SIZE);
+        bytes.extend_from_slice(&self.width.to_be_bytes());
+        bytes.extend_from_slice(&self.height.to_be_bytes());
+        bytes.extend_from_slice(&self.frame_rate.0.to_be_bytes());
+        bytes.extend_from_slice(&self.frame_rate.1.to_be_bytes());
+        bytes.push(id(&PixelFormat::ALL, &self.pixel_format));
+        bytes.push(id(&Kernel::ALL, &self.kernel));
+        bytes
+    }
+
+    /// Parses the header, ignoring the bytes that later versions may append.
+    fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < Self::SIZE {
+            return Err(invalid("Stream header too short"));
+        }
+        let u32_at = |i: usize| u32::from_be_bytes(bytes[i..i + 4].try_into().unwrap());
+        if u32_at(12) == 0 {
+            return Err(invalid("Frame rate with a zero denominator"));
+        }
+        Ok(Self {
+            width: u32_at(0),
+            height: u32_at(4),
+            frame_rate: (u32_at(8), u32_at(12)),
+            pixel_format: from_id(&PixelFormat::ALL, bytes[16], "pixel format")?,
+            kernel: from_id(&Kernel::ALL, bytes[17], "kernel")?,
+        })
+    }
+
+    /// Whether a compressed image, starting with its [`Header`], is a frame of this stream.
+    fn is_frame(&self, image: &[u8]) -> bool {
+        Header::read(&mut &image[..]).is_ok_and(|header| {
+            (header.width, header.height) == (self.width, self.height)
+                && header.settings.bit_depth == self.pixel_format.bit_depth()
+                && header.settings.kernel == self.kernel
+        })
+    }
+}
+
+/// Keyframes decode on their own, the other frames depend on the previous ones.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FrameType {
+    #[default]
+    Key,
+    Inter,
+}
+
+/// Header of a frame chunk: 8 bytes of timestamp then 1 byte of flags, the lowest bit set on keyframes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FrameHeader {
+    /// Presentation time in microseconds
+    pub timestamp: u64,
+    pub frame_type: FrameType,
+}
+
+impl FrameHeader {
+    const SIZE: usize = 9;
+
+    fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..8].copy_from_slice(&self.timestamp.to_be_bytes());
+        bytes[8] = (self.frame_type == FrameType::Key) as u8;
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < Self::SIZE {
+            return Err(invalid("Frame header too short"));
+        }
+        Ok(Self {
+            timestamp: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            frame_type: if bytes[8] & 1 != 0 {
+                FrameType::Key
+            } else {
+                FrameType::Inter
+            },
+        })
+    }
+}
+
+/// Position of a frame chunk in the stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IndexEntry {
+    /// Offset of the chunk from the start of the stream
+    pub offset: u64,
+    pub header: FrameHeader,
+}
+
+/// Writer of a video stream.
+///
+/// The stream is a magic number and version, then a sequence of chunks, each one being a 4 bytes type,
+/// a 4 bytes big endian length and its payload. It starts with the [`HEAD`] chunk, then the [`FRAME`] chunks,
+/// and ends with the [`INDEX`] chunk and the [`TAIL`] chunk giving its offset.
+/// Readers skip the chunks they do not know, so new chunk types can be added without breaking them.
+#[derive(Debug)]
+pub struct ContainerWriter<W> {
+    inner: W,
+    header: StreamHeader,
+    position: u64,
+    index: Vec<IndexEntry>,
+}
+
+impl<W: Write> ContainerWriter<W> {
+    pub fn new(inner: W, header: &StreamHeader) -> io::Result<Self> {
+        if header.frame_rate.1 == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Frame rate with a zero denominator",
+            ));
+        }
+        let mut writer = Self {
+            inner,
+            header: *header,
+            position: 0,
+            index: Vec::new(),
+        };
+        writer.inner.write_all(&STREAM_MAGIC)?;
+        writer.inner.write_all(&[STREAM_VERSION])?;
+        writer.position = STREAM_MAGIC.len() as u64 + 1;
+        writer.write_chunk(HEAD, &header.to_bytes())?;
+        Ok(writer)
+    }
+
+    /// Writes a chunk of any type.
+    pub fn write_chunk(&mut self, kind: [u8; 4], payload: &[u8]) -> io::Result<()> {
+        self.write_chunk_parts(kind, &[payload])
+    }
+
+    fn write_chunk_parts(&mut self, kind: [u8; 4], parts: &[&[u8]]) -> io::Result<()> {
+        let len = parts.iter().map(|p| p.len()).sum::<usize>();
+        let len = u32::try_from(len)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Chunk larger than 4 GiB"))?;
+        self.inner.write_all(&kind)?;
+        self.inner.write_all(&len.to_be_bytes())?;
+        for part in parts {
+            self.inner.write_all(part)?;
+        }
+        self.position += CHUNK_HEADER + len as u64;
+        Ok(())
+    }
+
+    /// Writes a frame, `data` being its compressed image, which must match the stream header.
+    pub fn write_frame(&mut self, header: &FrameHeader, data: &[u8]) -> io::Result<()> {
+        if !self.header.is_frame(data) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Frame image does not match the stream header",
+            ));
+        }
+        let offset = self.position;
+        self.write_chunk_parts(FRAME, &[&header.to_bytes(), data])?;
+        self.index.push(IndexEntry {
+            offset,
+            header: *header,
+        });
+        Ok(())
+    }
+
+    /// Writes the index and returns the sink.
+    pub fn finish(mut self) -> io::Result<W> {
+        let mut index = Vec::with_capacity(self.index.len() * INDEX_ENTRY);
+        for entry in &self.index {
+            index.extend_from_slice(&entry.offset.to_be_bytes());
+            index.extend_from_slice(&entry.header.to_bytes());
+        }
+        let offset = self.position;
+        self.write_chunk(INDEX, &index)?;
+        self.write_chunk(TAIL, &offset.to_be_bytes())?;
+        Ok(self.inner)
+    }
+}
+
+/// Reader of a stream written by [`ContainerWriter`], with random access to the keyframes.
+///
+/// Without a valid index, as in a stream cut before its end, the index is rebuilt by scanning the chunks.
+#[derive(Debug)]
+pub struct ContainerReader<R> {
+    inner: R,
+    header: StreamHeader,
+    index: Vec<IndexEntry>,
+}
+
+impl<R: Read + Seek> ContainerReader<R> {
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut start = [0; 5];
+        inner.read_exact(&mut start)?;
+        if start[..4] != STREAM_MAGIC {
+            return Err(invalid("Not a video stream"));
+        }
+        if start[4] != STREAM_VERSION {
+            return Err(invalid(format!("Unsupported version {}", start[4])));
+        }
+        let (kind, payload) = read_chunk(&mut inner)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "Missing stream header"))?;
+        if kind != HEAD {
+            return Err(invalid("The stream does not start with its header"));
+        }
+        let header = StreamHeader::from_bytes(&payload)?;
+
+        let first = inner.stream_position()?;
+        let index = match read_index(&mut inner) {
+            Ok(index) => index,
+            Err(_) => scan_index(&mut inner, first)?,
+        };
+        inner.seek(SeekFrom::Start(first))?;
+        Ok(Self {
+            inner,
+            header,
+            index,
+        })
+    }
+
+    pub fn header(&self) -> &StreamHeader {
+        &self.header
+    }
+
+    /// All the frames, in stream order.
+    pub fn index(&self) -> &[IndexEntry] {
+        &self.index
+    }
+
+    pub fn keyframes(&self) -> impl Iterator<Item = &IndexEntry> + '_ {
+        self.index
+            .iter()
+            .filter(|e| e.header.frame_type == FrameType::Key)
+    }
+
+    /// Reads the next frame, skipping the other chunks, or `None` at the end of the stream.
+    ///
+    /// A frame whose image does not match the stream header is invalid.
+    pub fn next_frame(&mut self) -> io::Result<Option<(FrameHeader, Vec<u8>)>> {
+        while let Some((kind, mut payload)) = read_chunk(&mut self.inner)? {
+            if kind == FRAME {
+                let header = FrameHeader::from_bytes(&payload)?;
+                payload.drain(..FrameHeader::SIZE);
+                if !self.header.is_frame(&payload) {
+                    return Err(invalid("Frame image does not match the stream header"));
+                }
+                return Ok(Some((header, payload)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Moves to the last keyframe at or before `timestamp`, returning its header,
+    /// or `None` and leaving the position unchanged if there is none.
+    pub fn seek(&mut self, timestamp: u64) -> io::Result<Option<FrameHeader>> {
+        let Some(entry) = self
+            .keyframes()
+            .filter(|e| e.header.timestamp <= timestamp)
+            .max_by_key(|e| e.header.timestamp)
+            .copied()
+        else {
+            return Ok(None);
+        };
+        self.inner.seek(SeekFrom::Start(entry.offset))?;
+        Ok(Some(entry.header))
+    }
+}
+
+/// Reads a chunk, or `None` at the end of the stream.
+///
+/// The payload is read incrementally, so that a corrupted length fails at the end of the stream
+/// instead of allocating its size at once.
+fn read_chunk(r: &mut impl Read) -> io::Result<Option<([u8; 4], Vec<u8>)>> {
+    let mut header = [0; CHUNK_HEADER as usize];
+    let mut filled = 0;
+    while filled < header.len() {
+        match r.read(&mut header[filled..])? {
+            0 if filled == 0 => return Ok(None),
+            0 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Truncated chunk",
+                ))
+            }
+            n => filled += n,
+        }
+    }
+    let kind = header[..4].try_into().unwrap();
+    let len = u32::from_be_bytes(header[4..].try_into().unwrap()) as u64;
+    let mut payload = Vec::new();
+    r.take(len).read_to_end(&mut payload)?;
+    if payload.len() as u64 != len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Truncated chunk",
+        ));
+    }
+    Ok(Some((kind, payload)))
+}
+
+/// Reads the index pointed to by the tail chunk.
+fn read_index(r: &mut (impl Read + Seek)) -> io::Result<Vec<IndexEntry>> {
+    r.seek(SeekFrom::End(-(TAIL_SIZE as i64)))?;
+    let (kind, payload) = read_chunk(r)?.ok_or_else(|| invalid("Missing tail"))?;
+    if kind != TAIL || payload.len() != 8 {
+        return Err(invalid("Missing tail"));
+    }
+    r.seek(SeekFrom::Start(u64::from_be_bytes(
+        payload.try_into().unwrap(),
+    )))?;
+    let (kind, payload) = read_chunk(r)?.ok_or_else(|| invalid("Missing index"))?;
+    if kind != INDEX || payload.len() % INDEX_ENTRY != 0 {
+        return Err(invalid("Invalid index"));
+    }
+    payload
+        .chunks_exact(INDEX_ENTRY)
+        .map(|entry| {
+            Ok(IndexEntry {
+                offset: u64::from_be_bytes(entry[..8].try_into().unwrap()),
+                header: FrameHeader::from_bytes(&entry[8..])?,
+            })
+        })
+        .collect()
+}
+
+/// Rebuilds the index from the frame chunks after `first`, up to the first truncated chunk or invalid frame header.
+fn scan_index(r: &mut (impl Read + Seek), first: u64) -> io::Result<Vec<IndexEntry>> {
+    let mut index = Vec::new();
+    let mut offset = r.seek(SeekFrom::Start(first))?;
+    while let Ok(Some((kind, payload))) = read_chunk(r) {
+        if kind == FRAME {
+            let Ok(header) = FrameHeader::from_bytes(&payload) else {
+                break;
+            };
+            index.push(IndexEntry { offset, header });
+        }
+        offset += CHUNK_HEADER + payload.len() as u64;
+    }
+    Ok(index)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, ErrorKind};
+
+    use crate::{
+        format::{decode_image, encode_image, Kernel, Settings},
+        memory::Image,
+    };
+
+    use super::{
+        ContainerReader, ContainerWriter, FrameHeader, FrameType, PixelFormat, StreamHeader, FRAME,
+    };
+
+    const HEADER: StreamHeader = StreamHeader {
+        width: 24,
+        height: 16,
+        frame_rate: (30000, 1001),
+        pixel_format: PixelFormat::Gray8,
+        kernel: Kernel::Daub53,
+    };
+
+    /// Ten frames at 30 fps, a keyframe every four frames.
+    fn stream(extra_chunk: bool) -> (Vec<Vec<u8>>, Vec<u8>) {
+        let mut writer = ContainerWriter::new(Vec::new(), &HEADER).unwrap();
+        let mut frames = Vec::new();
+        for n in 0..10 {
+            let image = Image::with_fn(24, 16, |x, y| ((x * 5 + y * 3 + n * 7) % 256) as u16);
+            let data = encode_image(image.view(), &Settings::default()).unwrap();
+            let header = FrameHeader {
+                timestamp: n as u64 * 33_367,
+                frame_type: if n % 4 == 0 {
+                    FrameType::Key
+                } else {
+                    FrameType::Inter
+                },
+            };
+            writer.write_frame(&header, &data).unwrap();
+            if extra_chunk && n == 4 {
+                writer.write_chunk(*b"XTRA", b"unknown").unwrap();
+            }
+            frames.push(data);
+        }
+        (frames, writer.finish().unwrap())
+    }
+
+    #[test]
+    fn sequential_read() {
+        let (frames, data) = stream(true);
+        let mut reader = ContainerReader::new(Cursor::new(data)).unwrap();
+        assert_eq!(*reader.header(), HEADER);
+        assert_eq!(reader.index().len(), 10);
+        assert_eq!(reader.keyframes().count(), 3);
+
+        for (n, frame) in frames.iter().enumerate() {
+            let (header, payload) = reader.next_frame().unwrap().unwrap();
+            assert_eq!(header.timestamp, n as u64 * 33_367);
+            assert_eq!(&payload, frame);
+            decode_image(&payload).unwrap();
+        }
+        assert!(reader.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn seek_keyframes() {
+        let (frames, data) = stream(false);
+        let mut reader = ContainerReader::new(Cursor::new(data)).unwrap();
+
+        let header = reader.seek(7 * 33_367).unwrap().unwrap();
+        assert_eq!(header.timestamp, 4 * 33_367);
+        let (read, payload) = reader.next_frame().unwrap().unwrap();
+        assert_eq!(read, header);
+        assert_eq!(payload, frames[4]);
+
+        assert_eq!(
+            reader.seek(u64::MAX).unwrap().unwrap().timestamp,
+            8 * 33_367
+        );
+        assert_eq!(reader.next_frame().unwrap().unwrap().1, frames[8]);
+        assert_eq!(reader.seek(0).unwrap().unwrap().timestamp, 0);
+        assert_eq!(reader.next_frame().unwrap().unwrap().1, frames[0]);
+    }
+
+    #[test]
+    fn missing_index() {
+        let (frames, mut data) = stream(true);
+        // Cut in the middle of the seventh frame
+        let full = ContainerReader::new(Cursor::new(data.clone())).unwrap();
+        data.truncate(full.index()[6].offset as usize + 20);
+
+        let mut reader = ContainerReader::new(Cursor::new(data)).unwrap();
+        assert_eq!(reader.index(), &full.index()[..6]);
+        assert_eq!(
+            reader.seek(u64::MAX).unwrap().unwrap().timestamp,
+            4 * 33_367
+        );
+        assert_eq!(reader.next_frame().unwrap().unwrap().1, frames[4]);
+        assert_eq!(reader.next_frame().unwrap().unwrap().1, frames[5]);
+        let err = reader.next_frame().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+        // The rebuilt index stops at a frame chunk too short for its header
+        let (_, data) = stream(false);
+        let full = ContainerReader::new(Cursor::new(data.clone())).unwrap();
+        let mut writer = ContainerWriter::new(Vec::new(), &HEADER).unwrap();
+        let mut reader = ContainerReader::new(Cursor::new(data)).unwrap();
+        for _ in 0..3 {
+            let (header, payload) = reader.next_frame().unwrap().unwrap();
+            writer.write_frame(&header, &payload).unwrap();
+        }
+        writer.write_chunk(FRAME, b"short").unwrap();
+        let (header, payload) = reader.next_frame().unwrap().unwrap();
+        writer.write_frame(&header, &payload).unwrap();
+        let mut data = writer.finish().unwrap();
+        data.truncate(data.len() - 30);
+
+        let reader = ContainerReader::new(Cursor::new(data)).unwrap();
+        assert_eq!(reader.index(), &full.index()[..3]);
+    }
+
+    #[test]
+    fn invalid_streams() {
+        let (_, data) = stream(false);
+        let mut corrupted = data.clone();
+        corrupted[0] = b'X';
+        let err = ContainerReader::new(Cursor::new(corrupted)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let err = ContainerReader::new(Cursor::new(&data[..10])).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+        let mut corrupted = data.clone();
+        // Kernel of the stream header
+        corrupted[5 + 8 + 17] = 9;
+        let err = ContainerReader::new(Cursor::new(corrupted)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let mut corrupted = data.clone();
+        // Denominator of the frame rate
+        corrupted[5 + 8 + 12..5 + 8 + 16].fill(0);
+        let err = ContainerReader::new(Cursor::new(corrupted)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let header = StreamHeader {
+            frame_rate: (30, 0),
+            ..HEADER
+        };
+        let err = ContainerWriter::new(Vec::new(), &header).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        // Kernel of the image of the first frame
+        let mut corrupted = data.clone();
+        let offset = ContainerReader::new(Cursor::new(&data)).unwrap().index()[0].offset;
+        corrupted[offset as usize + 8 + 9 + 14] = 2;
+        let mut reader = ContainerReader::new(Cursor::new(corrupted)).unwrap();
+        let err = reader.next_frame().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let image = Image::with_value(24, 16, &0);
+        let mut writer = ContainerWriter::new(Vec::new(), &HEADER).unwrap();
+        for settings in [
+            Settings {
+                kernel: Kernel::Daub97,
+                ..Default::default()
+            },
+            Settings {
+                bit_depth: 10,
+                ..Default::default()
+            },
+        ] {
+            let data = encode_image(image.view(), &settings).unwrap();
+            let err = writer
+                .write_frame(&FrameHeader::default(), &data)
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+        let data =
+            encode_image(Image::with_value(16, 16, &0).view(), &Settings::default()).unwrap();
+        let err = writer
+            .write_frame(&FrameHeader::default(), &data)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
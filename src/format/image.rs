@@ -0,0 +1,482 @@
+//! Single compressed images: a fixed size [`Header`] followed by the entropy coded subbands.
+
+use std::io::{self, Read, Write};
+
+use crate::{
+    dwt::{
+        daub::{Daub53, Daub97},
+        haar::Haar,
+        lifting::Lifting,
+        Boundary, Dwt2, SubbandLayout,
+    },
+    entropy::{
+        ebcot::Ebcot,
+        ezw::Ezw,
+        huffman::Huffman,
+        rice::{RiceDecoder, RiceEncoder},
+        spiht::Spiht,
+    },
+    memory::{Image, ImageView},
+    numeric::Overflow,
+    quant::{DeadZone, Quantizer},
+};
+
+/// First bytes of a compressed image.
+pub const MAGIC: [u8; 4] = *b"WVPI";
+/// Version written by [`encode_image`], and the only one [`decode_image`] reads.
+pub const VERSION: u8 = 1;
+/// Largest number of pixels of an image, bounding the memory allocated from an untrusted header.
+pub const MAX_PIXELS: u64 = 1 << 26;
+
+pub(super) fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Wavelet of the decomposition, all of them being lossless in their integer lifting form.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kernel {
+    Haar,
+    #[default]
+    Daub53,
+    Daub97,
+}
+
+impl Kernel {
+    pub const ALL: [Kernel; 3] = [Kernel::Haar, Kernel::Daub53, Kernel::Daub97];
+
+    pub fn lifting(self) -> Lifting<'static> {
+        match self {
+            Kernel::Haar => Haar::LIFTING,
+            Kernel::Daub53 => Daub53::LIFTING,
+            Kernel::Daub97 => Daub97::LIFTING,
+        }
+    }
+}
+
+/// Entropy coder of the quantized subbands.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Entropy {
+    /// [`Ebcot`] with its default code block size.
+    #[default]
+    Ebcot,
+    Spiht,
+    Ezw,
+    Huffman,
+    /// Row by row Golomb-Rice coding of the low latency mode.
+    Rice,
+}
+
+impl Entropy {
+    pub const ALL: [Entropy; 5] = [
+        Entropy::Ebcot,
+        Entropy::Spiht,
+        Entropy::Ezw,
+        Entropy::Huffman,
+        Entropy::Rice,
+    ];
+}
+
+/// Index of `value` in `all`, its identifier in the stream.
+pub(super) fn id<T: PartialEq>(all: &[T], value: &T) -> u8 {
+    all.iter().position(|x| x == value).unwrap() as u8
+}
+
+pub(super) fn from_id<T: Copy>(all: &[T], id: u8, what: &str) -> io::Result<T> {
+    all.get(id as usize)
+        .copied()
+        .ok_or_else(|| invalid(format!("Unknown {what} {id}")))
+}
+
+/// Coding parameters of an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Settings {
+    /// Bits per sample of the image, from 1 to 16
+    pub bit_depth: u8,
+    pub kernel: Kernel,
+    pub boundary: Boundary,
+    pub levels: u8,
+    /// Quantizer of all the subbands, a step of 1 without dead zone being lossless
+    pub quantizer: DeadZone,
+    pub entropy: Entropy,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            bit_depth: 8,
+            kernel: Kernel::default(),
+            boundary: Boundary::default(),
+            levels: 5,
+            quantizer: DeadZone::new(1),
+            entropy: Entropy::default(),
+        }
+    }
+}
+
+/// Header of a compressed image, all the fields being big endian:
+///
+/// | Bytes | Field |
+/// |-------|-------|
+/// | 4     | [`MAGIC`] |
+/// | 1     | [`VERSION`] |
+/// | 4 + 4 | width and height |
+/// | 1     | bit depth |
+/// | 1     | kernel, index in [`Kernel::ALL`] |
+/// | 1     | boundary, index in [`Boundary::ALL`] |
+/// | 1     | decomposition levels |
+/// | 4 + 4 + 4 | quantizer step, threshold and offset |
+/// | 1     | entropy coder, index in [`Entropy::ALL`] |
+/// | 4     | payload length in bytes |
+///
+/// The entropy coded payload follows the header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Header {
+    pub width: u32,
+    pub height: u32,
+    pub settings: Settings,
+    pub payload_len: u32,
+}
+
+impl Header {
+    pub const SIZE: usize = 34;
+
+    pub fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        let s = &self.settings;
+        let mut bytes = Vec::with_capacity(Self::SIZE);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&self.width.to_be_bytes());
+        bytes.extend_from_slice(&self.height.to_be_bytes());
+        bytes.push(s.bit_depth);
+        bytes.push(id(&Kernel::ALL, &s.kernel));
+        bytes.push(id(&Boundary::ALL, &s.boundary));
+        bytes.push(s.levels);
+        for param in [
+            s.quantizer.step(),
+            s.quantizer.threshold(),
+            s.quantizer.offset(),
+        ] {
+            let param = u32::try_from(param).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Quantizer parameter does not fit in 32 bits",
+                )
+            })?;
+            bytes.extend_from_slice(&param.to_be_bytes());
+        }
+        bytes.push(id(&Entropy::ALL, &s.entropy));
+        bytes.extend_from_slice(&self.payload_len.to_be_bytes());
+        w.write_all(&bytes)
+    }
+
+    pub fn read(r: &mut impl Read) -> io::Result<Self> {
+        let mut bytes = [0u8; Self::SIZE];
+        r.read_exact(&mut bytes)?;
+        if bytes[..4] != MAGIC {
+            return Err(invalid("Not a compressed image"));
+        }
+        if bytes[4] != VERSION {
+            return Err(invalid(format!("Unsupported version {}", bytes[4])));
+        }
+        let u32_at = |i: usize| u32::from_be_bytes(bytes[i..i + 4].try_into().unwrap());
+
+        let bit_depth = bytes[13];
+        if !(1..=16).contains(&bit_depth) {
+            return Err(invalid(format!("Invalid bit depth {bit_depth}")));
+        }
+        let (step, threshold, offset) = (u32_at(17) as i64, u32_at(21) as i64, u32_at(25) as i64);
+        if step == 0 || offset >= step {
+            return Err(invalid("Invalid quantizer"));
+        }
+        let (width, height) = (u32_at(5), u32_at(9));
+        if width as u64 * height as u64 > MAX_PIXELS {
+            return Err(invalid(format!(
+                "Image of {width}x{height} pixels is too large"
+            )));
+        }
+        let levels = bytes[16];
+        if levels as u32 > max_levels(width, height) {
+            return Err(invalid(format!(
+                "{levels} decomposition levels of a {width}x{height} image"
+            )));
+        }
+        let settings = Settings {
+            bit_depth,
+            kernel: from_id(&Kernel::ALL, bytes[14], "kernel")?,
+            boundary: from_id(&Boundary::ALL, bytes[15], "boundary")?,
+            levels,
+            quantizer: DeadZone::new(step)
+                .with_threshold(threshold)
+                .with_offset(offset),
+            entropy: from_id(&Entropy::ALL, bytes[29], "entropy coder")?,
+        };
+        Ok(Self {
+            width,
+            height,
+            settings,
+            payload_len: u32_at(30),
+        })
+    }
+
+    pub fn layout(&self) -> SubbandLayout {
+        SubbandLayout::new(
+            self.width as usize,
+            self.height as usize,
+            self.settings.levels as usize,
+        )
+    }
+}
+
+/// Most decomposition levels of an image: the bit length of its larger side, enough to reduce it to a single sample.
+fn max_levels(width: u32, height: u32) -> u32 {
+    u32::BITS - width.max(height).leading_zeros()
+}
+
+/// Checks that `settings` can code an image of `width x height`, failing with [`io::ErrorKind::InvalidInput`].
+pub(crate) fn check_settings(width: usize, height: usize, settings: &Settings) -> io::Result<()> {
+    let input_error = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, msg.to_string());
+    if !(1..=16).contains(&settings.bit_depth) {
+        return Err(input_error("Bit depth must be between 1 and 16"));
+    }
+    let (Ok(width), Ok(height)) = (u32::try_from(width), u32::try_from(height)) else {
+        return Err(input_error("Image too large"));
+    };
+    if width as u64 * height as u64 > MAX_PIXELS {
+        return Err(input_error("Image too large"));
+    }
+    if settings.levels as u32 > max_levels(width, height) {
+        return Err(input_error(
+            "Too many decomposition levels for the image size",
+        ));
+    }
+    Ok(())
+}
+
+/// Level shift, decomposition and quantization of an image whose samples fit in `settings.bit_depth` bits.
+pub(crate) fn analyze(
+    image: ImageView<'_, u16>,
+    settings: &Settings,
+) -> io::Result<(Image<i32>, SubbandLayout)> {
+    check_settings(image.width(), image.height(), settings)?;
+    let max = (1u32 << settings.bit_depth) - 1;
+    if image.rows().flatten().any(|&x| x as u32 > max) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Sample larger than the bit depth",
+        ));
+    }
+
+    // Level shift to samples centred on zero
+    let half = 1i32 << (settings.bit_depth - 1);
+    let mut coefs = Image::with_fn(image.width(), image.height(), |x, y| {
+        *image.get(x, y) as i32 - half
+    });
+    let mut tmp = coefs.clone();
+    let kernel = settings.kernel.lifting().with_boundary(settings.boundary);
+    let layout = kernel.decompose(coefs.view_mut(), tmp.view_mut(), settings.levels as usize);
+    settings.quantizer.quantize_image(coefs.view_mut());
+    Ok((coefs, layout))
+}
+
+/// Inverse of [`analyze`] from the dequantized coefficients, clamping the samples to the bit depth.
+///
+/// Coefficients out of the range of the bit depth, from corrupted data, saturate instead of wrapping around.
+pub(crate) fn synthesize(mut coefs: Image<i32>, settings: &Settings) -> Image<u16> {
+    let mut tmp = coefs.clone();
+    let kernel = settings
+        .kernel
+        .lifting()
+        .with_boundary(settings.boundary)
+        .with_overflow(Overflow::Saturating);
+    kernel.reconstruct(coefs.view_mut(), tmp.view_mut(), settings.levels as usize);
+
+    let half = 1i32 << (settings.bit_depth - 1);
+    let max = (1i32 << settings.bit_depth) - 1;
+    Image::with_fn(coefs.width(), coefs.height(), |x, y| {
+        coefs.get(x, y).saturating_add(half).clamp(0, max) as u16
+    })
+}
+
+/// Compresses an image whose samples fit in `settings.bit_depth` bits.
+pub fn encode_image(image: ImageView<'_, u16>, settings: &Settings) -> io::Result<Vec<u8>> {
+    let (coefs, layout) = analyze(image, settings)?;
+    let input_error = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, msg.to_string());
+
+    let payload = match settings.entropy {
+        Entropy::Ebcot => Ebcot::default().encode(coefs.view(), &layout),
+        Entropy::Spiht => Spiht.encode(coefs.view(), &layout),
+        Entropy::Ezw => Ezw.encode(coefs.view(), &layout),
+        Entropy::Huffman => Huffman.encode(coefs.view(), &layout),
+        Entropy::Rice => {
+            let mut encoder = RiceEncoder::new(Vec::new(), &layout);
+            encoder.encode(coefs.view())?;
+            encoder.finish()?
+        }
+    };
+    let header = Header {
+        width: layout.width() as u32,
+        height: layout.height() as u32,
+        settings: *settings,
+        payload_len: u32::try_from(payload.len())
+            .map_err(|_| input_error("Payload larger than 4 GiB"))?,
+    };
+
+    let mut out = Vec::with_capacity(Header::SIZE + payload.len());
+    header.write(&mut out)?;
+    out.extend_from_slice(&payload);
+    Ok(out)
+}
+
+/// Decompresses an image written by [`encode_image`], returning it with its header.
+pub fn decode_image(data: &[u8]) -> io::Result<(Image<u16>, Header)> {
+    let mut reader = data;
+    let header = Header::read(&mut reader)?;
+    let payload = reader
+        .get(..header.payload_len as usize)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated payload"))?;
+
+    let (width, height) = (header.width as usize, header.height as usize);
+    let layout = header.layout();
+    let settings = &header.settings;
+    let mut coefs = Image::with_value(width, height, &0i32);
+    match settings.entropy {
+        Entropy::Ebcot => {
+            Ebcot::default().decode(payload, &layout, coefs.view_mut())?;
+        }
+        Entropy::Spiht => {
+            Spiht.decode(payload, &layout, coefs.view_mut())?;
+        }
+        Entropy::Ezw => {
+            Ezw.decode(payload, &layout, coefs.view_mut())?;
+        }
+        Entropy::Huffman => Huffman.decode(payload, &layout, coefs.view_mut())?,
+        Entropy::Rice => RiceDecoder::new(payload, &layout).decode(coefs.view_mut())?,
+    }
+
+    settings.quantizer.dequantize_image(coefs.view_mut());
+    let image = synthesize(coefs, settings);
+    Ok((image, header))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::ErrorKind;
+
+    use crate::{dwt::Boundary, memory::Image, quant::DeadZone};
+
+    use super::{decode_image, encode_image, Entropy, Header, Kernel, Settings};
+
+    fn test_image(bit_depth: u8) -> Image<u16> {
+        let max = (1usize << bit_depth) - 1;
+        Image::with_fn(53, 37, |x, y| {
+            (((x * x + y * y) * max / (52 * 52 + 36 * 36)) + (x * 7 + y * 3) % 5).min(max) as u16
+        })
+    }
+
+    #[test]
+    fn lossless_round_trip() {
+        for (kernel, boundary, entropy, bit_depth) in [
+            (Kernel::Haar, Boundary::Symmetric, Entropy::Ebcot, 8),
+            (Kernel::Daub53, Boundary::Periodic, Entropy::Spiht, 12),
+            (Kernel::Daub97, Boundary::HalfSymmetric, Entropy::Ezw, 16),
+            (Kernel::Daub53, Boundary::Zero, Entropy::Huffman, 1),
+            (Kernel::Daub97, Boundary::Constant, Entropy::Rice, 10),
+        ] {
+            let image = test_image(bit_depth);
+            let settings = Settings {
+                bit_depth,
+                kernel,
+                boundary,
+                levels: 3,
+                entropy,
+                ..Default::default()
+            };
+            let data = encode_image(image.view(), &settings).unwrap();
+            let (decoded, header) = decode_image(&data).unwrap();
+            assert_eq!(header.settings, settings);
+            assert_eq!((header.width, header.height), (53, 37));
+            assert_eq!(data.len(), Header::SIZE + header.payload_len as usize);
+            assert!(decoded.rows().eq(image.rows()), "{settings:?}");
+        }
+    }
+
+    #[test]
+    fn lossy() {
+        let image = test_image(8);
+        let settings = Settings {
+            quantizer: DeadZone::new(8).with_threshold(4).with_offset(4),
+            ..Default::default()
+        };
+        let lossless = encode_image(image.view(), &Settings::default()).unwrap();
+        let data = encode_image(image.view(), &settings).unwrap();
+        assert!(
+            data.len() < lossless.len() / 2,
+            "{} {}",
+            data.len(),
+            lossless.len()
+        );
+
+        let (decoded, header) = decode_image(&data).unwrap();
+        assert_eq!(header.settings.quantizer, settings.quantizer);
+        let max_error = decoded
+            .rows()
+            .flatten()
+            .zip(image.rows().flatten())
+            .map(|(&a, &b)| a.abs_diff(b))
+            .max()
+            .unwrap();
+        assert!(0 < max_error && max_error < 32, "{max_error}");
+    }
+
+    #[test]
+    fn invalid_streams() {
+        let image = test_image(8);
+        let data = encode_image(image.view(), &Settings::default()).unwrap();
+
+        let kind = |data: &[u8]| decode_image(data).unwrap_err().kind();
+        assert_eq!(kind(&data[..10]), ErrorKind::UnexpectedEof);
+        assert_eq!(kind(&data[..data.len() - 1]), ErrorKind::UnexpectedEof);
+        let mut corrupted = data.clone();
+        corrupted[0] = b'X';
+        assert_eq!(kind(&corrupted), ErrorKind::InvalidData);
+        let mut corrupted = data.clone();
+        corrupted[4] = 2;
+        assert_eq!(kind(&corrupted), ErrorKind::InvalidData);
+        let mut corrupted = data.clone();
+        corrupted[14] = 200;
+        assert_eq!(kind(&corrupted), ErrorKind::InvalidData);
+        // Decomposition levels
+        let mut corrupted = data.clone();
+        corrupted[16] = 200;
+        assert_eq!(kind(&corrupted), ErrorKind::InvalidData);
+        // Width of 2^32 - 1 pixels
+        let mut corrupted = data.clone();
+        corrupted[5..9].fill(0xff);
+        assert_eq!(kind(&corrupted), ErrorKind::InvalidData);
+
+        // Largest quantizer step: the coefficients, here the samples, saturate
+        let settings = Settings {
+            levels: 0,
+            ..Default::default()
+        };
+        let mut corrupted = encode_image(image.view(), &settings).unwrap();
+        corrupted[17..21].fill(0xff);
+        let (decoded, _) = decode_image(&corrupted).unwrap();
+        for (&x, &y) in decoded.rows().flatten().zip(image.rows().flatten()) {
+            assert_eq!(x, [0, 128, 255][(y.cmp(&128) as i8 + 1) as usize]);
+        }
+
+        let too_deep = Settings {
+            bit_depth: 4,
+            ..Default::default()
+        };
+        let err = encode_image(image.view(), &too_deep).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let too_many_levels = Settings {
+            levels: 7,
+            ..Default::default()
+        };
+        let err = encode_image(image.view(), &too_many_levels).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,13 @@
+//! Compressed bitstream formats: single images, and video streams of compressed frames.
+
+pub use container::{
+    ContainerReader, ContainerWriter, FrameHeader, FrameType, IndexEntry, PixelFormat,
+    StreamHeader, FRAME, HEAD, INDEX, STREAM_MAGIC, STREAM_VERSION, TAIL,
+};
+pub(crate) use image::{analyze, check_settings, synthesize};
+pub use image::{
+    decode_image, encode_image, Entropy, Header, Kernel, Settings, MAGIC, MAX_PIXELS, VERSION,
+};
+
+mod container;
+mod image;
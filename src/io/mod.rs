@@ -2,9 +2,8 @@ use std::io::{BufRead, Read};
 
 use crate::memory::{Image, ImageView};
 
-pub fn load_pgm(path: impl AsRef<std::path::Path>) -> Result<Image<u8>, std::io::Error> {
-    let mut file = std::io::BufReader::new(std::fs::OpenOptions::new().read(true).open(path)?);
-
+/// Reads the header of a binary PGM file, returning its width, height and max value.
+fn read_pgm_header(file: &mut impl BufRead) -> Result<(usize, usize, usize), std::io::Error> {
     let mut line = String::new();
     file.read_line(&mut line)?;
     if line != "P5\n" {
@@ -47,14 +46,26 @@ pub fn load_pgm(path: impl AsRef<std::path::Path>) -> Result<Image<u8>, std::io:
         }
     }
 
-    let width = nums[0];
-    let height = nums[1];
     let max_value = nums[2];
+    if max_value == 0 || max_value >= 65536 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Wrong PGM file: invalid max value {max_value}"),
+        ));
+    }
+
+    Ok((nums[0], nums[1], max_value))
+}
+
+pub fn load_pgm(path: impl AsRef<std::path::Path>) -> Result<Image<u8>, std::io::Error> {
+    let mut file = std::io::BufReader::new(std::fs::OpenOptions::new().read(true).open(path)?);
+
+    let (width, height, max_value) = read_pgm_header(&mut file)?;
 
     if max_value >= 256 {
         return Err(std::io::Error::new(
             std::io::ErrorKind::InvalidData,
-            String::from("Wrong PGM file: max value too large"),
+            String::from("Wrong PGM file: max value too large, use load_pgm16"),
         ));
     }
 
@@ -66,6 +77,33 @@ pub fn load_pgm(path: impl AsRef<std::path::Path>) -> Result<Image<u8>, std::io:
     Ok(image)
 }
 
+/// Loads a PGM file of any bit depth, returning the image with its max value.
+///
+/// Files with a max value of 256 or more store 2 bytes per sample, most significant byte first.
+pub fn load_pgm16(path: impl AsRef<std::path::Path>) -> Result<(Image<u16>, u16), std::io::Error> {
+    let mut file = std::io::BufReader::new(std::fs::OpenOptions::new().read(true).open(path)?);
+
+    let (width, height, max_value) = read_pgm_header(&mut file)?;
+    let bytes = if max_value >= 256 { 2 } else { 1 };
+
+    let mut image = Image::with_stride(width, height, (2 * width).next_multiple_of(64));
+    let mut buffer = vec![0u8; width * bytes];
+    for row in image.rows_mut() {
+        file.read_exact(&mut buffer)?;
+        for (dst, src) in row.iter_mut().zip(buffer.chunks_exact(bytes)) {
+            *dst = src.iter().fold(0, |x, &b| (x << 8) | b as u16);
+            if *dst as usize > max_value {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Wrong PGM file: sample {dst} above max value {max_value}"),
+                ));
+            }
+        }
+    }
+
+    Ok((image, max_value as u16))
+}
+
 pub fn save_pgm(
     image: ImageView<u8>,
     path: impl AsRef<std::path::Path>,
@@ -87,3 +125,88 @@ pub fn save_pgm(
 
     Ok(())
 }
+
+/// Saves a PGM file with the given max value, using 2 bytes per sample if it is 256 or more.
+///
+/// Samples are clamped to the max value.
+pub fn save_pgm16(
+    image: ImageView<u16>,
+    max_value: u16,
+    path: impl AsRef<std::path::Path>,
+) -> Result<(), std::io::Error> {
+    use std::io::Write;
+    if max_value == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            String::from("PGM max value should be non zero"),
+        ));
+    }
+    let mut file = std::io::BufWriter::new(
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?,
+    );
+
+    write!(
+        file,
+        "P5\n{} {}\n{max_value}\n",
+        image.width(),
+        image.height()
+    )?;
+
+    let mut buffer = Vec::with_capacity(2 * image.width());
+    for row in image.rows() {
+        buffer.clear();
+        for &x in row {
+            let x = x.min(max_value);
+            if max_value >= 256 {
+                buffer.extend_from_slice(&x.to_be_bytes());
+            } else {
+                buffer.push(x as u8);
+            }
+        }
+        file.write_all(&buffer)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::memory::Image;
+
+    #[test]
+    fn pgm16_round_trip() {
+        let path = std::env::temp_dir().join(format!("pgm16_{}.pgm", std::process::id()));
+        for max_value in [255, 1023, 4095, 65535] {
+            let image = Image::with_fn(37, 5, |x, y| {
+                ((x * 997 + y * 8191) % (max_value as usize + 1)) as u16
+            });
+            super::save_pgm16(image.view(), max_value, &path).unwrap();
+            let (loaded, loaded_max) = super::load_pgm16(&path).unwrap();
+            assert_eq!(loaded_max, max_value);
+            assert_eq!(loaded, image);
+
+            let bytes = std::fs::read(&path).unwrap();
+            let samples = if max_value >= 256 { 2 } else { 1 } * 37 * 5;
+            assert_eq!(
+                bytes.len(),
+                format!("P5\n37 5\n{max_value}\n").len() + samples
+            );
+        }
+
+        assert!(super::load_pgm(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn pgm16_above_max_value() {
+        let path = std::env::temp_dir().join(format!("pgm16_max_{}.pgm", std::process::id()));
+        std::fs::write(&path, b"P5\n2 1\n1023\n\x03\xff\x04\x00").unwrap();
+        let err = super::load_pgm16(&path).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,6 +1,6 @@
 #![allow(clippy::missing_safety_doc)]
 
-use std::{alloc::Layout, mem::MaybeUninit, ptr::NonNull};
+use std::{alloc::Layout, mem::MaybeUninit, num::NonZero, ptr::NonNull};
 
 use super::{
     slice::SlicePtr,
@@ -98,9 +98,9 @@ impl<'a, T> ImageView<'a, T> {
 
     pub fn into_subview(self, x: usize, y: usize, width: usize, height: usize) -> Self {
         let mut matrix = self.as_matrix();
-        matrix = matrix.into_partial(x, height, strided::STEP_1);
+        matrix = matrix.into_partial(y, height, strided::STEP_1);
         matrix.transpose01();
-        matrix = matrix.into_partial(y, width, strided::STEP_1);
+        matrix = matrix.into_partial(x, width, strided::STEP_1);
         matrix.transpose01();
 
         Self(unsafe { matrix.try_into().unwrap_unchecked() })
@@ -259,9 +259,9 @@ impl<'a, T> ImageViewMut<'a, T> {
 
     pub fn into_subview_mut(self, x: usize, y: usize, width: usize, height: usize) -> Self {
         let mut matrix = self.into_matrix_mut();
-        matrix = matrix.into_partial(x, height, strided::STEP_1);
+        matrix = matrix.into_partial(y, height, strided::STEP_1);
         matrix.transpose01();
-        matrix = matrix.into_partial(y, width, strided::STEP_1);
+        matrix = matrix.into_partial(x, width, strided::STEP_1);
         matrix.transpose01();
 
         Self(unsafe { matrix.try_into().unwrap_unchecked() })
@@ -280,6 +280,14 @@ impl<'a, T> ImageViewMut<'a, T> {
         ImageView(self.0.into_borrow()).into_subview(x, y, width, height)
     }
 
+    /// Splits an image made of frames stacked vertically into a `[frame][row][col]` volume.
+    ///
+    /// Trailing rows that do not fill a whole frame are left out.
+    pub fn into_frames_mut(self, height: usize) -> Strided<Strided<Strided<&'a mut T>>> {
+        let height = NonZero::new(height).expect("Frame height should be non zero");
+        self.into_matrix_mut().into_chunks(height).0
+    }
+
     pub fn subview(&self, x: usize, y: usize, width: usize, height: usize) -> ImageView<'_, T> {
         self.view().into_subview(x, y, width, height)
     }
@@ -481,6 +489,7 @@ impl<T> Image<T> {
 }
 
 impl<T> Image<MaybeUninit<T>> {
+    #[allow(clippy::manual_is_multiple_of)]
     fn new_uninit_or_zeroed(
         width: usize,
         height: usize,
@@ -621,6 +630,39 @@ mod test {
         }
     }
 
+    #[test]
+    fn subview() {
+        // A non-square image and offsets, so that swapped coordinates are noticed
+        let mut image = Image::with_fn(7, 4, |x, y| y * 10 + x);
+        let view = image.subview(5, 1, 2, 3);
+        assert_eq!((view.width(), view.height()), (2, 3));
+        assert!(view.rows().eq([[15, 16], [25, 26], [35, 36]].iter()));
+
+        *image.subview_mut(5, 1, 2, 3).get_mut(1, 2) = 0;
+        assert_eq!(*image.get(6, 3), 0);
+    }
+
+    #[test]
+    fn empty_subview() {
+        // Empty subviews at the edges, like the subbands of a one sample high image
+        let mut image = Image::with_fn(5, 1, |x, y| y * 10 + x);
+        for (x, y, width, height) in [
+            (0, 1, 3, 0),
+            (3, 1, 2, 0),
+            (5, 0, 0, 1),
+            (2, 0, 0, 1),
+            (0, 0, 0, 0),
+            (5, 1, 0, 0),
+        ] {
+            let view = image.subview(x, y, width, height);
+            assert_eq!((view.width(), view.height()), (width, height));
+            assert_eq!(view.rows().flatten().count(), 0);
+
+            let view = image.subview_mut(x, y, width, height);
+            assert_eq!((view.width(), view.height()), (width, height));
+        }
+    }
+
     #[test]
     fn oob() {
         let mut image = Image::<u8>::new(10, 10);
@@ -573,13 +573,7 @@ fn transpose_state(
     stride_b: &mut isize,
 ) {
     std::mem::swap(len_a, len_b);
-
-    if *len_a == 0 || *len_b == 0 {
-        *stride_a = 0;
-        *stride_b = 0;
-    } else {
-        std::mem::swap(stride_a, stride_b);
-    }
+    std::mem::swap(stride_a, stride_b);
 }
 
 impl<T> StridedState<StridedState<T>> {
@@ -818,7 +812,8 @@ impl<T: FromPtr> Strided<T> {
                 self.unchecked_into_partial(start, len, step_by.get())
             }
         } else {
-            unsafe { self.unchecked_into_partial(0, 0, 0) }
+            // Keep the stride so that an empty view is still contiguous
+            unsafe { self.unchecked_into_partial(0, 0, step_by.get()) }
         }
     }
 
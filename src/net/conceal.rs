@@ -0,0 +1,478 @@
+use std::io;
+
+use crate::{
+    bitio::{BitReader, BitWriter},
+    dwt::{Orientation, Subband, SubbandLayout},
+    entropy::huffman::Huffman,
+    format::{analyze, check_settings, synthesize, Settings},
+    memory::{Image, ImageView},
+    quant::Quantizer,
+};
+
+use super::packet::invalid;
+
+/// Encodes an image as one independent segment per subband, from the `LL` band to the finest details,
+/// to be packetized with [`Packetizer`](super::Packetizer).
+///
+/// Each segment is coded with [`Huffman::encode_band`], whatever `settings.entropy`.
+pub fn encode_frame(image: ImageView<'_, u16>, settings: &Settings) -> io::Result<Vec<Vec<u8>>> {
+    let (coefs, layout) = analyze(image, settings)?;
+    layout
+        .bands()
+        .map(|band| {
+            if band.width == 0 || band.height == 0 {
+                return Ok(Vec::new());
+            }
+            let mut writer = BitWriter::new(Vec::new());
+            Huffman.encode_band(band.view(coefs.view()), &mut writer)?;
+            writer.finish()
+        })
+        .collect()
+}
+
+/// Replacement of the missing detail subbands.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Concealment {
+    /// Missing coefficients are zero, which blurs the details of the band.
+    #[default]
+    Zero,
+    /// Missing coefficients are the coefficient of their parent in the coarser band of the same orientation, halved,
+    /// as the details of smooth regions decay across scales.
+    /// The coarsest detail bands have no parent and are zero.
+    Parent,
+}
+
+/// Origin of the `LL` band of a decoded frame.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LowBand {
+    #[default]
+    Received,
+    /// Missing, replaced by the `LL` band of the previous frame
+    Previous,
+    /// Missing without a previous frame, replaced by mid-grey
+    Zero,
+}
+
+/// What the decoder concealed in a frame.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct Report {
+    pub low_band: LowBand,
+    /// Detail bands missing or corrupted, replaced according to the [`Concealment`] mode
+    pub concealed: Vec<Subband>,
+}
+
+impl Report {
+    /// Tells whether the frame was decoded without concealment.
+    pub fn is_clean(&self) -> bool {
+        self.low_band == LowBand::Received && self.concealed.is_empty()
+    }
+}
+
+/// Decoder of the frames encoded by [`encode_frame`], reconstructing them whatever the missing subbands.
+///
+/// Missing or corrupted subbands are concealed and reported, only inconsistent frames are errors.
+/// A subband is corrupted if it does not decode, or if a dequantized coefficient is out of the range
+/// the transform gives to samples of the bit depth.
+#[derive(Debug, Clone)]
+pub struct ResilientDecoder {
+    settings: Settings,
+    layout: SubbandLayout,
+    pub concealment: Concealment,
+    /// Dequantized `LL` band of the previous frame
+    previous: Option<Image<i32>>,
+}
+
+impl ResilientDecoder {
+    /// Fails with [`io::ErrorKind::InvalidInput`] if `settings` cannot code a `width x height` image.
+    pub fn new(width: usize, height: usize, settings: Settings) -> io::Result<Self> {
+        check_settings(width, height, &settings)?;
+        Ok(Self {
+            layout: SubbandLayout::new(width, height, settings.levels as usize),
+            settings,
+            concealment: Concealment::default(),
+            previous: None,
+        })
+    }
+
+    pub fn with_concealment(self, concealment: Concealment) -> Self {
+        Self {
+            concealment,
+            ..self
+        }
+    }
+
+    /// Decodes a frame from its subband segments, `None` for the missing ones, as in [`Frame::bands`](super::Frame::bands).
+    pub fn decode(&mut self, bands: &[Option<Vec<u8>>]) -> io::Result<(Image<u16>, Report)> {
+        let subbands = self.layout.bands().collect::<Vec<_>>();
+        if bands.len() != subbands.len() {
+            return Err(invalid(format!(
+                "Frame of {} subbands instead of {}",
+                bands.len(),
+                subbands.len()
+            )));
+        }
+
+        let mut coefs = Image::with_value(self.layout.width(), self.layout.height(), &0i32);
+        let mut missing = Vec::new();
+        for (band, data) in subbands.iter().zip(bands) {
+            if band.width == 0 || band.height == 0 {
+                continue;
+            }
+            let decoded = data.as_ref().is_some_and(|data| {
+                Huffman
+                    .decode_band(
+                        &mut BitReader::new(data.as_slice()),
+                        band.view_mut(coefs.view_mut()),
+                    )
+                    .is_ok()
+                    && self.in_range(&coefs, band)
+            });
+            if !decoded {
+                band.view_mut(coefs.view_mut())
+                    .for_each_mut(|_, _, x| *x = 0);
+                missing.push(*band);
+            }
+        }
+        self.settings.quantizer.dequantize_image(coefs.view_mut());
+
+        let mut report = Report::default();
+        for band in missing {
+            if band.orientation == Orientation::LL {
+                report.low_band = self.conceal_low_band(&mut coefs, &band);
+            } else {
+                self.conceal(&mut coefs, &band);
+                report.concealed.push(band);
+            }
+        }
+
+        let ll = self.layout.band(self.layout.levels(), Orientation::LL);
+        let low = ll.view(coefs.view());
+        self.previous = Some(Image::with_fn(ll.width, ll.height, |x, y| *low.get(x, y)));
+        Ok((synthesize(coefs, &self.settings), report))
+    }
+
+    /// Whether the dequantized coefficients of a decoded band are in the range of the transform of valid samples.
+    ///
+    /// Each level of the 2D transform multiplies magnitudes by less than 8,
+    /// and dequantization adds less than a step to them.
+    fn in_range(&self, coefs: &Image<i32>, band: &Subband) -> bool {
+        let quantizer = &self.settings.quantizer;
+        let bits = self.settings.bit_depth as usize - 1 + 3 * band.level;
+        let max = (1u64 << bits.min(62)) + quantizer.step() as u64;
+        band.view(coefs.view())
+            .rows()
+            .flatten()
+            .all(|&q| quantizer.dequantize(q as i64).unsigned_abs() <= max)
+    }
+
+    fn conceal_low_band(&self, coefs: &mut Image<i32>, band: &Subband) -> LowBand {
+        let Some(previous) = &self.previous else {
+            return LowBand::Zero;
+        };
+        let mut view = band.view_mut(coefs.view_mut());
+        for (dst, src) in view.rows_mut().zip(previous.rows()) {
+            dst.copy_from_slice(src);
+        }
+        LowBand::Previous
+    }
+
+    /// Conceals a detail band, the missing bands being processed from the coarsest,
+    /// so that a concealed parent is used in turn.
+    fn conceal(&self, coefs: &mut Image<i32>, band: &Subband) {
+        if self.concealment == Concealment::Zero || band.level == self.layout.levels() {
+            return;
+        }
+        let parent = self.layout.band(band.level + 1, band.orientation);
+        if parent.width == 0 || parent.height == 0 {
+            return;
+        }
+        for y in 0..band.height {
+            for x in 0..band.width {
+                let px = parent.x + (x / 2).min(parent.width - 1);
+                let py = parent.y + (y / 2).min(parent.height - 1);
+                let value = *coefs.get(px, py) / 2;
+                *coefs.get_mut(band.x + x, band.y + y) = value;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use crate::{
+        bitio::BitWriter,
+        dwt::{Orientation, SubbandLayout},
+        entropy::huffman::Huffman,
+        format::{Kernel, Settings},
+        memory::Image,
+        net::{Depacketizer, Packetizer},
+        quant::DeadZone,
+    };
+
+    use super::{encode_frame, Concealment, LowBand, ResilientDecoder};
+
+    fn image(width: usize, height: usize, shift: usize) -> Image<u16> {
+        Image::with_fn(width, height, |x, y| {
+            let (x, y) = ((x + shift) as i32, y as i32);
+            (128 + (x - 30) * (y - 20) / 12 + if (x / 8 + y / 8) % 2 == 0 { 30 } else { 0 })
+                .clamp(0, 255) as u16
+        })
+    }
+
+    fn squared_error(a: &Image<u16>, b: &Image<u16>) -> i64 {
+        let mut sum = 0;
+        for (a, b) in a.rows().zip(b.rows()) {
+            for (&a, &b) in a.iter().zip(b) {
+                sum += (a as i64 - b as i64).pow(2);
+            }
+        }
+        sum
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            levels: 3,
+            kernel: Kernel::Daub97,
+            quantizer: DeadZone::new(2),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn received() {
+        for (width, height, levels) in [(64, 48, 3), (37, 21, 4), (5, 1, 2), (16, 16, 0)] {
+            let settings = Settings {
+                levels,
+                ..Default::default()
+            };
+            let input = image(width, height, 0);
+            let bands = encode_frame(input.view(), &settings).unwrap();
+            let mut decoder = ResilientDecoder::new(width, height, settings).unwrap();
+            let (output, report) = decoder
+                .decode(&bands.into_iter().map(Some).collect::<Vec<_>>())
+                .unwrap();
+            assert!(report.is_clean());
+            assert!(output.rows().eq(input.rows()), "{width}x{height}");
+        }
+    }
+
+    #[test]
+    fn lost_details() {
+        let settings = settings();
+        let input = image(64, 48, 0);
+        let bands = encode_frame(input.view(), &settings)
+            .unwrap()
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        let (reference, _) = ResilientDecoder::new(64, 48, settings)
+            .unwrap()
+            .decode(&bands)
+            .unwrap();
+        let reference_error = squared_error(&reference, &input);
+
+        // The finest level, and the coarsest horizontal details
+        let mut partial = bands.clone();
+        for b in [3, 7, 8, 9] {
+            partial[b] = None;
+        }
+        for concealment in [Concealment::Zero, Concealment::Parent] {
+            let mut decoder = ResilientDecoder::new(64, 48, settings)
+                .unwrap()
+                .with_concealment(concealment);
+            let (output, report) = decoder.decode(&partial).unwrap();
+            assert_eq!(report.low_band, LowBand::Received);
+            let concealed = report
+                .concealed
+                .iter()
+                .map(|band| (band.level, band.orientation))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                concealed,
+                [
+                    (3, Orientation::HH),
+                    (1, Orientation::LH),
+                    (1, Orientation::HL),
+                    (1, Orientation::HH)
+                ]
+            );
+            let error = squared_error(&output, &input);
+            assert!(error > reference_error, "{concealment:?}");
+            assert!(error < 64 * 48 * 20 * 20, "{concealment:?}: {error}");
+        }
+    }
+
+    #[test]
+    fn lost_low_band() {
+        let settings = settings();
+        let mut decoder = ResilientDecoder::new(40, 30, settings).unwrap();
+        let input = image(40, 30, 0);
+        let mut bands = encode_frame(input.view(), &settings)
+            .unwrap()
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        bands[0] = None;
+        let (_, report) = decoder.decode(&bands).unwrap();
+        assert_eq!(report.low_band, LowBand::Zero);
+
+        // A static scene: the previous low band is the lost one
+        let complete = encode_frame(input.view(), &settings)
+            .unwrap()
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        let (reference, _) = decoder.decode(&complete).unwrap();
+        let (output, report) = decoder.decode(&bands).unwrap();
+        assert_eq!(report.low_band, LowBand::Previous);
+        assert!(report.concealed.is_empty());
+        assert!(output.rows().eq(reference.rows()));
+
+        // A moving one stays close to it
+        let moved = image(40, 30, 1);
+        let mut bands = encode_frame(moved.view(), &settings)
+            .unwrap()
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        bands[0] = None;
+        let (output, _) = decoder.decode(&bands).unwrap();
+        assert!(squared_error(&output, &moved) < 40 * 30 * 16 * 16);
+    }
+
+    #[test]
+    fn corrupted() {
+        let settings = settings();
+        let input = image(33, 17, 0);
+        let bands = encode_frame(input.view(), &settings).unwrap();
+        let mut decoder = ResilientDecoder::new(33, 17, settings).unwrap();
+        for b in 0..bands.len() {
+            for len in [0, 1, bands[b].len() / 2] {
+                let mut partial = bands.iter().cloned().map(Some).collect::<Vec<_>>();
+                partial[b].as_mut().unwrap().truncate(len);
+                decoder.decode(&partial).unwrap();
+            }
+            let mut partial = bands.iter().cloned().map(Some).collect::<Vec<_>>();
+            for byte in partial[b].as_mut().unwrap() {
+                *byte = byte.wrapping_mul(31) ^ 0xa5;
+            }
+            decoder.decode(&partial).unwrap();
+        }
+        assert!(decoder.decode(&[None]).is_err());
+    }
+
+    /// Segments of subbands whose coefficients are all `value`.
+    fn constant_bands(layout: &SubbandLayout, value: i32) -> Vec<Option<Vec<u8>>> {
+        layout
+            .bands()
+            .map(|band| {
+                let mut writer = BitWriter::new(Vec::new());
+                let coefs = Image::with_value(band.width, band.height, &value);
+                Huffman.encode_band(coefs.view(), &mut writer).unwrap();
+                Some(writer.finish().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn extreme_values() {
+        // The samples themselves, out of the bit depth
+        let settings = Settings {
+            levels: 0,
+            quantizer: DeadZone::new(1),
+            ..Default::default()
+        };
+        let layout = SubbandLayout::new(4, 3, 0);
+        let mut decoder = ResilientDecoder::new(4, 3, settings).unwrap();
+        let (output, report) = decoder.decode(&constant_bands(&layout, i32::MAX)).unwrap();
+        assert_eq!(report.low_band, LowBand::Zero);
+        assert!(output.rows().flatten().all(|&x| x == 128));
+
+        // Coefficients of the coarsest level have room for `i32::MAX`, the others are concealed
+        let settings = Settings {
+            bit_depth: 16,
+            levels: 6,
+            quantizer: DeadZone::new(1),
+            ..Default::default()
+        };
+        let layout = SubbandLayout::new(64, 64, 6);
+        let mut decoder = ResilientDecoder::new(64, 64, settings).unwrap();
+        let (output, report) = decoder.decode(&constant_bands(&layout, i32::MAX)).unwrap();
+        assert_eq!(report.low_band, LowBand::Received);
+        assert_eq!(report.concealed.len(), 15);
+        // The reconstruction saturates instead of wrapping around to black
+        assert!(output.rows().flatten().all(|&x| x == u16::MAX));
+
+        // Valid extremes are in range
+        for kernel in Kernel::ALL {
+            let settings = Settings {
+                kernel,
+                levels: 4,
+                quantizer: DeadZone::new(1),
+                ..Default::default()
+            };
+            let input = Image::with_fn(48, 40, |x, y| ((x + y) % 2 * 255) as u16);
+            let bands = encode_frame(input.view(), &settings).unwrap();
+            let mut decoder = ResilientDecoder::new(48, 40, settings).unwrap();
+            let (output, report) = decoder
+                .decode(&bands.into_iter().map(Some).collect::<Vec<_>>())
+                .unwrap();
+            assert!(report.is_clean(), "{kernel:?}");
+            assert!(output.rows().eq(input.rows()));
+        }
+    }
+
+    #[test]
+    fn invalid_settings() {
+        let kind = |width, height, settings| {
+            ResilientDecoder::new(width, height, settings)
+                .unwrap_err()
+                .kind()
+        };
+        for bit_depth in [0, 17] {
+            let settings = Settings {
+                bit_depth,
+                ..settings()
+            };
+            assert_eq!(kind(16, 16, settings), io::ErrorKind::InvalidInput);
+        }
+        let settings = Settings {
+            levels: 6,
+            ..settings()
+        };
+        assert_eq!(kind(16, 16, settings), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            kind(1 << 14, 1 << 13, settings),
+            io::ErrorKind::InvalidInput
+        );
+        assert!(ResilientDecoder::new(32, 16, settings).is_ok());
+    }
+
+    #[test]
+    fn packet_loss() {
+        let settings = settings();
+        let input = image(64, 64, 0);
+        let bands = encode_frame(input.view(), &settings).unwrap();
+        let packets = Packetizer::new(3, 256)
+            .unwrap()
+            .packetize(9, &bands)
+            .unwrap();
+        assert!(packets.len() > 2);
+
+        let mut depacketizer = Depacketizer::new();
+        for packet in &packets[..packets.len() - 1] {
+            depacketizer.push(packet).unwrap();
+        }
+        let frame = depacketizer.take(3, 9).unwrap();
+        let missing = frame.missing_bands().collect::<Vec<_>>();
+        assert!(!missing.is_empty());
+
+        let mut decoder = ResilientDecoder::new(64, 64, settings).unwrap();
+        let (output, report) = decoder.decode(&frame.bands).unwrap();
+        assert_eq!(report.low_band, LowBand::Received);
+        assert_eq!(report.concealed.len(), missing.len());
+        assert!(squared_error(&output, &input) < 64 * 64 * 20 * 20);
+    }
+}
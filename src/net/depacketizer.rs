@@ -0,0 +1,229 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+};
+
+use super::packet::{invalid, PacketHeader, Piece};
+
+/// Subband being reassembled, with the byte ranges received so far.
+#[derive(Debug, Clone)]
+struct Band {
+    data: Vec<u8>,
+    /// Received ranges, as `start -> end`, merged when they touch
+    received: BTreeMap<usize, usize>,
+}
+
+impl Band {
+    fn new(len: usize) -> Self {
+        Self {
+            data: vec![0; len],
+            received: BTreeMap::new(),
+        }
+    }
+
+    fn insert(&mut self, offset: usize, data: &[u8]) {
+        let (mut start, mut end) = (offset, offset + data.len());
+        self.data[start..end].copy_from_slice(data);
+        // Merge with the overlapping or adjacent ranges
+        let overlapping = self
+            .received
+            .range(..=end)
+            .filter(|&(_, &e)| e >= start)
+            .map(|(&s, &e)| (s, e))
+            .collect::<Vec<_>>();
+        for (s, e) in overlapping {
+            self.received.remove(&s);
+            start = start.min(s);
+            end = end.max(e);
+        }
+        self.received.insert(start, end);
+    }
+
+    fn is_complete(&self) -> bool {
+        self.data.is_empty() || self.received.get(&0) == Some(&self.data.len())
+    }
+}
+
+/// Frame being reassembled.
+#[derive(Debug, Clone)]
+struct Assembly {
+    count: u16,
+    packets: Vec<bool>,
+    bands: Vec<Option<Band>>,
+    /// Total length of the allocated subbands
+    len: usize,
+    /// Order of the first packet of the frame among all the frames
+    sequence: u64,
+}
+
+/// Frame reassembled from its packets, possibly with missing parts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub stream_id: u16,
+    pub frame: u32,
+    /// Segment of each subband, `None` if a part of it is missing
+    pub bands: Vec<Option<Vec<u8>>>,
+    /// Indices of the packets not received
+    pub missing_packets: Vec<u16>,
+}
+
+impl Frame {
+    pub fn is_complete(&self) -> bool {
+        self.missing_packets.is_empty()
+    }
+
+    /// Indices of the subbands with missing parts.
+    pub fn missing_bands(&self) -> impl Iterator<Item = usize> + '_ {
+        self.bands
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.is_none())
+            .map(|(i, _)| i)
+    }
+}
+
+/// Reassembles the frames of the packets written by [`Packetizer`](super::Packetizer), in any order.
+///
+/// Packets of several streams and frames can be interleaved, duplicated packets are ignored.
+/// The subbands of a frame are allocated when their first piece arrives, up to `max_frame_len` bytes per frame.
+/// At most `max_frames` frames are reassembled at once: the first packet of another frame
+/// drops the frame whose first packet is the oldest, as frames never taken would otherwise stay forever.
+#[derive(Debug, Clone)]
+pub struct Depacketizer {
+    frames: HashMap<(u16, u32), Assembly>,
+    sequence: u64,
+    pub max_frame_len: usize,
+    pub max_frames: usize,
+}
+
+impl Default for Depacketizer {
+    fn default() -> Self {
+        Self {
+            frames: HashMap::new(),
+            sequence: 0,
+            max_frame_len: 1 << 26,
+            max_frames: 16,
+        }
+    }
+}
+
+impl Depacketizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_frame_len(self, max_frame_len: usize) -> Self {
+        Self {
+            max_frame_len,
+            ..self
+        }
+    }
+
+    pub fn with_max_frames(self, max_frames: usize) -> Self {
+        assert!(max_frames > 0, "At least one frame must be reassembled");
+        Self { max_frames, ..self }
+    }
+
+    /// Adds a packet, returning its header.
+    ///
+    /// A malformed packet, one inconsistent with the previous packets of its frame,
+    /// or one making its frame longer than `max_frame_len`, is rejected as a whole.
+    pub fn push(&mut self, packet: &[u8]) -> io::Result<PacketHeader> {
+        let header = PacketHeader::read(packet)?;
+        let pieces = Piece::parse(&packet[PacketHeader::SIZE..])?;
+        let key = (header.stream_id, header.frame);
+        let assembly = self.frames.get(&key);
+        if assembly
+            .is_some_and(|a| a.count != header.count || a.bands.len() != header.bands as usize)
+        {
+            return Err(invalid("Packet inconsistent with its frame"));
+        }
+        // Length of each subband, from the frame or else from the first piece of the packet
+        let mut lens = HashMap::new();
+        let mut len = assembly.map_or(0, |a| a.len);
+        for (piece, _) in &pieces {
+            if !(header.first_band..=header.last_band).contains(&piece.band) {
+                return Err(invalid("Piece inconsistent with its frame"));
+            }
+            let band_len = *lens.entry(piece.band).or_insert_with(|| {
+                let band = assembly.and_then(|a| a.bands[piece.band as usize].as_ref());
+                band.map_or_else(
+                    || {
+                        len = len.saturating_add(piece.band_len as usize);
+                        piece.band_len as usize
+                    },
+                    |b| b.data.len(),
+                )
+            });
+            if band_len != piece.band_len as usize {
+                return Err(invalid("Piece inconsistent with its frame"));
+            }
+        }
+        if len > self.max_frame_len {
+            return Err(invalid("Frame longer than the maximal length"));
+        }
+
+        if !self.frames.contains_key(&key) {
+            if self.frames.len() >= self.max_frames {
+                self.evict();
+            }
+            self.frames.insert(
+                key,
+                Assembly {
+                    count: header.count,
+                    packets: vec![false; header.count as usize],
+                    bands: vec![None; header.bands as usize],
+                    len: 0,
+                    sequence: self.sequence,
+                },
+            );
+            self.sequence += 1;
+        }
+        let assembly = self.frames.get_mut(&key).unwrap();
+        if !std::mem::replace(&mut assembly.packets[header.index as usize], true) {
+            assembly.len = len;
+            for (piece, data) in pieces {
+                assembly.bands[piece.band as usize]
+                    .get_or_insert_with(|| Band::new(piece.band_len as usize))
+                    .insert(piece.offset as usize, data);
+            }
+        }
+        Ok(header)
+    }
+
+    /// Drops the frame whose first packet is the oldest.
+    fn evict(&mut self) {
+        if let Some(&key) = self
+            .frames
+            .iter()
+            .min_by_key(|(_, a)| a.sequence)
+            .map(|(key, _)| key)
+        {
+            self.frames.remove(&key);
+        }
+    }
+
+    /// Tells whether all the packets of a frame were received.
+    pub fn is_complete(&self, stream_id: u16, frame: u32) -> bool {
+        self.frames
+            .get(&(stream_id, frame))
+            .is_some_and(|a| a.packets.iter().all(|&p| p))
+    }
+
+    /// Removes a frame, returning what was received of it, or `None` if none of its packets was.
+    pub fn take(&mut self, stream_id: u16, frame: u32) -> Option<Frame> {
+        let assembly = self.frames.remove(&(stream_id, frame))?;
+        Some(Frame {
+            stream_id,
+            frame,
+            bands: assembly
+                .bands
+                .into_iter()
+                .map(|b| b.filter(Band::is_complete).map(|b| b.data))
+                .collect(),
+            missing_packets: (0..assembly.count)
+                .filter(|&i| !assembly.packets[i as usize])
+                .collect(),
+        })
+    }
+}
@@ -0,0 +1,222 @@
+//! Transport of the encoded frames over datagram networks.
+//!
+//! Frames are split into packets fitting the MTU, each one describing the part of the frame it carries,
+//! so that the receiver reassembles them in any order and knows which subbands are missing.
+//! Frames coded one segment per subband are then decoded whatever the missing ones, concealing them.
+
+pub use conceal::{encode_frame, Concealment, LowBand, Report, ResilientDecoder};
+pub use depacketizer::{Depacketizer, Frame};
+pub use packet::{PacketHeader, Packetizer, Piece, VERSION};
+
+mod conceal;
+mod depacketizer;
+mod packet;
+
+#[cfg(test)]
+mod test {
+    use std::io::ErrorKind;
+
+    use super::{Depacketizer, PacketHeader, Packetizer};
+
+    fn bands() -> Vec<Vec<u8>> {
+        [3, 0, 500, 40, 41, 1, 2000]
+            .iter()
+            .enumerate()
+            .map(|(b, &len)| (0..len).map(|i| (i * 7 + b * 13) as u8).collect())
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let bands = bands();
+        let packets = Packetizer::new(7, 200)
+            .unwrap()
+            .packetize(42, &bands)
+            .unwrap();
+        assert!(packets.iter().all(|p| p.len() <= 200));
+        // Pieces of a subband only start a new packet when the previous one is full
+        let total = bands.iter().map(Vec::len).sum::<usize>();
+        assert!(packets.len() <= total.div_ceil(200 - 17 - 12) + 1);
+
+        let mut depacketizer = Depacketizer::new();
+        // Out of order, with duplicates
+        for packet in packets.iter().rev().chain(&packets[..3]) {
+            let header = depacketizer.push(packet).unwrap();
+            assert_eq!((header.stream_id, header.frame), (7, 42));
+        }
+        assert!(depacketizer.is_complete(7, 42));
+        let frame = depacketizer.take(7, 42).unwrap();
+        assert!(frame.is_complete());
+        assert_eq!(frame.bands, bands.into_iter().map(Some).collect::<Vec<_>>());
+        assert!(depacketizer.take(7, 42).is_none());
+    }
+
+    #[test]
+    fn missing_packets() {
+        let bands = bands();
+        let packets = Packetizer::new(1, 128)
+            .unwrap()
+            .packetize(0, &bands)
+            .unwrap();
+        let lost = PacketHeader::read(&packets[1]).unwrap();
+
+        let mut depacketizer = Depacketizer::new();
+        for (i, packet) in packets.iter().enumerate() {
+            if i != 1 {
+                depacketizer.push(packet).unwrap();
+            }
+        }
+        assert!(!depacketizer.is_complete(1, 0));
+        let frame = depacketizer.take(1, 0).unwrap();
+        assert_eq!(frame.missing_packets, [1]);
+        let missing = frame.missing_bands().collect::<Vec<_>>();
+        assert_eq!(
+            missing,
+            (lost.first_band as usize..=lost.last_band as usize).collect::<Vec<_>>()
+        );
+        for (b, band) in frame.bands.iter().enumerate() {
+            if !missing.contains(&b) {
+                assert_eq!(band.as_ref(), Some(&bands[b]));
+            }
+        }
+    }
+
+    #[test]
+    fn interleaved_streams() {
+        let bands = bands();
+        let a = Packetizer::new(1, 300)
+            .unwrap()
+            .packetize(5, &bands)
+            .unwrap();
+        let b = Packetizer::new(2, 1500)
+            .unwrap()
+            .packetize(5, &bands[..3])
+            .unwrap();
+        let c = Packetizer::new(1, 300)
+            .unwrap()
+            .packetize(6, &bands[4..])
+            .unwrap();
+
+        let mut depacketizer = Depacketizer::new();
+        for packet in a.iter().chain(&b).chain(&c) {
+            depacketizer.push(packet).unwrap();
+        }
+        assert_eq!(depacketizer.take(2, 5).unwrap().bands.len(), 3);
+        assert_eq!(depacketizer.take(1, 6).unwrap().bands.len(), 3);
+        assert!(depacketizer.take(1, 5).unwrap().is_complete());
+    }
+
+    #[test]
+    fn invalid_packets() {
+        let packets = Packetizer::new(1, 100)
+            .unwrap()
+            .packetize(0, &bands())
+            .unwrap();
+        let mut depacketizer = Depacketizer::new();
+        let kind = |d: &mut Depacketizer, p: &[u8]| d.push(p).unwrap_err().kind();
+
+        assert_eq!(
+            kind(&mut depacketizer, &packets[0][..10]),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(
+            kind(&mut depacketizer, &packets[0][..30]),
+            ErrorKind::InvalidData
+        );
+        let mut corrupted = packets[0].clone();
+        corrupted[0] = 2;
+        assert_eq!(kind(&mut depacketizer, &corrupted), ErrorKind::InvalidData);
+
+        // Packet count differing from the first packet of the frame
+        depacketizer.push(&packets[0]).unwrap();
+        let mut corrupted = packets[1].clone();
+        corrupted[10] += 1;
+        assert_eq!(kind(&mut depacketizer, &corrupted), ErrorKind::InvalidData);
+
+        let err = Packetizer::new(1, 100)
+            .unwrap()
+            .packetize(0, &[] as &[Vec<u8>])
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = Packetizer::new(1, PacketHeader::SIZE + 8).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = Packetizer {
+            stream_id: 1,
+            mtu: 4,
+        }
+        .packetize(0, &bands())
+        .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    /// Packet of frame 0 with one subband, carrying `(offset, band_len, data)` pieces.
+    fn packet(pieces: &[(u32, u32, &[u8])]) -> Vec<u8> {
+        let header = PacketHeader {
+            stream_id: 3,
+            frame: 0,
+            index: 0,
+            count: 2,
+            bands: 1,
+            first_band: 0,
+            last_band: 0,
+        };
+        let mut packet = Vec::new();
+        header.write(&mut packet);
+        for &(offset, band_len, data) in pieces {
+            packet.extend_from_slice(&0u16.to_be_bytes());
+            packet.extend_from_slice(&offset.to_be_bytes());
+            packet.extend_from_slice(&band_len.to_be_bytes());
+            packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            packet.extend_from_slice(data);
+        }
+        packet
+    }
+
+    #[test]
+    fn inconsistent_pieces() {
+        let mut depacketizer = Depacketizer::new();
+        // Two pieces of the same subband with different lengths
+        let err = depacketizer
+            .push(&packet(&[(0, 4, b"abcd"), (4, 8, b"efgh")]))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(depacketizer.take(3, 0).is_none());
+
+        // Length of 4 GiB - 1
+        let err = depacketizer
+            .push(&packet(&[(0, u32::MAX, b"abcd")]))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let mut depacketizer = Depacketizer::new().with_max_frame_len(8);
+        depacketizer.push(&packet(&[(0, 8, b"abcd")])).unwrap();
+        let err = depacketizer.push(&packet(&[(0, 9, b"abcd")])).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let mut depacketizer = Depacketizer::new();
+        depacketizer
+            .push(&packet(&[(0, 8, b"abcd"), (4, 8, b"efgh")]))
+            .unwrap();
+        let frame = depacketizer.take(3, 0).unwrap();
+        assert_eq!(frame.bands, [Some(b"abcdefgh".to_vec())]);
+        assert_eq!(frame.missing_packets, [1]);
+    }
+
+    #[test]
+    fn eviction() {
+        let bands = bands();
+        let mut depacketizer = Depacketizer::new().with_max_frames(2);
+        for frame in 0..3 {
+            let packets = Packetizer::new(1, 300)
+                .unwrap()
+                .packetize(frame, &bands)
+                .unwrap();
+            // The first frame is never completed
+            for packet in &packets[(frame == 0) as usize..] {
+                depacketizer.push(packet).unwrap();
+            }
+        }
+        assert!(depacketizer.take(1, 0).is_none());
+        assert!(depacketizer.take(1, 1).unwrap().is_complete());
+        assert!(depacketizer.take(1, 2).unwrap().is_complete());
+    }
+}
@@ -0,0 +1,229 @@
+use std::io;
+
+pub(super) fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Version of the packet format.
+pub const VERSION: u8 = 1;
+
+/// Header of a packet, all the fields being big endian:
+///
+/// | Bytes | Field |
+/// |-------|-------|
+/// | 1     | [`VERSION`] |
+/// | 2     | stream id |
+/// | 4     | frame number |
+/// | 2 + 2 | packet index and packet count of the frame |
+/// | 2     | number of subbands of the frame |
+/// | 2 + 2 | first and last subband of the packet |
+///
+/// The payload follows, as pieces of subbands, each one with a [`Piece`] header.
+///
+/// The subbands are the segments given to the [`Packetizer`], so with one segment per precinct,
+/// they number the precincts. There is no separate precinct field: the receiver maps
+/// the segment numbers back to subbands and precincts from the coding parameters of the stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PacketHeader {
+    pub stream_id: u16,
+    pub frame: u32,
+    pub index: u16,
+    pub count: u16,
+    pub bands: u16,
+    pub first_band: u16,
+    pub last_band: u16,
+}
+
+impl PacketHeader {
+    pub const SIZE: usize = 17;
+
+    pub fn write(&self, out: &mut Vec<u8>) {
+        out.push(VERSION);
+        out.extend_from_slice(&self.stream_id.to_be_bytes());
+        out.extend_from_slice(&self.frame.to_be_bytes());
+        for x in [
+            self.index,
+            self.count,
+            self.bands,
+            self.first_band,
+            self.last_band,
+        ] {
+            out.extend_from_slice(&x.to_be_bytes());
+        }
+    }
+
+    pub fn read(packet: &[u8]) -> io::Result<Self> {
+        if packet.len() < Self::SIZE {
+            return Err(invalid("Packet shorter than its header"));
+        }
+        if packet[0] != VERSION {
+            return Err(invalid(format!("Unsupported packet version {}", packet[0])));
+        }
+        let u16_at = |i: usize| u16::from_be_bytes([packet[i], packet[i + 1]]);
+        let header = Self {
+            stream_id: u16_at(1),
+            frame: u32::from_be_bytes(packet[3..7].try_into().unwrap()),
+            index: u16_at(7),
+            count: u16_at(9),
+            bands: u16_at(11),
+            first_band: u16_at(13),
+            last_band: u16_at(15),
+        };
+        if header.index >= header.count
+            || header.first_band > header.last_band
+            || header.last_band >= header.bands
+        {
+            return Err(invalid("Inconsistent packet header"));
+        }
+        Ok(header)
+    }
+}
+
+/// Part of a subband carried by a packet, its data following its header:
+///
+/// | Bytes | Field |
+/// |-------|-------|
+/// | 2     | subband |
+/// | 4     | offset of the data in the subband |
+/// | 4     | length of the whole subband |
+/// | 2     | length of the data |
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Piece {
+    pub band: u16,
+    pub offset: u32,
+    pub band_len: u32,
+    pub len: u16,
+}
+
+impl Piece {
+    pub const SIZE: usize = 12;
+
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.band.to_be_bytes());
+        out.extend_from_slice(&self.offset.to_be_bytes());
+        out.extend_from_slice(&self.band_len.to_be_bytes());
+        out.extend_from_slice(&self.len.to_be_bytes());
+    }
+
+    /// Splits the payload of a packet into its pieces and their data.
+    pub fn parse(mut payload: &[u8]) -> io::Result<Vec<(Piece, &[u8])>> {
+        let mut pieces = Vec::new();
+        while !payload.is_empty() {
+            if payload.len() < Self::SIZE {
+                return Err(invalid("Truncated piece header"));
+            }
+            let piece = Piece {
+                band: u16::from_be_bytes([payload[0], payload[1]]),
+                offset: u32::from_be_bytes(payload[2..6].try_into().unwrap()),
+                band_len: u32::from_be_bytes(payload[6..10].try_into().unwrap()),
+                len: u16::from_be_bytes([payload[10], payload[11]]),
+            };
+            let end = Self::SIZE + piece.len as usize;
+            if payload.len() < end || piece.offset as u64 + piece.len as u64 > piece.band_len as u64
+            {
+                return Err(invalid("Piece out of its packet or subband"));
+            }
+            pieces.push((piece, &payload[Self::SIZE..end]));
+            payload = &payload[end..];
+        }
+        Ok(pieces)
+    }
+}
+
+/// Splits encoded frames into packets of at most `mtu` bytes.
+///
+/// A frame is given as one segment per subband, or per precinct, each one coded independently
+/// like with [`Huffman::encode_band`](crate::entropy::huffman::Huffman::encode_band).
+/// The segments fill the packets in order, and a segment larger than the room left in a packet continues in the next ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packetizer {
+    pub stream_id: u16,
+    pub mtu: usize,
+}
+
+impl Packetizer {
+    /// Fails with [`io::ErrorKind::InvalidInput`] if `mtu` leaves no room for data after the headers.
+    pub fn new(stream_id: u16, mtu: usize) -> io::Result<Self> {
+        let packetizer = Self { stream_id, mtu };
+        packetizer.check_mtu()?;
+        Ok(packetizer)
+    }
+
+    fn check_mtu(&self) -> io::Result<()> {
+        if self.mtu <= PacketHeader::SIZE + Piece::SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "MTU too small for the packet headers",
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn packetize(&self, frame: u32, bands: &[impl AsRef<[u8]>]) -> io::Result<Vec<Vec<u8>>> {
+        let input_error = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, msg.to_string());
+        self.check_mtu()?;
+        if bands.is_empty() {
+            return Err(input_error("Frame without subbands"));
+        }
+        let band_count =
+            u16::try_from(bands.len()).map_err(|_| input_error("Too many subbands"))?;
+        let capacity = self.mtu - PacketHeader::SIZE;
+        let max_piece = (capacity - Piece::SIZE).min(u16::MAX as usize);
+
+        // Pieces of each packet, as (piece, data)
+        let mut packets = vec![Vec::new()];
+        let mut room = capacity;
+        for (band, data) in bands.iter().enumerate() {
+            let data = data.as_ref();
+            let band_len =
+                u32::try_from(data.len()).map_err(|_| input_error("Subband larger than 4 GiB"))?;
+            let mut offset = 0;
+            loop {
+                if room <= Piece::SIZE {
+                    packets.push(Vec::new());
+                    room = capacity;
+                }
+                let len = (data.len() - offset).min(room - Piece::SIZE).min(max_piece);
+                let piece = Piece {
+                    band: band as u16,
+                    offset: offset as u32,
+                    band_len,
+                    len: len as u16,
+                };
+                packets
+                    .last_mut()
+                    .unwrap()
+                    .push((piece, &data[offset..offset + len]));
+                room -= Piece::SIZE + len;
+                offset += len;
+                if offset == data.len() {
+                    break;
+                }
+            }
+        }
+
+        let count = u16::try_from(packets.len()).map_err(|_| input_error("Too many packets"))?;
+        Ok(packets
+            .into_iter()
+            .enumerate()
+            .map(|(index, pieces)| {
+                let header = PacketHeader {
+                    stream_id: self.stream_id,
+                    frame,
+                    index: index as u16,
+                    count,
+                    bands: band_count,
+                    first_band: pieces.first().map_or(0, |(p, _)| p.band),
+                    last_band: pieces.last().map_or(0, |(p, _)| p.band),
+                };
+                let mut packet = Vec::with_capacity(self.mtu);
+                header.write(&mut packet);
+                for (piece, data) in pieces {
+                    piece.write(&mut packet);
+                    packet.extend_from_slice(data);
+                }
+                packet
+            })
+            .collect())
+    }
+}
@@ -1,5 +1,30 @@
+use std::marker::PhantomData;
+
 use crate::memory::Image;
 
+pub use overflow::{Overflow, OverflowError};
+pub use sample::Sample;
+
+mod overflow;
+mod sample;
+
+/// Invokes `$mac!(T, args...)` for every [`Sample`] type.
+///
+/// Generic `Dwt1` impls would overlap with the blanket impl over `Dwt0` kernels,
+/// so kernels write their algorithm once for any `T: Sample` and use this to implement the traits.
+macro_rules! for_each_sample {
+    ($mac:ident $(, $args:tt)*) => {
+        $mac!(i8 $(, $args)*);
+        $mac!(i16 $(, $args)*);
+        $mac!(i32 $(, $args)*);
+        $mac!(i64 $(, $args)*);
+        $mac!(u8 $(, $args)*);
+        $mac!(u16 $(, $args)*);
+        $mac!(u32 $(, $args)*);
+    };
+}
+pub(crate) use for_each_sample;
+
 pub trait Convert<T> {
     fn convert(&self) -> T;
 }
@@ -163,9 +188,92 @@ where
     }
 }
 
+/// Image whose samples use only `bits` bits of their type, converted to samples of type `D`.
+///
+/// Converting it level shifts by `2^(bits - 1)` instead of half the range of the type,
+/// so that unsigned samples become signed samples centred on zero keeping full precision,
+/// and signed samples go back to unsigned samples.
+/// Samples are clamped to the `bits` bits range, so that every converted sample fits in `D`.
+#[derive(Debug, Clone, Copy)]
+pub struct BitDepth<'a, S, D> {
+    image: &'a Image<S>,
+    bits: u32,
+    target: PhantomData<D>,
+}
+
+impl<'a, S: Sample, D: Sample> BitDepth<'a, S, D> {
+    /// `None` if `bits` is 0, more than the width of unsigned samples or 63,
+    /// or if the converted range does not fit in `D`.
+    pub fn new(image: &'a Image<S>, bits: u32) -> Option<Self> {
+        let signed = S::MIN.to_i64() < 0;
+        let max_bits = if signed {
+            63
+        } else {
+            i64::BITS - S::MAX.to_i64().leading_zeros()
+        };
+        if !(1..=max_bits).contains(&bits) {
+            return None;
+        }
+        let half = 1 << (bits - 1);
+        // Unsigned samples are shifted, signed samples are unshifted
+        let (min, max) = if signed {
+            (0, half - 1 + half)
+        } else {
+            (-half, half - 1)
+        };
+        let fits = D::MIN.to_i64() <= min && max <= D::MAX.to_i64();
+        fits.then_some(Self {
+            image,
+            bits,
+            target: PhantomData,
+        })
+    }
+
+    fn half(&self) -> i64 {
+        1 << (self.bits - 1)
+    }
+}
+
+macro_rules! bit_depth_impl {
+    (shift $($src:ty),+) => {
+        $(
+            impl<D: Sample> Convert<Image<D>> for BitDepth<'_, $src, D> {
+                fn convert(&self) -> Image<D> {
+                    let half = self.half();
+                    Image::with_fn(self.image.width(), self.image.height(), |x, y| {
+                        let sample = (*self.image.get(x, y) as i64).min(half - 1 + half);
+                        // Fits, see `BitDepth::new`
+                        D::saturating_from_i64(sample - half)
+                    })
+                }
+            }
+        )+
+    };
+    (unshift $($src:ty),+) => {
+        $(
+            impl<D: Sample> Convert<Image<D>> for BitDepth<'_, $src, D> {
+                fn convert(&self) -> Image<D> {
+                    let half = self.half();
+                    Image::with_fn(self.image.width(), self.image.height(), |x, y| {
+                        let sample = (*self.image.get(x, y) as i64).saturating_add(half);
+                        // Fits, see `BitDepth::new`
+                        D::saturating_from_i64(sample.clamp(0, half - 1 + half))
+                    })
+                }
+            }
+        )+
+    };
+}
+
+bit_depth_impl!(shift u8, u16, u32);
+bit_depth_impl!(unshift i8, i16, i32, i64);
+
 #[cfg(test)]
 mod tests {
-    use crate::numeric::Convert;
+    use crate::{
+        memory::Image,
+        numeric::{BitDepth, Convert},
+    };
 
     #[test]
     fn foo() {
@@ -176,4 +284,38 @@ mod tests {
         assert_eq!(Convert::<u8>::convert(&0i8), 128u8);
         assert_eq!(Convert::<u8>::convert(&0i16), 128u8);
     }
+
+    #[test]
+    fn bit_depth() {
+        let image = Image::with_fn(9, 4, |x, y| (x * 113 + y * 1000) as u16);
+        let shifted: Image<i32> = BitDepth::new(&image, 12).unwrap().convert();
+        assert_eq!(*shifted.get(0, 0), -2048);
+        assert_eq!(*shifted.get(8, 3), 904 + 3000 - 2048);
+        let unshifted: Image<u16> = BitDepth::new(&shifted, 12).unwrap().convert();
+        assert_eq!(unshifted, image);
+
+        // Out of range samples are clamped
+        let shifted = Image::with_fn(2, 1, |x, _| [-5000i32, 5000][x]);
+        let unshifted: Image<u16> = BitDepth::new(&shifted, 10).unwrap().convert();
+        assert_eq!((*unshifted.get(0, 0), *unshifted.get(1, 0)), (0, 1023));
+        let image = Image::with_fn(2, 1, |x, _| [0u16, 5000][x]);
+        let shifted: Image<i16> = BitDepth::new(&image, 12).unwrap().convert();
+        assert_eq!((*shifted.get(0, 0), *shifted.get(1, 0)), (-2048, 2047));
+    }
+
+    #[test]
+    fn invalid_bit_depth() {
+        let image = Image::<u8>::new(1, 1);
+        assert!(BitDepth::<_, i16>::new(&image, 0).is_none());
+        assert!(BitDepth::<_, i16>::new(&image, 9).is_none());
+
+        // Target samples too narrow for the bit depth
+        let image = Image::<u16>::new(1, 1);
+        assert!(BitDepth::<_, i16>::new(&image, 16).is_some());
+        assert!(BitDepth::<_, i8>::new(&image, 9).is_none());
+        assert!(BitDepth::<_, u32>::new(&image, 12).is_none());
+        let image = Image::<i32>::new(1, 1);
+        assert!(BitDepth::<_, u8>::new(&image, 8).is_some());
+        assert!(BitDepth::<_, u8>::new(&image, 9).is_none());
+    }
 }
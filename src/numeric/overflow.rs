@@ -0,0 +1,82 @@
+use super::Sample;
+
+/// What a transform does when a result does not fit in the sample type.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Overflow {
+    /// Modular arithmetic: results wrap around.
+    ///
+    /// The inverse wraps the same way, so reversible kernels stay perfectly reversible,
+    /// but wrapped coefficients lose their meaning for quantization.
+    #[default]
+    Wrapping,
+    /// Results are clamped to the sample range.
+    ///
+    /// Coefficients keep their sign and order of magnitude, but clamped samples are not reconstructed exactly.
+    Saturating,
+    /// Results are computed on a wider type and checked when narrowed back.
+    ///
+    /// A result out of the sample range makes the `try_` transforms fail with an [`OverflowError`].
+    /// The transforms that cannot fail wrap around instead, see [`Overflow::infallible`].
+    Widening,
+}
+
+impl Overflow {
+    /// The policy used where errors cannot be reported: [`Overflow::Widening`] becomes [`Overflow::Wrapping`].
+    ///
+    /// Both give the same results when nothing overflows, and wrapping keeps the transform reversible.
+    pub fn infallible(self) -> Self {
+        match self {
+            Overflow::Widening => Overflow::Wrapping,
+            overflow => overflow,
+        }
+    }
+
+    /// `x` narrowed to the sample type under this policy, `None` on a [`Overflow::Widening`] overflow.
+    pub fn narrow<T: Sample>(self, x: i64) -> Option<T> {
+        match self {
+            Overflow::Wrapping => Some(T::wrapping_from_i64(x)),
+            Overflow::Saturating => Some(T::saturating_from_i64(x)),
+            Overflow::Widening => T::checked_from_i64(x),
+        }
+    }
+
+    /// `x + d` under this policy, `None` on a [`Overflow::Widening`] overflow.
+    ///
+    /// `d` is wider than any sample so that filters on `i64` samples do not overflow.
+    pub fn add<T: Sample>(self, x: T, d: i128) -> Option<T> {
+        let sum = x.to_i64() as i128 + d;
+        match self {
+            Overflow::Wrapping => self.narrow(sum as i64),
+            Overflow::Saturating => {
+                self.narrow(sum.clamp(i64::MIN as i128, i64::MAX as i128) as i64)
+            }
+            Overflow::Widening => i64::try_from(sum).ok().and_then(|sum| self.narrow(sum)),
+        }
+    }
+}
+
+/// A transform result did not fit in the sample type with [`Overflow::Widening`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverflowError;
+
+impl std::fmt::Display for OverflowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Arithmetic overflow in wavelet transform")
+    }
+}
+
+impl std::error::Error for OverflowError {}
+
+#[cfg(test)]
+mod test {
+    use super::Overflow;
+
+    #[test]
+    fn add() {
+        assert_eq!(Overflow::Wrapping.add(120i8, 10), Some(-126));
+        assert_eq!(Overflow::Saturating.add(120i8, 10), Some(127));
+        assert_eq!(Overflow::Widening.add(120i8, 10), None);
+        assert_eq!(Overflow::Widening.add(120i8, -10), Some(110));
+        assert_eq!(Overflow::Saturating.add(3u16, -10), Some(0));
+    }
+}
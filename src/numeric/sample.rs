@@ -0,0 +1,118 @@
+use std::{
+    fmt::Debug,
+    ops::{Add, Div, Mul, Sub},
+};
+
+/// Integer sample type the wavelet kernels are written for.
+///
+/// Lifting filters accumulate in `i128`, whatever the sample type.
+pub trait Sample:
+    Copy + Debug + Default + Ord + Add<Output = Self> + Sub<Output = Self> + Send + Sync + 'static
+{
+    /// Intermediate type holding any sum or difference of two samples without overflow
+    type Wide: Copy
+        + Debug
+        + Ord
+        + From<Self>
+        + From<i8>
+        + Add<Output = Self::Wide>
+        + Sub<Output = Self::Wide>
+        + Mul<Output = Self::Wide>
+        + Div<Output = Self::Wide>;
+
+    const MIN: Self;
+    const MAX: Self;
+
+    fn widen(self) -> Self::Wide {
+        self.into()
+    }
+    fn wrapping_narrow(wide: Self::Wide) -> Self;
+    fn saturating_narrow(wide: Self::Wide) -> Self;
+    fn checked_narrow(wide: Self::Wide) -> Option<Self>;
+
+    fn wrapping_add(self, other: Self) -> Self;
+    fn wrapping_sub(self, other: Self) -> Self;
+    fn saturating_add(self, other: Self) -> Self;
+    fn saturating_sub(self, other: Self) -> Self;
+    fn checked_add(self, other: Self) -> Option<Self>;
+    fn checked_sub(self, other: Self) -> Option<Self>;
+
+    fn to_i64(self) -> i64;
+    fn wrapping_from_i64(x: i64) -> Self;
+    fn saturating_from_i64(x: i64) -> Self;
+    fn checked_from_i64(x: i64) -> Option<Self>;
+}
+
+macro_rules! sample_impl {
+    ($($t:ty => $wide:ty),+) => {
+        $(
+            impl Sample for $t {
+                type Wide = $wide;
+
+                const MIN: Self = <$t>::MIN;
+                const MAX: Self = <$t>::MAX;
+
+                fn wrapping_narrow(wide: $wide) -> Self {
+                    wide as $t
+                }
+                fn saturating_narrow(wide: $wide) -> Self {
+                    wide.clamp(<$t>::MIN as $wide, <$t>::MAX as $wide) as $t
+                }
+                fn checked_narrow(wide: $wide) -> Option<Self> {
+                    <$t>::try_from(wide).ok()
+                }
+
+                fn wrapping_add(self, other: Self) -> Self {
+                    <$t>::wrapping_add(self, other)
+                }
+                fn wrapping_sub(self, other: Self) -> Self {
+                    <$t>::wrapping_sub(self, other)
+                }
+                fn saturating_add(self, other: Self) -> Self {
+                    <$t>::saturating_add(self, other)
+                }
+                fn saturating_sub(self, other: Self) -> Self {
+                    <$t>::saturating_sub(self, other)
+                }
+                fn checked_add(self, other: Self) -> Option<Self> {
+                    <$t>::checked_add(self, other)
+                }
+                fn checked_sub(self, other: Self) -> Option<Self> {
+                    <$t>::checked_sub(self, other)
+                }
+
+                fn to_i64(self) -> i64 {
+                    self as i64
+                }
+                fn wrapping_from_i64(x: i64) -> Self {
+                    x as $t
+                }
+                fn saturating_from_i64(x: i64) -> Self {
+                    x.clamp(<$t>::MIN as i64, <$t>::MAX as i64) as $t
+                }
+                fn checked_from_i64(x: i64) -> Option<Self> {
+                    <$t>::try_from(x).ok()
+                }
+            }
+        )+
+    };
+}
+
+sample_impl!(i8 => i16, i16 => i32, i32 => i64, i64 => i128);
+sample_impl!(u8 => i16, u16 => i32, u32 => i64);
+
+#[cfg(test)]
+mod test {
+    use super::Sample;
+
+    #[test]
+    fn narrowing() {
+        assert_eq!(i8::wrapping_narrow(200), -56);
+        assert_eq!(i8::saturating_narrow(200), 127);
+        assert_eq!(i8::checked_narrow(200), None);
+        assert_eq!(u16::saturating_narrow(-3), 0);
+        assert_eq!(i16::saturating_from_i64(1 << 40), i16::MAX);
+        assert_eq!(i32::wrapping_from_i64(1 << 32), 0);
+        assert_eq!(Sample::widen(-128i8) - Sample::widen(127i8), -255);
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+
+use crate::{
+    dwt::{Orientation, Subband, SubbandLayout},
+    memory::ImageViewMut,
+    numeric::Sample,
+};
+
+pub use scalar::{DeadZone, PiecewiseLinear};
+pub use table::QuantTable;
+
+mod scalar;
+mod table;
+
+/// Maps wavelet coefficients to integer indices, and indices back to coefficients.
+pub trait Quantizer {
+    /// Index of the interval containing `x`.
+    fn quantize(&self, x: i64) -> i64;
+    /// Representative value of the interval of index `q`.
+    fn dequantize(&self, q: i64) -> i64;
+
+    /// Quantizes every sample in place, saturating indices to the sample range.
+    fn quantize_image<T: Sample>(&self, mut img: ImageViewMut<'_, T>)
+    where
+        Self: Sized,
+    {
+        img.for_each_mut(|_, _, x| *x = T::saturating_from_i64(self.quantize(x.to_i64())));
+    }
+
+    /// Dequantizes every sample in place, saturating values to the sample range.
+    fn dequantize_image<T: Sample>(&self, mut img: ImageViewMut<'_, T>)
+    where
+        Self: Sized,
+    {
+        img.for_each_mut(|_, _, q| *q = T::saturating_from_i64(self.dequantize(q.to_i64())));
+    }
+}
+
+/// Quantizer of each subband of a Mallat decomposition.
+///
+/// A band uses its own quantizer if one is set, else the one of its level, else the default one.
+#[derive(Debug, Clone)]
+pub struct SubbandQuantizer<Q> {
+    default: Q,
+    levels: HashMap<usize, Q>,
+    bands: HashMap<(usize, Orientation), Q>,
+}
+
+impl<Q: Quantizer> SubbandQuantizer<Q> {
+    pub fn new(default: Q) -> Self {
+        Self {
+            default,
+            levels: HashMap::new(),
+            bands: HashMap::new(),
+        }
+    }
+
+    /// Sets the quantizer of every band of `level`.
+    pub fn with_level(mut self, level: usize, quantizer: Q) -> Self {
+        self.levels.insert(level, quantizer);
+        self
+    }
+
+    /// Sets the quantizer of a single band, which takes precedence over its level.
+    pub fn with_band(mut self, level: usize, orientation: Orientation, quantizer: Q) -> Self {
+        self.bands.insert((level, orientation), quantizer);
+        self
+    }
+
+    pub fn get(&self, band: &Subband) -> &Q {
+        self.bands
+            .get(&(band.level, band.orientation))
+            .or_else(|| self.levels.get(&band.level))
+            .unwrap_or(&self.default)
+    }
+
+    /// Quantizes a decomposed image, band by band.
+    pub fn quantize<T: Sample>(&self, layout: &SubbandLayout, mut img: ImageViewMut<'_, T>) {
+        for band in layout.bands() {
+            self.get(&band)
+                .quantize_image(band.view_mut(img.view_mut()));
+        }
+    }
+
+    /// Dequantizes an image quantized by [`SubbandQuantizer::quantize`] with the same layout.
+    pub fn dequantize<T: Sample>(&self, layout: &SubbandLayout, mut img: ImageViewMut<'_, T>) {
+        for band in layout.bands() {
+            self.get(&band)
+                .dequantize_image(band.view_mut(img.view_mut()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        dwt::{daub::Daub53, Dwt2, Orientation, SubbandLayout},
+        memory::Image,
+    };
+
+    use super::{DeadZone, SubbandQuantizer};
+
+    #[test]
+    fn step_per_subband() {
+        let quantizer = SubbandQuantizer::new(DeadZone::new(8))
+            .with_level(2, DeadZone::new(4))
+            .with_band(2, Orientation::LL, DeadZone::new(1))
+            .with_band(1, Orientation::HH, DeadZone::new(16));
+        let layout = SubbandLayout::new(16, 16, 2);
+        let steps = layout
+            .bands()
+            .map(|band| (band.level, band.orientation, quantizer.get(&band).step()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            steps,
+            [
+                (2, Orientation::LL, 1),
+                (2, Orientation::LH, 4),
+                (2, Orientation::HL, 4),
+                (2, Orientation::HH, 4),
+                (1, Orientation::LH, 8),
+                (1, Orientation::HL, 8),
+                (1, Orientation::HH, 16),
+            ]
+        );
+    }
+
+    #[test]
+    fn lossless_low_band() {
+        let input = Image::with_fn(24, 20, |x, y| ((x * 7 + y * 11) % 200) as i16 - 100);
+        let mut image = input.clone();
+        let mut tmp = input.clone();
+        let layout = Daub53.decompose(image.view_mut(), tmp.view_mut(), 2);
+        let decomposed = image.clone();
+
+        let quantizer = SubbandQuantizer::new(DeadZone::new(4).with_offset(2)).with_band(
+            2,
+            Orientation::LL,
+            DeadZone::new(1),
+        );
+        quantizer.quantize(&layout, image.view_mut());
+        quantizer.dequantize(&layout, image.view_mut());
+
+        for band in layout.bands() {
+            let max_error = band
+                .view(image.view())
+                .rows()
+                .zip(band.view(decomposed.view()).rows())
+                .flat_map(|(a, b)| a.iter().zip(b).map(|(a, b)| a.abs_diff(*b)))
+                .max()
+                .unwrap();
+            // The dead zone is wider than the other intervals
+            let expected = if band.orientation == Orientation::LL {
+                0
+            } else {
+                3
+            };
+            assert!(max_error <= expected, "{band:?}: {max_error}");
+        }
+    }
+}
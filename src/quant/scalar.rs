@@ -0,0 +1,186 @@
+use super::Quantizer;
+
+/// Uniform scalar quantizer with a dead zone around 0.
+///
+/// Magnitudes up to `threshold` map to 0, the others to `(|x| - threshold) / step`, truncated.
+/// Non-zero indices are reconstructed at `threshold + |q| * step + offset`, with the sign of `q`:
+/// an offset of 0 reconstructs at the lower bound of the interval, `step / 2` at its middle.
+/// Reconstructions beyond the `i64` range saturate to `±i64::MAX`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DeadZone {
+    step: i64,
+    threshold: i64,
+    offset: i64,
+}
+
+impl DeadZone {
+    pub const fn new(step: i64) -> Self {
+        assert!(step > 0, "Quantization step must be positive");
+        Self {
+            step,
+            threshold: 0,
+            offset: 0,
+        }
+    }
+
+    /// Widens the dead zone by `threshold` on each side.
+    pub const fn with_threshold(self, threshold: i64) -> Self {
+        assert!(threshold >= 0, "Dead zone threshold must not be negative");
+        Self { threshold, ..self }
+    }
+
+    pub const fn with_offset(self, offset: i64) -> Self {
+        assert!(
+            0 <= offset && offset < self.step,
+            "Reconstruction offset out of the interval"
+        );
+        Self { offset, ..self }
+    }
+
+    pub fn step(&self) -> i64 {
+        self.step
+    }
+    pub fn threshold(&self) -> i64 {
+        self.threshold
+    }
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+}
+
+impl Quantizer for DeadZone {
+    fn quantize(&self, x: i64) -> i64 {
+        let q = x.unsigned_abs().saturating_sub(self.threshold as u64) / self.step as u64;
+        // Only `i64::MIN` with a unit step gives `2^63`
+        q.min(i64::MAX as u64) as i64 * x.signum()
+    }
+
+    fn dequantize(&self, q: i64) -> i64 {
+        if q == 0 {
+            return 0;
+        }
+        let x = self.threshold as i128 + q.unsigned_abs() as i128 * self.step as i128;
+        let x = (x + self.offset as i128).min(i64::MAX as i128) as i64;
+        x * q.signum()
+    }
+}
+
+/// Piecewise linear quantizer: identity up to `threshold`, then divided by `scale`.
+///
+/// Small coefficients are kept exactly, large ones lose precision, like a coarse logarithm.
+/// Beyond the threshold, indices are reconstructed at `offset` from the lower bound of their interval.
+/// Reconstructions beyond the `i64` range saturate to `±i64::MAX`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PiecewiseLinear {
+    threshold: i64,
+    scale: i64,
+    offset: i64,
+}
+
+impl PiecewiseLinear {
+    pub const fn new(threshold: i64, scale: i64) -> Self {
+        assert!(threshold >= 0, "Threshold must not be negative");
+        assert!(scale > 0, "Scale must be positive");
+        Self {
+            threshold,
+            scale,
+            offset: 0,
+        }
+    }
+
+    pub const fn with_offset(self, offset: i64) -> Self {
+        assert!(
+            0 <= offset && offset < self.scale,
+            "Reconstruction offset out of the interval"
+        );
+        Self { offset, ..self }
+    }
+
+    pub fn threshold(&self) -> i64 {
+        self.threshold
+    }
+    pub fn scale(&self) -> i64 {
+        self.scale
+    }
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+}
+
+impl Quantizer for PiecewiseLinear {
+    fn quantize(&self, x: i64) -> i64 {
+        let t = self.threshold;
+        if x < -t {
+            ((x + t) / self.scale) - t
+        } else if x > t {
+            ((x - t) / self.scale) + t
+        } else {
+            x
+        }
+    }
+
+    fn dequantize(&self, q: i64) -> i64 {
+        let t = self.threshold as i128;
+        let m = q.unsigned_abs() as i128;
+        if m <= t {
+            return q;
+        }
+        let x = (m - t) * self.scale as i128 + t + self.offset as i128;
+        x.min(i64::MAX as i128) as i64 * q.signum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::quant::Quantizer;
+
+    use super::{DeadZone, PiecewiseLinear};
+
+    #[test]
+    fn dead_zone() {
+        let q = DeadZone::new(4);
+        let indices = [-9, -8, -4, -3, 0, 3, 4, 8, 9].map(|x| q.quantize(x));
+        assert_eq!(indices, [-2, -2, -1, 0, 0, 0, 1, 2, 2]);
+        assert_eq!(
+            indices.map(|i| q.dequantize(i)),
+            [-8, -8, -4, 0, 0, 0, 4, 8, 8]
+        );
+
+        let q = DeadZone::new(4).with_threshold(10).with_offset(2);
+        assert_eq!(q.quantize(13), 0);
+        assert_eq!(q.quantize(-14), -1);
+        assert_eq!(q.dequantize(-1), -16);
+        assert_eq!(q.dequantize(2), 20);
+
+        assert_eq!(DeadZone::new(1).quantize(i64::MIN), -i64::MAX);
+        assert_eq!(DeadZone::new(2).quantize(i64::MIN), i64::MIN / 2);
+
+        let q = DeadZone::new(u32::MAX as i64).with_threshold(u32::MAX as i64);
+        assert_eq!(q.dequantize(1 << 40), i64::MAX);
+        assert_eq!(q.dequantize(i64::MIN), -i64::MAX);
+        assert_eq!(q.dequantize(-2), -3 * u32::MAX as i64);
+    }
+
+    #[test]
+    fn piecewise_linear() {
+        let q = PiecewiseLinear::new(64, 3);
+        for x in -64..=64 {
+            assert_eq!(q.dequantize(q.quantize(x)), x);
+        }
+        assert_eq!(q.quantize(100), 76);
+        assert_eq!(q.quantize(-100), -76);
+        assert_eq!(q.dequantize(76), 100);
+
+        // The first interval past the threshold is merged with it, and never offset
+        let q = q.with_offset(1);
+        for x in (-300..=300).filter(|x: &i64| x.abs() > 66) {
+            let error = q.dequantize(q.quantize(x)) - x;
+            assert!(error.abs() <= 1, "{x}: {error}");
+        }
+
+        let q = PiecewiseLinear::new(u32::MAX as i64, u32::MAX as i64);
+        assert_eq!(q.dequantize(1 << 40), i64::MAX);
+        assert_eq!(q.dequantize(i64::MIN), -i64::MAX);
+        assert_eq!(q.dequantize(-(u32::MAX as i64) - 1), -2 * u32::MAX as i64);
+    }
+}
@@ -0,0 +1,98 @@
+use super::Quantizer;
+
+/// Quantizer defined by a lookup table of intervals.
+///
+/// Index `k` holds the non-negative coefficients in `bounds[k]..bounds[k + 1]`,
+/// the last index holding everything above its bound, and is reconstructed as `values[k]`.
+/// Negative coefficients mirror the positive ones through `x -> !x = -x - 1`,
+/// so that `-1` maps to `!0 = -1` and intervals keep the same width on both sides.
+/// On the negative side, the last index is reconstructed at `-values[k]` rather than `!values[k]`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct QuantTable {
+    bounds: Vec<i64>,
+    values: Vec<i64>,
+}
+
+impl QuantTable {
+    pub fn new(bounds: Vec<i64>, values: Vec<i64>) -> Self {
+        assert_eq!(
+            bounds.len(),
+            values.len(),
+            "One value is needed per interval"
+        );
+        assert_eq!(
+            bounds.first(),
+            Some(&0),
+            "The first interval must start at 0"
+        );
+        assert!(
+            bounds.windows(2).all(|w| w[0] < w[1]),
+            "Bounds must be strictly increasing"
+        );
+        Self { bounds, values }
+    }
+
+    /// Logarithmic table: exact up to 4, then two intervals per power of two, up to 256.
+    pub fn log() -> Self {
+        Self::new(
+            vec![
+                0, 1, 2, 3, 4, 6, 8, 12, 16, 24, 32, 48, 64, 96, 128, 192, 256,
+            ],
+            vec![
+                0, 1, 2, 3, 4, 6, 9, 13, 19, 27, 39, 55, 79, 111, 159, 223, 256,
+            ],
+        )
+    }
+
+    pub fn bounds(&self) -> &[i64] {
+        &self.bounds
+    }
+    pub fn values(&self) -> &[i64] {
+        &self.values
+    }
+}
+
+impl Quantizer for QuantTable {
+    fn quantize(&self, x: i64) -> i64 {
+        if x < 0 {
+            return !self.quantize(!x);
+        }
+        self.bounds.partition_point(|&bound| bound <= x) as i64 - 1
+    }
+
+    fn dequantize(&self, q: i64) -> i64 {
+        let last = self.values.len() - 1;
+        if q < 0 {
+            if !q as usize >= last {
+                return -self.values[last];
+            }
+            return !self.dequantize(!q);
+        }
+        self.values[(q as usize).min(last)]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::quant::Quantizer;
+
+    use super::QuantTable;
+
+    #[test]
+    fn log_table() {
+        let table = QuantTable::log();
+        let x = [-300, -256, -7, -5, -4, -1, 0, 3, 4, 5, 6, 10, 200, 1000];
+        let q = [-17, -16, -6, -5, -4, -1, 0, 3, 4, 4, 5, 6, 15, 16];
+        assert_eq!(x.map(|x| table.quantize(x)), q);
+        assert_eq!(
+            q.map(|q| table.dequantize(q)),
+            [-256, -224, -7, -5, -4, -1, 0, 3, 4, 4, 6, 9, 223, 256]
+        );
+        assert_eq!(table.dequantize(i64::MIN), -256);
+
+        for x in -256..256 {
+            let y = table.dequantize(table.quantize(x));
+            assert_eq!(table.quantize(y), table.quantize(x), "{x}");
+        }
+    }
+}
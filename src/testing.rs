@@ -0,0 +1,12 @@
+//! Helpers shared by the unit tests.
+
+/// Pseudo-random sequence of `len` values, mapping the 16 high bits of a linear congruential generator with `f`.
+pub fn signal<T>(len: usize, seed: u32, mut f: impl FnMut(u32) -> T) -> Vec<T> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            f(state >> 16)
+        })
+        .collect()
+}